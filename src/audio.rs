@@ -0,0 +1,100 @@
+//! Audio buses.
+//!
+//! Every sound is tagged with the bus it plays on, its loudness is the master
+//! `Volume` times the bus volume times the sound's own `SoundLevel`. Changing any of
+//! the sliders applies to the playing sounds right away. Without an audio device no
+//! sinks are ever created and all of this simply does nothing.
+
+use std::path::Path;
+
+use bevy::{audio::Volume as AudioVolume, prelude::*};
+
+use crate::{
+    menus::ui::SettingButton,
+    settings::{
+        globals::{AmbienceVolume, EffectsVolume, MusicVolume, Volume},
+        settings_io::SettingPlugin,
+    },
+};
+
+const CLICK_SOUND: &str = "bevy_examples/sounds/breakout_collision.ogg";
+
+pub fn audio_plugin(app: &mut App) {
+    app
+        .add_plugins((
+            SettingPlugin::new(Path::new("assets/settings/music_volume.json"), MusicVolume(60)),
+            SettingPlugin::new(Path::new("assets/settings/effects_volume.json"), EffectsVolume(80)),
+            SettingPlugin::new(Path::new("assets/settings/ambience_volume.json"), AmbienceVolume(50)),
+        ))
+        .add_systems(Update, (button_click_sound_system, apply_bus_volume_system).chain());
+}
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioBus {
+    Music,
+    Effects,
+    Ambience,
+}
+
+/// Loudness of a single sound before the bus volumes, 1.0 when missing
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct SoundLevel(pub f32);
+
+/// The bus volumes as plain factors
+#[derive(bevy::ecs::system::SystemParam)]
+pub struct BusVolumes<'w> {
+    master: Res<'w, Volume>,
+    music: Res<'w, MusicVolume>,
+    effects: Res<'w, EffectsVolume>,
+    ambience: Res<'w, AmbienceVolume>,
+}
+
+impl BusVolumes<'_> {
+    pub fn get(&self, bus: AudioBus) -> f32 {
+        let level = match bus {
+            AudioBus::Music => self.music.0,
+            AudioBus::Effects => self.effects.0,
+            AudioBus::Ambience => self.ambience.0,
+        };
+        self.master.0 as f32 / 100.0 * level as f32 / 100.0
+    }
+
+    fn is_changed(&self) -> bool {
+        self.master.is_changed() || self.music.is_changed() || self.effects.is_changed() || self.ambience.is_changed()
+    }
+}
+
+/// Plays a one shot sound effect
+pub fn play_effect(commands: &mut Commands, asset_server: &AssetServer, volumes: &BusVolumes, path: &'static str) {
+    commands.spawn((
+        AudioPlayer::new(asset_server.load(path)),
+        PlaybackSettings::DESPAWN.with_volume(AudioVolume::new(volumes.get(AudioBus::Effects))),
+        AudioBus::Effects,
+    ));
+}
+
+fn button_click_sound_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    volumes: BusVolumes,
+    interaction_q: Query<&Interaction, (Changed<Interaction>, With<SettingButton>)>,
+) {
+    if interaction_q.iter().any(|interaction| *interaction == Interaction::Pressed) {
+        play_effect(&mut commands, &asset_server, &volumes, CLICK_SOUND);
+    }
+}
+
+// Keeps every playing sound at its bus volume, sounds that just started included
+#[allow(clippy::type_complexity)]
+fn apply_bus_volume_system(
+    volumes: BusVolumes,
+    sink_q: Query<(Ref<AudioSink>, &AudioBus, Option<Ref<SoundLevel>>)>,
+) {
+    let all = volumes.is_changed();
+    for (sink, bus, level) in &sink_q {
+        let level_changed = level.as_ref().is_some_and(|level| level.is_changed());
+        if all || sink.is_added() || level_changed {
+            sink.set_volume(volumes.get(*bus) * level.map_or(1.0, |level| level.0));
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! Ambient city sounds.
+//!
+//! Two looping layers play on the ambience bus: a traffic hum that follows the
+//! assigned flow (`EdgeFlow`) of the roads around the camera, and industrial noise
+//! from nearby industrial lots. Both are weighted by distance to the view center,
+//! fade as the camera zooms out and quieten at night. Without an audio device the
+//! layers are never heard but everything else keeps working.
+//!
+//! Neither layer is a recording: both are noise synthesized while playing
+//! (`AmbienceSound`), so they never loop audibly and share nothing with the music.
+
+use bevy::{
+    audio::{AddAudioSource, AudioPlugin, PlaybackMode, Source, Volume as AudioVolume},
+    prelude::*,
+};
+
+use crate::{
+    audio::{AudioBus, SoundLevel},
+    city::{
+        assignment::EdgeFlow,
+        clock::SimClock,
+        zones::{Zone, ZoneKind},
+    },
+    common::StageSelect,
+    game::{Line, OnGameScreen, PlayState},
+    rng::SimpleRng,
+};
+
+const SAMPLE_RATE: u32 = 44_100;
+/// Seconds between two hammer blows of the industry layer
+const HAMMER_PERIOD: f32 = 0.9;
+/// Seconds between recomputing the mix
+const MIX_INTERVAL: f32 = 0.5;
+/// How fast the layers follow the mix, per second
+const LEVEL_RATE: f32 = 1.5;
+/// Vehicles per hour near the camera for a full traffic hum
+const FULL_TRAFFIC: f32 = 4000.0;
+/// Industrial lots near the camera for full industrial noise
+const FULL_INDUSTRY: f32 = 6.0;
+
+pub fn ambience_plugin(app: &mut App) {
+    // Without the audio plugin there is nothing to play the layers, but they are
+    // still spawned and mixed
+    if app.is_plugin_added::<AudioPlugin>() {
+        app.add_audio_source::<AmbienceSound>();
+    } else {
+        app.init_asset::<AmbienceSound>();
+    }
+    app
+        .add_systems(OnEnter(StageSelect::Game), ambience_setup)
+        .add_systems(
+            Update,
+            (mix_ambience_system, follow_ambience_mix_system)
+                .chain()
+                .run_if(in_state(PlayState::Play)),
+        );
+}
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+enum AmbienceLayer {
+    Traffic,
+    Industry,
+}
+
+/// Endless synthesized sound of a layer
+#[derive(Asset, TypePath, Debug, Clone, Copy)]
+pub struct AmbienceSound(AmbienceLayer);
+
+impl Decodable for AmbienceSound {
+    type DecoderItem = f32;
+    type Decoder = AmbienceNoise;
+
+    fn decoder(&self) -> Self::Decoder {
+        AmbienceNoise { layer: self.0, rng: SimpleRng::new(0xA3B1_E4CE), rumble: 0.0, hammer: 0.0, sample: 0 }
+    }
+}
+
+/// Brown noise as the rumble of engines and tyres, industry adds a muffled hammer
+/// striking at a steady pace
+pub struct AmbienceNoise {
+    layer: AmbienceLayer,
+    rng: SimpleRng,
+    rumble: f32,
+    /// Low passed noise of the current hammer blow
+    hammer: f32,
+    /// Samples since the last hammer blow
+    sample: u32,
+}
+
+impl Iterator for AmbienceNoise {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let white = self.rng.next_range(-1.0, 1.0);
+        self.rumble = (self.rumble + 0.02 * white) / 1.02;
+        let rumble = self.rumble * 3.5;
+        self.sample = (self.sample + 1) % (HAMMER_PERIOD * SAMPLE_RATE as f32) as u32;
+
+        let sample = match self.layer {
+            AmbienceLayer::Traffic => rumble,
+            AmbienceLayer::Industry => {
+                let since_blow = self.sample as f32 / SAMPLE_RATE as f32;
+                self.hammer += 0.2 * (white - self.hammer);
+                0.5 * rumble + self.hammer * (-since_blow * 25.0).exp()
+            }
+        };
+        Some(sample.clamp(-1.0, 1.0))
+    }
+}
+
+impl Source for AmbienceNoise {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
+
+/// Level a layer is heading to, the `SoundLevel` eases toward it
+#[derive(Component, Debug, Clone, Copy, Default)]
+struct TargetLevel(f32);
+
+fn ambience_setup(mut commands: Commands, mut sounds: ResMut<Assets<AmbienceSound>>) {
+    for layer in [AmbienceLayer::Traffic, AmbienceLayer::Industry] {
+        commands.spawn((
+            OnGameScreen,
+            layer,
+            AudioPlayer(sounds.add(AmbienceSound(layer))),
+            // the sound never ends, looping would only buffer it
+            PlaybackSettings {
+                mode: PlaybackMode::Once,
+                volume: AudioVolume::new(0.0),
+                ..default()
+            },
+            AudioBus::Ambience,
+            SoundLevel(0.0),
+            TargetLevel::default(),
+        ));
+    }
+}
+
+/// 1.0 at the view center falling to 0.0 at `radius`
+fn proximity(pos: Vec2, center: Vec2, radius: f32) -> f32 {
+    (1.0 - pos.distance(center) / radius).max(0.0)
+}
+
+/// Quieter between late evening and early morning, smoothly in between
+fn daytime_factor(hour: f32) -> f32 {
+    let day = (0.5 - 0.5 * (hour / 24.0 * std::f32::consts::TAU).cos()).clamp(0.0, 1.0);
+    0.25 + 0.75 * day
+}
+
+// Works out how loud each layer should be for what the camera looks at
+#[allow(clippy::too_many_arguments)]
+fn mix_ambience_system(
+    time: Res<Time>,
+    clock: Res<SimClock>,
+    camera_q: Query<&Transform, With<Camera2d>>,
+    windows: Query<&Window>,
+    line_q: Query<(&Transform, &EdgeFlow), With<Line>>,
+    zone_q: Query<(&Transform, &Zone)>,
+    mut layer_q: Query<(&AmbienceLayer, &mut TargetLevel)>,
+    mut since_mix: Local<f32>,
+) {
+    *since_mix += time.delta_secs();
+    if *since_mix < MIX_INTERVAL {
+        return;
+    }
+    *since_mix = 0.0;
+
+    let Ok(camera) = camera_q.get_single() else { return };
+    let center = camera.translation.truncate();
+    let scale = camera.scale.x;
+    let view = windows.get_single().map_or(Vec2::new(1280.0, 720.0), |window| window.size()) * scale;
+    // Sounds carry a bit beyond the edge of the screen
+    let radius = view.length() * 0.6;
+    // Zoomed far out the city blends into a distant murmur
+    let zoom = (1.5 / scale).clamp(0.2, 1.0);
+    let daytime = daytime_factor(clock.hour());
+
+    let traffic: f32 = line_q
+        .iter()
+        .map(|(transform, flow)| flow.volume * proximity(transform.translation.truncate(), center, radius))
+        .sum();
+    let industry: f32 = zone_q
+        .iter()
+        .filter(|(_, zone)| zone.kind == ZoneKind::Industrial && zone.level > 0)
+        .map(|(transform, _)| proximity(transform.translation.truncate(), center, radius))
+        .sum();
+
+    for (layer, mut target) in &mut layer_q {
+        target.0 = match layer {
+            AmbienceLayer::Traffic => (traffic / FULL_TRAFFIC).min(1.0) * zoom * daytime,
+            // Factories keep some of their noise through the night shift
+            AmbienceLayer::Industry => (industry / FULL_INDUSTRY).min(1.0) * zoom * (0.5 + 0.5 * daytime),
+        };
+    }
+}
+
+fn follow_ambience_mix_system(time: Res<Time>, mut layer_q: Query<(&TargetLevel, &mut SoundLevel), With<AmbienceLayer>>) {
+    let step = LEVEL_RATE * time.delta_secs();
+    for (target, mut level) in &mut layer_q {
+        let next = level.0 + (target.0 - level.0).clamp(-step, step);
+        level.set_if_neq(SoundLevel(next));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::state::app::StatesPlugin;
+
+    use super::*;
+
+    #[test]
+    fn runs_without_the_audio_plugin() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default(), StatesPlugin))
+            .init_state::<StageSelect>()
+            .insert_state(PlayState::Play)
+            .init_resource::<SimClock>()
+            .add_plugins(ambience_plugin);
+        app.world_mut().spawn((Camera2d, Transform::default()));
+        for _ in 0..5 {
+            app.update();
+        }
+
+        let layers = app.world_mut().query::<&AmbienceLayer>().iter(app.world()).count();
+        assert_eq!(layers, 2);
+    }
+
+    #[test]
+    fn noise_stays_in_range() {
+        for layer in [AmbienceLayer::Traffic, AmbienceLayer::Industry] {
+            let noise = AmbienceSound(layer).decoder();
+            assert!(noise.take(SAMPLE_RATE as usize * 2).all(|sample| (-1.0..=1.0).contains(&sample)));
+        }
+    }
+}
@@ -0,0 +1,352 @@
+//! Static traffic assignment.
+//!
+//! Loads the `OdMatrix` onto the road graph with Frank-Wolfe user equilibrium and
+//! BPR volume-delay functions, giving every `Line` a volume and a congested travel
+//! time without simulating any vehicle. `assign_traffic` only needs a `RoadGraph`
+//! and an `OdMatrix`, so it can be run without an `App`. In game it reruns shortly
+//! after the roads or the demand change, V shows the result on the map.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    city::{
+        demand::OdMatrix,
+        graph::{GraphSettled, RoadGraph},
+    },
+    game::{Line, PlayState},
+    settings::bindings::{Actions, InputAction},
+};
+
+pub fn assignment_plugin(app: &mut App) {
+    app
+        .register_type::<EdgeFlow>()
+        .init_resource::<AssignmentParams>()
+        .init_resource::<TrafficAssignment>()
+        .init_resource::<ShowTraffic>()
+        .add_systems(
+            Update,
+            (
+                mark_assignment_stale_system,
+                run_assignment_system,
+                toggle_traffic_view_system,
+                draw_traffic_system.run_if(|show: Res<ShowTraffic>| show.0),
+            )
+                .chain()
+                .run_if(in_state(PlayState::Play)),
+        );
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct AssignmentParams {
+    /// BPR alpha, extra delay at capacity
+    pub alpha: f32,
+    /// BPR beta, how sharply delay grows past capacity
+    pub beta: f32,
+    /// Free flow speed in world units per minute
+    pub free_speed: f32,
+    /// Vehicles per hour a road carries before it starts to congest
+    pub capacity: f32,
+    pub max_iterations: usize,
+    /// Stop once the relative gap falls below this
+    pub gap_tolerance: f32,
+}
+
+impl Default for AssignmentParams {
+    fn default() -> Self {
+        Self {
+            alpha: 0.15,
+            beta: 4.0,
+            free_speed: 500.0,
+            capacity: 1800.0,
+            max_iterations: 30,
+            gap_tolerance: 1e-3,
+        }
+    }
+}
+
+impl AssignmentParams {
+    /// BPR volume-delay function
+    pub fn travel_time(&self, free_flow_time: f32, volume: f32) -> f32 {
+        free_flow_time * (1.0 + self.alpha * (volume / self.capacity).powf(self.beta))
+    }
+}
+
+/// Assigned flow of one road, stored on the `Line` entity
+#[derive(Component, Reflect, Debug, Clone, Copy, Default, PartialEq)]
+#[reflect(Component)]
+pub struct EdgeFlow {
+    /// Vehicles per hour, both directions together
+    pub volume: f32,
+    /// Congested travel time in minutes
+    pub travel_time: f32,
+    pub free_flow_time: f32,
+}
+
+impl EdgeFlow {
+    pub fn volume_capacity_ratio(&self, params: &AssignmentParams) -> f32 {
+        self.volume / params.capacity
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AssignmentResult {
+    pub flows: HashMap<Entity, EdgeFlow>,
+    pub iterations: usize,
+    pub relative_gap: f32,
+}
+
+/// Latest assignment, `stale` once the roads or the demand changed since
+#[derive(Resource, Debug, Default)]
+pub struct TrafficAssignment {
+    pub result: AssignmentResult,
+    pub stale: bool,
+    since_stale: f32,
+}
+
+/// Frank-Wolfe user equilibrium of `od` over `graph`
+pub fn assign_traffic(graph: &RoadGraph, od: &OdMatrix, params: &AssignmentParams) -> AssignmentResult {
+    // every Line appears twice in the adjacency, give each one a single id
+    let mut ids: HashMap<Entity, usize> = HashMap::new();
+    let mut free_flow = vec![];
+    for edges in &graph.adjacency {
+        for edge in edges {
+            ids.entry(edge.line).or_insert_with(|| {
+                free_flow.push(edge.length / params.free_speed);
+                free_flow.len() - 1
+            });
+        }
+    }
+
+    let times_for = |volumes: &[f32]| -> Vec<f32> {
+        free_flow.iter().zip(volumes).map(|(t0, v)| params.travel_time(*t0, *v)).collect()
+    };
+
+    let mut volumes = all_or_nothing(graph, od, &ids, &free_flow);
+    let mut iterations = 0;
+    let mut relative_gap = 0.0;
+
+    for _ in 0..params.max_iterations {
+        iterations += 1;
+        let times = times_for(&volumes);
+        let target = all_or_nothing(graph, od, &ids, &times);
+
+        let current_cost: f32 = volumes.iter().zip(&times).map(|(v, t)| v * t).sum();
+        let target_cost: f32 = target.iter().zip(&times).map(|(v, t)| v * t).sum();
+        relative_gap = if current_cost > 0.0 { (current_cost - target_cost) / current_cost } else { 0.0 };
+        if relative_gap < params.gap_tolerance {
+            break;
+        }
+
+        // bisection on the derivative of the Beckmann objective along the direction
+        let (mut low, mut high) = (0.0f32, 1.0f32);
+        for _ in 0..20 {
+            let step = (low + high) / 2.0;
+            let slope: f32 = volumes
+                .iter()
+                .zip(&target)
+                .zip(&free_flow)
+                .map(|((x, y), t0)| (y - x) * params.travel_time(*t0, x + step * (y - x)))
+                .sum();
+            if slope > 0.0 {
+                high = step;
+            } else {
+                low = step;
+            }
+        }
+        let step = (low + high) / 2.0;
+        for (x, y) in volumes.iter_mut().zip(&target) {
+            *x += step * (y - *x);
+        }
+    }
+
+    let times = times_for(&volumes);
+    let flows = ids
+        .iter()
+        .map(|(line, &id)| {
+            (*line, EdgeFlow { volume: volumes[id], travel_time: times[id], free_flow_time: free_flow[id] })
+        })
+        .collect();
+
+    AssignmentResult { flows, iterations, relative_gap }
+}
+
+/// Loads every trip on its shortest path for the given edge times
+fn all_or_nothing(graph: &RoadGraph, od: &OdMatrix, ids: &HashMap<Entity, usize>, times: &[f32]) -> Vec<f32> {
+    let mut volumes = vec![0.0; times.len()];
+    let zones: Vec<Option<usize>> = od.zones.iter().map(|z| graph.index.get(z).copied()).collect();
+
+    for (i, origin) in zones.iter().enumerate() {
+        let Some(origin) = *origin else { continue };
+        if (0..od.len()).all(|j| od.get(i, j) <= 0.0) {
+            continue;
+        }
+        let (cost, previous) = graph.dijkstra_with(origin, |e| times[ids[&e.line]], f32::INFINITY);
+
+        for (j, destination) in zones.iter().enumerate() {
+            let trips = od.get(i, j);
+            let Some(mut node) = *destination else { continue };
+            if trips <= 0.0 || !cost[node].is_finite() {
+                continue;
+            }
+            while let Some((p, line)) = previous[node] {
+                volumes[ids[&line]] += trips;
+                node = p;
+            }
+        }
+    }
+    volumes
+}
+
+fn mark_assignment_stale_system(
+    mut graph_settled: GraphSettled,
+    od: Res<OdMatrix>,
+    mut assignment: ResMut<TrafficAssignment>,
+) {
+    if graph_settled.changed() || od.is_changed() {
+        assignment.stale = true;
+        assignment.since_stale = 0.0;
+    }
+}
+
+// Waits for the roads to settle (e.g. while a node is dragged) before reassigning
+fn run_assignment_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    graph: Res<RoadGraph>,
+    od: Res<OdMatrix>,
+    params: Res<AssignmentParams>,
+    mut assignment: ResMut<TrafficAssignment>,
+    line_q: Query<Entity, With<Line>>,
+) {
+    if !assignment.stale {
+        return;
+    }
+    assignment.since_stale += time.delta_secs();
+    if assignment.since_stale < 0.5 {
+        return;
+    }
+
+    let result = assign_traffic(&graph, &od, &params);
+    debug!("Traffic assigned in {} iterations, gap {:.5}", result.iterations, result.relative_gap);
+    for line in &line_q {
+        let flow = result.flows.get(&line).copied().unwrap_or_default();
+        commands.entity(line).insert(flow);
+    }
+    assignment.result = result;
+    assignment.stale = false;
+}
+
+#[derive(Resource, Default, Debug)]
+pub struct ShowTraffic(pub bool);
+
+fn toggle_traffic_view_system(actions: Actions, mut show: ResMut<ShowTraffic>) {
+    if actions.just_pressed(InputAction::TrafficView) {
+        show.0 = !show.0;
+    }
+}
+
+// Roads from green (free flowing) to red (at capacity and beyond)
+fn draw_traffic_system(
+    graph: Res<RoadGraph>,
+    params: Res<AssignmentParams>,
+    flow_q: Query<(&Line, &EdgeFlow)>,
+    mut gizmos: Gizmos,
+) {
+    for (line, flow) in &flow_q {
+        let (Some(a), Some(b)) = (graph.index.get(&line.from), graph.index.get(&line.to)) else { continue };
+        let ratio = flow.volume_capacity_ratio(&params).min(1.0);
+        let color = Color::srgb(ratio, 1.0 - ratio, 0.1);
+        gizmos.line_2d(graph.positions[*a], graph.positions[*b], color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(i: usize) -> Entity {
+        Entity::from_raw(100 + i as u32)
+    }
+
+    fn road_graph(positions: &[Vec2], edges: &[(usize, usize)]) -> RoadGraph {
+        let mut graph = RoadGraph::default();
+        for (i, pos) in positions.iter().enumerate() {
+            graph.add_node(Entity::from_raw(i as u32), *pos);
+        }
+        for (i, &(a, b)) in edges.iter().enumerate() {
+            graph.add_edge(a, b, line(i));
+        }
+        graph
+    }
+
+    fn single_trip(graph: &RoadGraph, from: usize, to: usize, trips: f32) -> OdMatrix {
+        let mut od = OdMatrix::new(vec![graph.nodes[from], graph.nodes[to]]);
+        od.set(0, 1, trips);
+        od
+    }
+
+    fn converged() -> AssignmentParams {
+        AssignmentParams { max_iterations: 500, gap_tolerance: 1e-6, ..default() }
+    }
+
+    #[test]
+    fn bpr_delay_at_capacity() {
+        let params = AssignmentParams::default();
+        let t0 = 2.5;
+        let at_capacity = params.travel_time(t0, params.capacity);
+        assert!((at_capacity - t0 * (1.0 + params.alpha)).abs() < 1e-5);
+        assert_eq!(params.travel_time(t0, 0.0), t0);
+    }
+
+    #[test]
+    fn equilibrium_equalizes_parallel_routes() {
+        // a direct road and a longer detour through node 2
+        let graph = road_graph(
+            &[Vec2::ZERO, Vec2::new(1000.0, 0.0), Vec2::new(500.0, 300.0)],
+            &[(0, 1), (0, 2), (2, 1)],
+        );
+        let od = single_trip(&graph, 0, 1, 6000.0);
+        let result = assign_traffic(&graph, &od, &converged());
+
+        let direct = result.flows[&line(0)];
+        let detour = [result.flows[&line(1)], result.flows[&line(2)]];
+        assert!(detour[0].volume > 0.0, "the detour should take some of the traffic");
+        let detour_time = detour[0].travel_time + detour[1].travel_time;
+        assert!(
+            (direct.travel_time - detour_time).abs() / direct.travel_time < 0.01,
+            "{} vs {}",
+            direct.travel_time,
+            detour_time
+        );
+    }
+
+    #[test]
+    fn parallel_lines_between_the_same_nodes_share_the_load() {
+        let graph = road_graph(&[Vec2::ZERO, Vec2::new(800.0, 0.0)], &[(0, 1), (0, 1)]);
+        let od = single_trip(&graph, 0, 1, 4000.0);
+        let result = assign_traffic(&graph, &od, &converged());
+
+        let (a, b) = (result.flows[&line(0)].volume, result.flows[&line(1)].volume);
+        assert!((a - b).abs() / 4000.0 < 0.01, "{a} vs {b}");
+    }
+
+    #[test]
+    fn flow_is_conserved() {
+        // a square, trips go from one corner to the opposite one
+        let graph = road_graph(
+            &[Vec2::ZERO, Vec2::new(600.0, 0.0), Vec2::new(0.0, 600.0), Vec2::new(600.0, 600.0)],
+            &[(0, 1), (1, 3), (0, 2), (2, 3)],
+        );
+        let trips = 5000.0;
+        let od = single_trip(&graph, 0, 3, trips);
+        let result = assign_traffic(&graph, &od, &converged());
+        let volume = |i| result.flows[&line(i)].volume;
+
+        assert!((volume(0) + volume(2) - trips).abs() < 1.0, "leaving the origin");
+        assert!((volume(1) + volume(3) - trips).abs() < 1.0, "reaching the destination");
+        assert!((volume(0) - volume(1)).abs() < 1.0, "through node 1");
+        assert!((volume(2) - volume(3)).abs() < 1.0, "through node 2");
+    }
+}
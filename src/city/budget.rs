@@ -0,0 +1,74 @@
+//! City finances: taxes from every occupied lot, more from valuable land, and the
+//! upkeep of the services, settled every simulated hour.
+
+use bevy::prelude::*;
+
+use crate::{
+    city::{
+        citizens::Citizen,
+        clock::SimClock,
+        land_value::{lot_occupants, LandValue},
+        services::ServiceBuilding,
+    },
+    game::PlayState,
+    notifications::{Notification, NotificationKind},
+};
+
+pub fn budget_plugin(app: &mut App) {
+    app
+        .init_resource::<Budget>()
+        .add_systems(Update, settle_budget_system.run_if(in_state(PlayState::Play)));
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct Budget {
+    pub balance: f32,
+    /// Taxes per occupant and hour on a lot worth 1.0
+    pub tax_rate: f32,
+    /// Taxes collected in the last hour
+    pub income: f32,
+    /// Upkeep paid in the last hour
+    pub expenses: f32,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Self {
+            balance: 20_000.0,
+            tax_rate: 4.0,
+            income: 0.0,
+            expenses: 0.0,
+        }
+    }
+}
+
+fn settle_budget_system(
+    clock: Res<SimClock>,
+    mut budget: ResMut<Budget>,
+    mut last_hour: Local<Option<u32>>,
+    citizen_q: Query<&Citizen>,
+    lot_q: Query<(Entity, &LandValue)>,
+    service_q: Query<&ServiceBuilding>,
+    mut notifications: EventWriter<Notification>,
+) {
+    let hour = (clock.minutes / 60.0) as u32;
+    match last_hour.replace(hour) {
+        Some(previous) if previous != hour => {}
+        _ => return,
+    }
+
+    let occupants = lot_occupants(citizen_q.iter());
+    let income: f32 = lot_q
+        .iter()
+        .map(|(lot, value)| occupants.get(&lot).copied().unwrap_or(0) as f32 * (0.25 + value.value) * budget.tax_rate)
+        .sum();
+    let expenses: f32 = service_q.iter().map(|s| s.kind.upkeep()).sum();
+
+    budget.income = income;
+    budget.expenses = expenses;
+    let was_solvent = budget.balance >= 0.0;
+    budget.balance += income - expenses;
+    if was_solvent && budget.balance < 0.0 {
+        notifications.send(Notification::new(NotificationKind::BudgetDeficit { balance: budget.balance }));
+    }
+}
@@ -0,0 +1,368 @@
+//! Moving the camera around the city.
+//!
+//! WASD pans and Q/E zoom (or whatever they are rebound to), the mouse wheel zooms toward the cursor and
+//! holding the middle or right button drags the view. Edge scrolling is optional.
+//! On a gamepad the left stick pans and the right/left triggers zoom in/out.
+//! All input only moves a target (`CameraRig`) which the camera eases toward, and
+//! that target is kept inside the city bounds. Other systems may still move the
+//! camera directly (minimap, event log), the rig picks that up as its new target.
+//!
+//! Ctrl+1..9 bookmark the current view and 1..9 fly back to it, bookmarks are part
+//! of the city save. F follows the selected entity until pressed again.
+
+use std::path::Path;
+
+use bevy::{
+    input::mouse::{AccumulatedMouseMotion, AccumulatedMouseScroll, MouseScrollUnit},
+    prelude::*,
+    ui::RelativeCursorPosition,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    city::{graph::RoadGraph, minimap::city_bounds, terrain::Terrain},
+    game::{PlayState, Selection},
+    settings::{
+        bindings::{Actions, InputAction},
+        globals::{CameraSmoothing, EdgeScroll, PanSensitivity, ZoomSensitivity},
+        settings_io::SettingPlugin,
+    },
+};
+
+pub const MIN_ZOOM: f32 = 0.1;
+pub const MAX_ZOOM: f32 = 10.0;
+/// World units per second at zoom 1.0
+const PAN_SPEED: f32 = 300.0;
+/// Zoom factor per second when holding Q or E
+const KEY_ZOOM_SPEED: f32 = 1.5;
+/// Zoom factor per wheel line
+const WHEEL_ZOOM_STEP: f32 = 1.1;
+/// Pixels per wheel line for touchpads reporting pixels
+const PIXELS_PER_LINE: f32 = 40.0;
+/// Width of the window border that scrolls when edge scrolling is on
+const EDGE_MARGIN: f32 = 8.0;
+
+pub fn camera_plugin(app: &mut App) {
+    app
+        .add_plugins((
+            SettingPlugin::new(Path::new("assets/settings/zoom_sensitivity.json"), ZoomSensitivity::default()),
+            SettingPlugin::new(Path::new("assets/settings/pan_sensitivity.json"), PanSensitivity::default()),
+            SettingPlugin::new(Path::new("assets/settings/camera_smoothing.json"), CameraSmoothing::default()),
+            SettingPlugin::new(Path::new("assets/settings/edge_scroll.json"), EdgeScroll::default()),
+        ))
+        .init_resource::<CameraRig>()
+        .init_resource::<CameraBookmarks>()
+        .init_resource::<CameraFollow>()
+        .add_systems(
+            Update,
+            (
+                sync_camera_rig_system,
+                keyboard_camera_system,
+                gamepad_camera_system,
+                mouse_zoom_system,
+                drag_pan_system,
+                edge_scroll_system,
+                camera_bookmark_system,
+                toggle_follow_system,
+                follow_camera_system,
+                clamp_camera_rig_system,
+                apply_camera_rig_system,
+            )
+                .chain()
+                .run_if(in_state(PlayState::Play)),
+        );
+}
+
+/// Where the camera is headed, input moves this and the camera follows
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct CameraRig {
+    pub translation: Vec2,
+    pub scale: f32,
+    /// What was last written to the camera, anything else means someone else moved it
+    applied: Option<(Vec2, f32)>,
+}
+
+impl Default for CameraRig {
+    fn default() -> Self {
+        Self {
+            translation: Vec2::ZERO,
+            scale: 1.0,
+            applied: None,
+        }
+    }
+}
+
+impl CameraRig {
+    /// Zooms by a factor while keeping the world point under `anchor` (window coordinates) still
+    fn zoom_around(&mut self, factor: f32, anchor: Vec2, window_size: Vec2) {
+        let offset = (anchor - window_size / 2.0) * Vec2::new(1.0, -1.0);
+        let anchor_world = self.translation + offset * self.scale;
+        let scale = (self.scale * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        self.translation = anchor_world - offset * scale;
+        self.scale = scale;
+    }
+}
+
+/// A remembered view
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct CameraBookmark {
+    pub translation: Vec2,
+    pub scale: f32,
+}
+
+/// Views stored under the number keys, saved with the city
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct CameraBookmarks(pub [Option<CameraBookmark>; 9]);
+
+/// Entity the camera keeps centered on
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq)]
+pub struct CameraFollow(pub Option<Entity>);
+
+/// Whether the cursor is over some UI, which then gets the mouse instead of the camera
+pub fn cursor_over_ui(
+    interaction_q: &Query<&Interaction>,
+    cursor_q: &Query<(&RelativeCursorPosition, &InheritedVisibility)>,
+) -> bool {
+    interaction_q.iter().any(|i| *i != Interaction::None)
+        || cursor_q.iter().any(|(cursor, visible)| visible.get() && cursor.mouse_over())
+}
+
+// Follows the camera when it was moved by something other than the rig
+fn sync_camera_rig_system(mut rig: ResMut<CameraRig>, camera_q: Query<&Transform, With<Camera2d>>) {
+    let Ok(transform) = camera_q.get_single() else { return };
+    let current = (transform.translation.truncate(), transform.scale.x);
+    if rig.applied != Some(current) {
+        rig.translation = current.0;
+        rig.scale = current.1;
+        rig.applied = Some(current);
+    }
+}
+
+fn keyboard_camera_system(
+    time: Res<Time>,
+    actions: Actions,
+    pan: Res<PanSensitivity>,
+    zoom: Res<ZoomSensitivity>,
+    mut rig: ResMut<CameraRig>,
+) {
+    let delta = time.delta_secs();
+
+    let mut direction = Vec2::ZERO;
+    if actions.pressed(InputAction::PanLeft) {
+        direction.x -= 1.0;
+    }
+    if actions.pressed(InputAction::PanRight) {
+        direction.x += 1.0;
+    }
+    if actions.pressed(InputAction::PanUp) {
+        direction.y += 1.0;
+    }
+    if actions.pressed(InputAction::PanDown) {
+        direction.y -= 1.0;
+    }
+    if direction != Vec2::ZERO {
+        let step = direction * PAN_SPEED * pan.0 * rig.scale * delta;
+        rig.translation += step;
+    }
+
+    let mut scale_change = 0.0;
+    if actions.pressed(InputAction::ZoomOut) {
+        scale_change += 1.0;
+    }
+    if actions.pressed(InputAction::ZoomIn) {
+        scale_change -= 1.0;
+    }
+    if scale_change != 0.0 {
+        let factor = KEY_ZOOM_SPEED.powf(scale_change * zoom.0 * delta);
+        rig.scale = (rig.scale * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+}
+
+fn gamepad_camera_system(
+    time: Res<Time>,
+    gamepads: Query<&Gamepad>,
+    pan: Res<PanSensitivity>,
+    zoom: Res<ZoomSensitivity>,
+    mut rig: ResMut<CameraRig>,
+) {
+    let delta = time.delta_secs();
+    for gamepad in &gamepads {
+        let stick = gamepad.left_stick();
+        if stick != Vec2::ZERO {
+            let step = stick * PAN_SPEED * pan.0 * rig.scale * delta;
+            rig.translation += step;
+        }
+
+        let zoom_in = gamepad.get(GamepadButton::RightTrigger2).unwrap_or(0.0);
+        let zoom_out = gamepad.get(GamepadButton::LeftTrigger2).unwrap_or(0.0);
+        let scale_change = zoom_out - zoom_in;
+        if scale_change != 0.0 {
+            let factor = KEY_ZOOM_SPEED.powf(scale_change * zoom.0 * delta);
+            rig.scale = (rig.scale * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        }
+    }
+}
+
+// Wheel up zooms in toward the cursor, wheel down zooms out away from it
+fn mouse_zoom_system(
+    scroll: Res<AccumulatedMouseScroll>,
+    zoom: Res<ZoomSensitivity>,
+    windows: Query<&Window>,
+    interaction_q: Query<&Interaction>,
+    cursor_q: Query<(&RelativeCursorPosition, &InheritedVisibility)>,
+    mut rig: ResMut<CameraRig>,
+) {
+    if scroll.delta.y == 0.0 || cursor_over_ui(&interaction_q, &cursor_q) {
+        return;
+    }
+    let Ok(window) = windows.get_single() else { return };
+    let Some(cursor) = window.cursor_position() else { return };
+
+    let lines = match scroll.unit {
+        MouseScrollUnit::Line => scroll.delta.y,
+        MouseScrollUnit::Pixel => scroll.delta.y / PIXELS_PER_LINE,
+    };
+    let factor = WHEEL_ZOOM_STEP.powf(-lines * zoom.0);
+    rig.zoom_around(factor, cursor, window.size());
+}
+
+// Holding the middle or right button drags the city along with the cursor
+fn drag_pan_system(
+    buttons: Res<ButtonInput<MouseButton>>,
+    motion: Res<AccumulatedMouseMotion>,
+    interaction_q: Query<&Interaction>,
+    cursor_q: Query<(&RelativeCursorPosition, &InheritedVisibility)>,
+    mut dragging: Local<bool>,
+    mut rig: ResMut<CameraRig>,
+) {
+    let drag_buttons = [MouseButton::Middle, MouseButton::Right];
+    if buttons.any_just_pressed(drag_buttons) {
+        *dragging = !cursor_over_ui(&interaction_q, &cursor_q);
+    }
+    if !buttons.any_pressed(drag_buttons) {
+        *dragging = false;
+    }
+    if *dragging && motion.delta != Vec2::ZERO {
+        let step = motion.delta * Vec2::new(-1.0, 1.0) * rig.scale;
+        rig.translation += step;
+    }
+}
+
+fn edge_scroll_system(
+    time: Res<Time>,
+    edge_scroll: Res<EdgeScroll>,
+    pan: Res<PanSensitivity>,
+    windows: Query<&Window>,
+    mut rig: ResMut<CameraRig>,
+) {
+    if *edge_scroll == EdgeScroll::Disabled {
+        return;
+    }
+    let Ok(window) = windows.get_single() else { return };
+    let Some(cursor) = window.cursor_position() else { return };
+    let size = window.size();
+
+    let mut direction = Vec2::ZERO;
+    if cursor.x <= EDGE_MARGIN {
+        direction.x -= 1.0;
+    }
+    if cursor.x >= size.x - EDGE_MARGIN {
+        direction.x += 1.0;
+    }
+    if cursor.y <= EDGE_MARGIN {
+        direction.y += 1.0;
+    }
+    if cursor.y >= size.y - EDGE_MARGIN {
+        direction.y -= 1.0;
+    }
+    if direction != Vec2::ZERO {
+        let step = direction * PAN_SPEED * pan.0 * rig.scale * time.delta_secs();
+        rig.translation += step;
+    }
+}
+
+// Ctrl+bookmark stores the view the camera is headed to, the bookmark alone flies back to it
+fn camera_bookmark_system(
+    keys: Res<ButtonInput<KeyCode>>,
+    actions: Actions,
+    mut bookmarks: ResMut<CameraBookmarks>,
+    mut follow: ResMut<CameraFollow>,
+    mut rig: ResMut<CameraRig>,
+) {
+    let ctrl = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    for (slot, action) in InputAction::BOOKMARKS.into_iter().enumerate() {
+        if !actions.just_pressed(action) {
+            continue;
+        }
+        if ctrl {
+            bookmarks.0[slot] = Some(CameraBookmark {
+                translation: rig.translation,
+                scale: rig.scale,
+            });
+            info!("Camera bookmark {} stored", slot + 1);
+        } else if let Some(bookmark) = bookmarks.0[slot] {
+            follow.0 = None;
+            rig.translation = bookmark.translation;
+            rig.scale = bookmark.scale;
+        }
+    }
+}
+
+fn toggle_follow_system(actions: Actions, selection: Res<Selection>, mut follow: ResMut<CameraFollow>) {
+    if actions.just_pressed(InputAction::FollowSelection) {
+        follow.0 = if follow.0.is_some() { None } else { selection.0 };
+    }
+}
+
+// Keeps the followed entity in the middle of the view, stops once it is gone
+fn follow_camera_system(
+    mut follow: ResMut<CameraFollow>,
+    transform_q: Query<&GlobalTransform>,
+    mut rig: ResMut<CameraRig>,
+) {
+    let Some(entity) = follow.0 else { return };
+    match transform_q.get(entity) {
+        Ok(transform) => rig.translation = transform.translation().truncate(),
+        Err(_) => follow.0 = None,
+    }
+}
+
+// The view center may not leave the city, so there is always something to look at
+fn clamp_camera_rig_system(terrain: Res<Terrain>, graph: Res<RoadGraph>, mut rig: ResMut<CameraRig>) {
+    let bounds = city_bounds(&terrain, &graph);
+    let clamped = rig.translation.clamp(bounds.min, bounds.max);
+    if clamped != rig.translation {
+        rig.translation = clamped;
+    }
+}
+
+// Eases the camera toward the rig, the smoothing setting picks how fast
+fn apply_camera_rig_system(
+    time: Res<Time>,
+    smoothing: Res<CameraSmoothing>,
+    mut rig: ResMut<CameraRig>,
+    mut camera_q: Query<&mut Transform, With<Camera2d>>,
+) {
+    let Ok(mut transform) = camera_q.get_single_mut() else { return };
+
+    let rate = 40.0 * (1.0 - smoothing.0).powi(2) + 3.0;
+    let t = if smoothing.0 <= 0.0 { 1.0 } else { 1.0 - (-rate * time.delta_secs()).exp() };
+
+    let current = transform.translation.truncate();
+    let mut translation = current.lerp(rig.translation, t);
+    let mut scale = transform.scale.x + (rig.scale - transform.scale.x) * t;
+    // Snap the last bit so the camera comes to rest
+    if translation.distance(rig.translation) < 0.01 * rig.scale {
+        translation = rig.translation;
+    }
+    if (scale - rig.scale).abs() < 0.0001 {
+        scale = rig.scale;
+    }
+
+    if (translation, scale) != (current, transform.scale.x) {
+        transform.translation.x = translation.x;
+        transform.translation.y = translation.y;
+        transform.scale = Vec3::splat(scale);
+    }
+    rig.applied = Some((translation, scale));
+}
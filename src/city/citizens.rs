@@ -0,0 +1,643 @@
+//! Citizens living in the residential lots.
+//!
+//! Every citizen has a home, maybe a workplace, an age and a daily schedule run on
+//! the `SimClock`: sleep, commute, work, sometimes shop, return home. Trips pick a
+//! mode (walk, transit when a line stops within walking distance of both ends,
+//! otherwise car) and cars drive
+//! the route that is fastest given the assigned traffic. Happiness follows the
+//! services covering their home, health the air they breathe there. `CitizenStats` sums it all up.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    city::{
+        assignment::{AssignmentParams, EdgeFlow},
+        budget::Budget,
+        clock::SimClock,
+        graph::RoadGraph,
+        pollution::Pollution,
+        services::{ServiceCoverage, ServiceKind},
+        transit::{Passenger, TransitLine, TransitVehicle},
+        vehicles::{spawn_vehicle, NodeReached, Vehicle},
+        zones::{Zone, ZoneKind},
+    },
+    common::StageSelect,
+    game::{CityEntity, OnGameScreen, PlayState},
+    menus::ui::TEXT_COLOR,
+    rng::SimpleRng,
+};
+
+/// Citizens moving into empty homes per frame
+const MOVE_INS_PER_FRAME: usize = 10;
+/// Trips shorter than this are walked
+const WALK_DISTANCE: f32 = 600.0;
+/// World units per simulated minute on foot
+const WALK_SPEED: f32 = 80.0;
+const CAR_SPEED: f32 = 500.0;
+const CAR_COLOR: Color = Color::srgb(0.95, 0.95, 0.95);
+/// Simulated minutes after which a ride that never arrived is given up on
+const TRIP_TIMEOUT: f32 = 180.0;
+
+pub fn citizens_plugin(app: &mut App) {
+    app
+        .add_event::<TripEnded>()
+        .init_resource::<CitizenStats>()
+        .add_systems(OnEnter(StageSelect::Game), city_stats_panel_setup)
+        .add_systems(
+            Update,
+            (
+                move_in_system,
+                car_arrival_system,
+                trip_ended_system,
+                citizen_schedule_system,
+                citizen_happiness_system,
+                update_citizen_stats_system,
+                update_city_stats_panel_system,
+            )
+                .chain()
+                .run_if(in_state(PlayState::Play)),
+        );
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Activity {
+    Sleep,
+    Home,
+    /// On the way to work
+    Commute,
+    Work,
+    /// Shopping, or on the way to the shop while a trip is running
+    Shop,
+    /// On the way back home
+    Return,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TravelMode {
+    Walk,
+    Transit,
+    Car,
+}
+
+/// Hours of the day
+#[derive(Clone, Copy, Debug)]
+pub struct Schedule {
+    pub wake: f32,
+    pub work_start: f32,
+    pub work_end: f32,
+    pub sleep: f32,
+    /// Goes shopping after work (or late morning without a job)
+    pub shops: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Trip {
+    pub mode: TravelMode,
+    pub started: f32,
+    /// Arrival time of trips on foot
+    pub walk_until: Option<f32>,
+}
+
+#[derive(Component, Debug, Clone)]
+pub struct Citizen {
+    /// Residential `Zone` lot
+    pub home: Entity,
+    /// Commercial or industrial `Zone` lot
+    pub workplace: Option<Entity>,
+    pub age: u32,
+    pub schedule: Schedule,
+    pub activity: Activity,
+    pub trip: Option<Trip>,
+    /// Day of the last commute, so nobody goes to work twice a day
+    pub last_work_day: Option<u32>,
+    pub last_shop_day: Option<u32>,
+    /// Simulated minutes until a shopping trip is over
+    pub shop_until: f32,
+    /// Minutes the last commute took
+    pub last_commute: Option<f32>,
+    /// From 0 to 1
+    pub happiness: f32,
+    /// From 0 to 1
+    pub health: f32,
+}
+
+impl Citizen {
+    pub fn working_age(&self) -> bool {
+        (18..65).contains(&self.age)
+    }
+}
+
+/// Sent when a citizen arrives at the end of a trip, whatever the mode
+#[derive(Event, Debug, Clone, Copy)]
+pub struct TripEnded {
+    pub citizen: Entity,
+}
+
+/// Marks the car a citizen is driving
+#[derive(Component, Debug, Clone, Copy)]
+pub struct CarTrip {
+    pub citizen: Entity,
+}
+
+#[derive(Resource, Debug, Clone, Default)]
+pub struct CitizenStats {
+    pub population: u32,
+    pub working_age: u32,
+    pub employed: u32,
+    /// Average of everyone's last commute in minutes
+    pub average_commute: f32,
+    pub average_happiness: f32,
+    pub average_health: f32,
+    pub trips_by_mode: HashMap<TravelMode, u32>,
+}
+
+impl CitizenStats {
+    pub fn employment_rate(&self) -> f32 {
+        if self.working_age == 0 { 0.0 } else { self.employed as f32 / self.working_age as f32 }
+    }
+}
+
+// Fills empty homes with new citizens, who look for a job not too far away
+fn move_in_system(
+    mut commands: Commands,
+    mut rng: ResMut<SimpleRng>,
+    zone_q: Query<(Entity, &Zone, &Transform)>,
+    citizen_q: Query<&Citizen>,
+) {
+    let mut residents: HashMap<Entity, u32> = HashMap::new();
+    let mut workers: HashMap<Entity, u32> = HashMap::new();
+    for citizen in &citizen_q {
+        *residents.entry(citizen.home).or_default() += 1;
+        if let Some(work) = citizen.workplace {
+            *workers.entry(work).or_default() += 1;
+        }
+    }
+
+    let mut moved_in = 0;
+    for (home, zone, home_transform) in &zone_q {
+        let vacant = zone.homes().saturating_sub(residents.get(&home).copied().unwrap_or(0));
+        for _ in 0..vacant {
+            if moved_in >= MOVE_INS_PER_FRAME {
+                return;
+            }
+            moved_in += 1;
+
+            let age = (rng.next_scaled() * 80.0) as u32;
+            let home_pos = home_transform.translation.truncate();
+            let workplace = if (18..65).contains(&age) && rng.next_scaled() < 0.9 {
+                // closer jobs with more openings are more likely
+                let openings: Vec<(Entity, f32)> = zone_q
+                    .iter()
+                    .filter_map(|(lot, zone, transform)| {
+                        let open = zone.jobs().saturating_sub(workers.get(&lot).copied().unwrap_or(0));
+                        let distance = transform.translation.truncate().distance(home_pos);
+                        (open > 0).then(|| (lot, open as f32 * (-distance / 1500.0).exp()))
+                    })
+                    .collect();
+                let total: f32 = openings.iter().map(|(_, w)| w).sum();
+                let mut pick = rng.next_scaled() * total;
+                openings.into_iter().find(|(_, w)| {
+                    pick -= w;
+                    pick <= 0.0
+                }).map(|(lot, _)| lot)
+            } else {
+                None
+            };
+            if let Some(work) = workplace {
+                *workers.entry(work).or_default() += 1;
+            }
+
+            let wake = rng.next_range(5.5, 8.0);
+            let work_start = wake + rng.next_range(1.0, 2.0);
+            commands.spawn((
+                OnGameScreen,
+                CityEntity,
+                Citizen {
+                    home,
+                    workplace,
+                    age,
+                    schedule: Schedule {
+                        wake,
+                        work_start,
+                        work_end: work_start + 8.0,
+                        sleep: rng.next_range(21.5, 23.5),
+                        shops: rng.next_scaled() < 0.3,
+                    },
+                    activity: Activity::Sleep,
+                    trip: None,
+                    last_work_day: None,
+                    last_shop_day: None,
+                    shop_until: 0.0,
+                    last_commute: None,
+                    happiness: 0.5,
+                    health: 1.0,
+                },
+            ));
+        }
+    }
+}
+
+fn car_arrival_system(
+    mut commands: Commands,
+    mut reached: EventReader<NodeReached>,
+    car_q: Query<&CarTrip>,
+    mut trips_ended: EventWriter<TripEnded>,
+) {
+    for event in reached.read().filter(|e| e.last) {
+        if let Ok(car) = car_q.get(event.vehicle) {
+            trips_ended.send(TripEnded { citizen: car.citizen });
+            commands.entity(event.vehicle).despawn_recursive();
+        }
+    }
+}
+
+fn trip_ended_system(
+    clock: Res<SimClock>,
+    mut trips_ended: EventReader<TripEnded>,
+    mut citizen_q: Query<&mut Citizen>,
+) {
+    for event in trips_ended.read() {
+        let Ok(mut citizen) = citizen_q.get_mut(event.citizen) else { continue };
+        arrive(&mut citizen, &clock);
+    }
+}
+
+fn arrive(citizen: &mut Citizen, clock: &SimClock) {
+    let Some(trip) = citizen.trip.take() else { return };
+    match citizen.activity {
+        Activity::Commute => {
+            citizen.last_commute = Some(clock.minutes - trip.started);
+            citizen.activity = Activity::Work;
+        }
+        Activity::Shop => citizen.shop_until = clock.minutes + 45.0,
+        Activity::Return => citizen.activity = Activity::Home,
+        _ => {}
+    }
+}
+
+/// Takes a citizen out of the car or the transit line a ride got stuck in
+fn abandon_ride(
+    commands: &mut Commands,
+    citizen: Entity,
+    car_q: &Query<(Entity, &CarTrip)>,
+    lines: &mut Query<(Entity, &mut TransitLine)>,
+    rider_q: &mut Query<&mut TransitVehicle>,
+) {
+    for (car, _) in car_q.iter().filter(|(_, trip)| trip.citizen == citizen) {
+        commands.entity(car).despawn_recursive();
+    }
+    for (_, mut line) in lines.iter_mut() {
+        for waiting in &mut line.waiting {
+            waiting.retain(|p| p.citizen != Some(citizen));
+        }
+    }
+    for mut vehicle in rider_q.iter_mut() {
+        vehicle.passengers.retain(|p| p.citizen != Some(citizen));
+    }
+}
+
+/// Everything needed to start trips, bundled so the schedule system stays readable
+struct TripPlanner<'a, 'w, 's, 'q> {
+    clock: &'a SimClock,
+    graph: &'a RoadGraph,
+    params: &'a AssignmentParams,
+    flows: &'a Query<'w, 's, &'q EdgeFlow>,
+}
+
+impl TripPlanner<'_, '_, '_, '_> {
+    /// Index of the stop of a line closest to a place and the distance to it, None
+    /// when every stop is further than a walk
+    fn nearest_stop(&self, line: &TransitLine, pos: Vec2) -> Option<(usize, f32)> {
+        line.stops
+            .iter()
+            .enumerate()
+            .filter_map(|(i, stop)| self.graph.index.get(stop).map(|n| (i, self.graph.positions[*n].distance(pos))))
+            .filter(|(_, distance)| *distance < WALK_DISTANCE)
+            .min_by(|(_, x), (_, y)| x.total_cmp(y))
+    }
+
+    /// Picks a mode and gets the citizen moving from one road node to another
+    #[allow(clippy::too_many_arguments)]
+    fn start_trip(
+        &self,
+        commands: &mut Commands,
+        meshes: &mut Assets<Mesh>,
+        materials: &mut Assets<ColorMaterial>,
+        lines: &mut Query<(Entity, &mut TransitLine)>,
+        citizen_entity: Entity,
+        citizen: &mut Citizen,
+        from: Entity,
+        to: Entity,
+    ) {
+        let started = self.clock.minutes;
+        let (Some(&a), Some(&b)) = (self.graph.index.get(&from), self.graph.index.get(&to)) else {
+            // no road to take, teleporting is better than being stuck forever
+            citizen.trip = Some(Trip { mode: TravelMode::Walk, started, walk_until: Some(started) });
+            return;
+        };
+
+        let distance = self.graph.positions[a].distance(self.graph.positions[b]);
+        if a == b || distance < WALK_DISTANCE {
+            citizen.trip = Some(Trip {
+                mode: TravelMode::Walk,
+                started,
+                walk_until: Some(started + distance / WALK_SPEED),
+            });
+            return;
+        }
+
+        // the line needing the least walking to its stops at both ends
+        let ride = lines
+            .iter()
+            .filter(|(_, line)| !line.route.is_empty())
+            .filter_map(|(entity, line)| {
+                let (origin, to_stop) = self.nearest_stop(line, self.graph.positions[a])?;
+                let (destination, from_stop) = self.nearest_stop(line, self.graph.positions[b])?;
+                (origin != destination).then_some((entity, origin, destination, to_stop + from_stop))
+            })
+            .min_by(|x, y| x.3.total_cmp(&y.3));
+        if let Some((entity, origin, destination, _)) = ride
+            && let Ok((_, mut line)) = lines.get_mut(entity)
+        {
+            line.waiting[origin].push(Passenger { destination, citizen: Some(citizen_entity) });
+            citizen.trip = Some(Trip { mode: TravelMode::Transit, started, walk_until: None });
+            return;
+        }
+
+        let path = self.graph.shortest_path_with(a, b, |e| match self.flows.get(e.line) {
+            Ok(flow) => flow.travel_time,
+            Err(_) => e.length / self.params.free_speed,
+        });
+        let Some(path) = path else {
+            citizen.trip = Some(Trip { mode: TravelMode::Walk, started, walk_until: Some(started) });
+            return;
+        };
+
+        let route = path.iter().map(|i| self.graph.nodes[*i]).collect();
+        let car = spawn_vehicle(
+            commands,
+            meshes,
+            materials,
+            Vehicle::new(route, CAR_SPEED),
+            self.graph.positions[a],
+            CAR_COLOR,
+        );
+        commands.entity(car).insert((OnGameScreen, CityEntity, CarTrip { citizen: citizen_entity }));
+        citizen.trip = Some(Trip { mode: TravelMode::Car, started, walk_until: None });
+    }
+}
+
+/// Nearest shop to each home node, forgotten when the shops or the roads change
+#[derive(Default)]
+struct NearestShops {
+    /// Road nodes of the commercial lots the cache was built for
+    shops: Vec<Entity>,
+    by_home: HashMap<Entity, Option<Entity>>,
+}
+
+impl NearestShops {
+    fn nearest(&mut self, graph: &RoadGraph, home: Entity) -> Option<Entity> {
+        let shops = &self.shops;
+        *self.by_home.entry(home).or_insert_with(|| {
+            let home_pos = graph.index.get(&home).map(|i| graph.positions[*i]).unwrap_or_default();
+            shops
+                .iter()
+                .filter_map(|node| graph.index.get(node).map(|i| (*node, graph.positions[*i].distance_squared(home_pos))))
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(node, _)| node)
+        })
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn citizen_schedule_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    clock: Res<SimClock>,
+    graph: Res<RoadGraph>,
+    params: Res<AssignmentParams>,
+    flows: Query<&EdgeFlow>,
+    mut lines: Query<(Entity, &mut TransitLine)>,
+    mut rider_q: Query<&mut TransitVehicle>,
+    car_q: Query<(Entity, &CarTrip)>,
+    zone_q: Query<(Entity, &Zone)>,
+    mut citizen_q: Query<(Entity, &mut Citizen)>,
+    mut nearest_shops: Local<NearestShops>,
+) {
+    let planner = TripPlanner { clock: &clock, graph: &graph, params: &params, flows: &flows };
+    let hour = clock.hour();
+    let today = clock.day();
+    // abandoned lots have no homes or jobs left
+    let home_of = |lot: Entity| zone_q.get(lot).ok().filter(|(_, zone)| zone.homes() > 0).map(|(_, zone)| zone.node);
+    let work_of = |lot: Entity| zone_q.get(lot).ok().filter(|(_, zone)| zone.jobs() > 0).map(|(_, zone)| zone.node);
+    let shops: Vec<Entity> = zone_q
+        .iter()
+        .filter(|(_, zone)| zone.kind == ZoneKind::Commercial)
+        .map(|(_, zone)| zone.node)
+        .collect();
+    if graph.is_changed() || nearest_shops.shops != shops {
+        nearest_shops.shops = shops;
+        nearest_shops.by_home.clear();
+    }
+
+    for (entity, mut citizen) in &mut citizen_q {
+        let Some(home) = home_of(citizen.home) else {
+            // their home is gone, so are they
+            commands.entity(entity).despawn_recursive();
+            continue;
+        };
+        if citizen.workplace.is_some_and(|work| work_of(work).is_none()) {
+            citizen.workplace = None;
+        }
+
+        if let Some(trip) = citizen.trip {
+            let walked = trip.walk_until.is_some_and(|t| clock.minutes >= t);
+            // a car or a line can lose its way, nobody stays out there for good
+            let stuck = trip.walk_until.is_none() && clock.minutes - trip.started > TRIP_TIMEOUT;
+            if stuck {
+                abandon_ride(&mut commands, entity, &car_q, &mut lines, &mut rider_q);
+            }
+            if walked || stuck {
+                arrive(&mut citizen, &clock);
+            }
+            continue;
+        }
+
+        let schedule = citizen.schedule;
+        let awake = hour >= schedule.wake && hour < schedule.sleep;
+        // the nearest shop to home stands in for wherever they'd go
+        let shop = nearest_shops.nearest(&graph, home);
+
+        let mut go = |citizen: &mut Citizen, activity: Activity, from: Entity, to: Entity| {
+            citizen.activity = activity;
+            planner.start_trip(&mut commands, &mut meshes, &mut materials, &mut lines, entity, citizen, from, to);
+        };
+
+        match citizen.activity {
+            Activity::Sleep => {
+                if awake {
+                    citizen.activity = Activity::Home;
+                }
+            }
+            Activity::Home => {
+                let work = citizen.workplace.and_then(work_of);
+                if !awake {
+                    citizen.activity = Activity::Sleep;
+                } else if let Some(work) = work.filter(|_| {
+                    citizen.last_work_day != Some(today)
+                        && hour >= schedule.work_start - 0.75
+                        && hour < schedule.work_end
+                }) {
+                    citizen.last_work_day = Some(today);
+                    go(&mut citizen, Activity::Commute, home, work);
+                } else if let Some(shop) = shop.filter(|_| {
+                    schedule.shops && work.is_none() && citizen.last_shop_day != Some(today) && (10.0..11.0).contains(&hour)
+                }) {
+                    citizen.last_shop_day = Some(today);
+                    go(&mut citizen, Activity::Shop, home, shop);
+                }
+            }
+            Activity::Work => {
+                if hour >= schedule.work_end || hour < schedule.wake {
+                    let work = citizen.workplace.and_then(work_of).unwrap_or(home);
+                    match shop.filter(|_| schedule.shops && citizen.last_shop_day != Some(today)) {
+                        Some(shop) => {
+                            citizen.last_shop_day = Some(today);
+                            go(&mut citizen, Activity::Shop, work, shop);
+                        }
+                        None => go(&mut citizen, Activity::Return, work, home),
+                    }
+                }
+            }
+            Activity::Shop => {
+                if clock.minutes >= citizen.shop_until {
+                    let shop = shop.unwrap_or(home);
+                    go(&mut citizen, Activity::Return, shop, home);
+                }
+            }
+            // trips in progress are handled above, these only happen when a trip got lost
+            Activity::Commute => citizen.activity = Activity::Work,
+            Activity::Return => citizen.activity = Activity::Home,
+        }
+    }
+}
+
+// Health and happiness drift over a few hours towards what the neighbourhood offers:
+// services nearby (schools only matter to the kids), no smog and not too much noise
+fn citizen_happiness_system(
+    clock: Res<SimClock>,
+    graph: Res<RoadGraph>,
+    coverage: Res<ServiceCoverage>,
+    pollution: Res<Pollution>,
+    zone_q: Query<(&Zone, &Transform)>,
+    mut citizen_q: Query<&mut Citizen>,
+) {
+    let rate = (clock.delta / 180.0).min(1.0);
+    for mut citizen in &mut citizen_q {
+        let Ok((zone, transform)) = zone_q.get(citizen.home) else { continue };
+        let Some(home) = graph.index.get(&zone.node) else { continue };
+        let pos = transform.translation.truncate();
+
+        let healthy = 1.0 - 0.7 * pollution.air_at(pos);
+        citizen.health = citizen.health.lerp(healthy, rate);
+
+        let weights = if citizen.age < 18 {
+            [0.2, 0.2, 0.2, 0.4]
+        } else {
+            [0.3, 0.3, 0.4, 0.0]
+        };
+        let served: f32 = ServiceKind::ALL
+            .iter()
+            .zip(weights)
+            .map(|(kind, weight)| weight * coverage.coverage(*kind, *home))
+            .sum();
+        let target = (0.3 + 0.7 * served) * (0.6 + 0.4 * citizen.health) - 0.2 * pollution.noise_at(pos);
+        citizen.happiness = citizen.happiness.lerp(target, rate);
+    }
+}
+
+fn update_citizen_stats_system(citizen_q: Query<&Citizen>, mut stats: ResMut<CitizenStats>) {
+    let mut next = CitizenStats::default();
+    let mut commutes = (0.0, 0);
+    for citizen in &citizen_q {
+        next.population += 1;
+        next.average_happiness += citizen.happiness;
+        next.average_health += citizen.health;
+        if citizen.working_age() {
+            next.working_age += 1;
+            if citizen.workplace.is_some() {
+                next.employed += 1;
+            }
+        }
+        if let Some(minutes) = citizen.last_commute {
+            commutes.0 += minutes;
+            commutes.1 += 1;
+        }
+        if let Some(trip) = citizen.trip {
+            *next.trips_by_mode.entry(trip.mode).or_default() += 1;
+        }
+    }
+    if next.population > 0 {
+        next.average_happiness /= next.population as f32;
+        next.average_health /= next.population as f32;
+    }
+    if commutes.1 > 0 {
+        next.average_commute = commutes.0 / commutes.1 as f32;
+    }
+    *stats = next;
+}
+
+// Tag component for the city statistics text
+#[derive(Component)]
+struct CityStatsText;
+
+fn city_stats_panel_setup(mut commands: Commands) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(10.0),
+                left: Val::Px(10.0),
+                padding: UiRect::all(Val::Px(8.0)),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+            OnGameScreen,
+        ))
+        .with_child((
+            CityStatsText,
+            Text::new(""),
+            TextFont { font_size: 16.0, ..default() },
+            TextColor(TEXT_COLOR),
+        ));
+}
+
+fn update_city_stats_panel_system(
+    clock: Res<SimClock>,
+    stats: Res<CitizenStats>,
+    budget: Res<Budget>,
+    mut text_q: Query<&mut Text, With<CityStatsText>>,
+) {
+    let Ok(mut text) = text_q.get_single_mut() else { return };
+    let minutes = clock.time_of_day() as u32;
+    let travelling = |mode| stats.trips_by_mode.get(&mode).copied().unwrap_or(0);
+    text.0 = format!(
+        "Day {} {:02}:{:02}\nBalance ${:.0} ({:+.0}/h)\nPopulation {}\nHappiness {:.0}%\nHealth {:.0}%\nEmployment {:.0}%\nAverage commute {:.0} min\nTravelling: {} by car, {} by transit, {} on foot",
+        clock.day() + 1,
+        minutes / 60,
+        minutes % 60,
+        budget.balance,
+        budget.income - budget.expenses,
+        stats.population,
+        stats.average_happiness * 100.0,
+        stats.average_health * 100.0,
+        stats.employment_rate() * 100.0,
+        stats.average_commute,
+        travelling(TravelMode::Car),
+        travelling(TravelMode::Transit),
+        travelling(TravelMode::Walk),
+    );
+}
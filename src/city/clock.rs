@@ -0,0 +1,55 @@
+//! Simulation calendar, everything time based in the city reads `SimClock`
+//! instead of `Time` so the simulation can run faster than real time.
+
+use bevy::prelude::*;
+
+use crate::game::PlayState;
+
+pub const MINUTES_PER_DAY: f32 = 24.0 * 60.0;
+
+pub fn clock_plugin(app: &mut App) {
+    app
+        .init_resource::<SimClock>()
+        .add_systems(PreUpdate, advance_clock_system.run_if(in_state(PlayState::Play)));
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct SimClock {
+    /// Simulated minutes since the city was founded
+    pub minutes: f32,
+    /// Simulated minutes per real second
+    pub speed: f32,
+    /// Simulated minutes that passed this frame
+    pub delta: f32,
+}
+
+impl Default for SimClock {
+    fn default() -> Self {
+        Self {
+            // cities are founded at 6 in the morning
+            minutes: 6.0 * 60.0,
+            speed: 2.0,
+            delta: 0.0,
+        }
+    }
+}
+
+impl SimClock {
+    pub fn day(&self) -> u32 {
+        (self.minutes / MINUTES_PER_DAY) as u32
+    }
+
+    /// Minutes since midnight
+    pub fn time_of_day(&self) -> f32 {
+        self.minutes % MINUTES_PER_DAY
+    }
+
+    pub fn hour(&self) -> f32 {
+        self.time_of_day() / 60.0
+    }
+}
+
+fn advance_clock_system(time: Res<Time>, mut clock: ResMut<SimClock>) {
+    clock.delta = time.delta_secs() * clock.speed;
+    clock.minutes += clock.delta;
+}
@@ -0,0 +1,272 @@
+//! Origin-destination travel demand between zones.
+//!
+//! A production constrained gravity model spreads the commute of every home over
+//! the jobs, weighted by a decay of the network distance between them. That daily
+//! demand is scaled by a time of day profile into the `OdMatrix` of the current
+//! hour: home to work in the morning peak, back home in the evening peak.
+//! F6 exports the current matrix as CSV.
+
+use std::{collections::HashMap, fmt::Write, fs, path::Path};
+
+use bevy::prelude::*;
+
+use crate::{
+    city::{
+        clock::SimClock,
+        graph::{GraphSettled, RoadGraph},
+        zones::Zone,
+    },
+    game::PlayState,
+    settings::bindings::{action_just_pressed, InputAction},
+};
+
+pub const OD_CSV_PATH: &str = "saves/od_matrix.csv";
+
+pub fn demand_plugin(app: &mut App) {
+    app
+        .init_resource::<OdMatrix>()
+        .init_resource::<DailyDemand>()
+        .init_resource::<GravityModel>()
+        .init_resource::<DemandProfile>()
+        .add_systems(
+            Update,
+            (
+                update_daily_demand_system,
+                update_hourly_demand_system,
+                export_od_system.run_if(action_just_pressed(InputAction::ExportDemand)),
+            )
+                .chain()
+                .run_if(in_state(PlayState::Play)),
+        );
+}
+
+/// Trips per hour between zones, each zone loads its trips onto a road node
+#[derive(Resource, Clone, Debug, Default, PartialEq)]
+pub struct OdMatrix {
+    /// Road node (a `Draggable` entity) each zone is connected to
+    pub zones: Vec<Entity>,
+    /// Trips from zone i to zone j at `trips[i * zones.len() + j]`
+    pub trips: Vec<f32>,
+}
+
+impl OdMatrix {
+    pub fn new(zones: Vec<Entity>) -> Self {
+        let n = zones.len();
+        Self { zones, trips: vec![0.0; n * n] }
+    }
+
+    pub fn len(&self) -> usize {
+        self.zones.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.zones.is_empty()
+    }
+
+    pub fn get(&self, from: usize, to: usize) -> f32 {
+        self.trips[from * self.len() + to]
+    }
+
+    pub fn set(&mut self, from: usize, to: usize, trips: f32) {
+        let n = self.len();
+        self.trips[from * n + to] = trips;
+    }
+
+    pub fn total(&self) -> f32 {
+        self.trips.iter().sum()
+    }
+
+    /// Trips leaving zone i
+    pub fn productions(&self, from: usize) -> f32 {
+        (0..self.len()).map(|to| self.get(from, to)).sum()
+    }
+
+    /// Trips arriving at zone j
+    pub fn attractions(&self, to: usize) -> f32 {
+        (0..self.len()).map(|from| self.get(from, to)).sum()
+    }
+
+    /// One row per non zero pair, zones are described by their node position
+    pub fn to_csv(&self, graph: &RoadGraph) -> String {
+        let position = |zone: usize| {
+            graph.index.get(&self.zones[zone]).map(|i| graph.positions[*i]).unwrap_or(Vec2::NAN)
+        };
+
+        let mut csv = String::from("origin,origin_x,origin_y,destination,destination_x,destination_y,trips\n");
+        for from in 0..self.len() {
+            for to in 0..self.len() {
+                let trips = self.get(from, to);
+                if trips > 0.0 {
+                    let (a, b) = (position(from), position(to));
+                    let _ = writeln!(csv, "{},{:.1},{:.1},{},{:.1},{:.1},{:.3}", from, a.x, a.y, to, b.x, b.y, trips);
+                }
+            }
+        }
+        csv
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DistanceDecay {
+    /// exp(-beta * distance)
+    Exponential(f32),
+    /// distance^-gamma
+    Power(f32),
+}
+
+impl DistanceDecay {
+    pub fn weight(&self, distance: f32) -> f32 {
+        match *self {
+            DistanceDecay::Exponential(beta) => (-beta * distance).exp(),
+            DistanceDecay::Power(gamma) => distance.max(1.0).powf(-gamma),
+        }
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct GravityModel {
+    /// Commute trips per resident and day, each way
+    pub trip_rate: f32,
+    pub decay: DistanceDecay,
+}
+
+impl Default for GravityModel {
+    fn default() -> Self {
+        Self {
+            trip_rate: 0.6,
+            decay: DistanceDecay::Exponential(1.0 / 1500.0),
+        }
+    }
+}
+
+/// Homes and jobs loaded onto one road node
+#[derive(Debug, Clone, Copy)]
+pub struct ZoneDemand {
+    pub node: Entity,
+    pub homes: f32,
+    pub jobs: f32,
+}
+
+/// Daily home to work trips between the zones
+pub fn gravity_model(graph: &RoadGraph, zones: &[ZoneDemand], model: &GravityModel) -> OdMatrix {
+    let mut od = OdMatrix::new(zones.iter().map(|z| z.node).collect());
+
+    for (i, origin) in zones.iter().enumerate() {
+        if origin.homes <= 0.0 {
+            continue;
+        }
+        let Some(&source) = graph.index.get(&origin.node) else { continue };
+        let distances = graph.distances_from(source);
+
+        let weights: Vec<f32> = zones
+            .iter()
+            .map(|destination| match graph.index.get(&destination.node) {
+                Some(&j) if distances[j].is_finite() => destination.jobs * model.decay.weight(distances[j]),
+                _ => 0.0,
+            })
+            .collect();
+        let total: f32 = weights.iter().sum();
+        if total <= 0.0 {
+            continue;
+        }
+
+        let produced = origin.homes * model.trip_rate;
+        for (j, weight) in weights.iter().enumerate() {
+            od.set(i, j, produced * weight / total);
+        }
+    }
+    od
+}
+
+/// Share of the daily commute travelling in each hour of the day
+#[derive(Resource, Clone, Debug)]
+pub struct DemandProfile {
+    /// Home to work, peaking in the morning
+    pub outbound: [f32; 24],
+    /// Work to home, peaking in the evening
+    pub inbound: [f32; 24],
+}
+
+impl Default for DemandProfile {
+    fn default() -> Self {
+        let mut outbound = [0.01; 24];
+        let mut inbound = [0.01; 24];
+        outbound[6..10].copy_from_slice(&[0.12, 0.3, 0.25, 0.1]);
+        inbound[16..20].copy_from_slice(&[0.12, 0.3, 0.25, 0.1]);
+        Self { outbound, inbound }
+    }
+}
+
+impl DemandProfile {
+    /// Trips per hour for the given hour of the day
+    pub fn for_hour(&self, daily: &OdMatrix, hour: usize) -> OdMatrix {
+        let hour = hour % 24;
+        let mut od = OdMatrix::new(daily.zones.clone());
+        for i in 0..daily.len() {
+            for j in 0..daily.len() {
+                od.set(i, j, daily.get(i, j) * self.outbound[hour] + daily.get(j, i) * self.inbound[hour]);
+            }
+        }
+        od
+    }
+}
+
+/// Gravity model output before the time of day profile is applied
+#[derive(Resource, Clone, Debug, Default)]
+pub struct DailyDemand(pub OdMatrix);
+
+// Reruns the gravity model when the zones change, or the roads once no node is dragged
+fn update_daily_demand_system(
+    graph: Res<RoadGraph>,
+    mut graph_settled: GraphSettled,
+    model: Res<GravityModel>,
+    zone_q: Query<&Zone>,
+    changed_zones: Query<(), Changed<Zone>>,
+    mut removed_zones: RemovedComponents<Zone>,
+    mut daily: ResMut<DailyDemand>,
+) {
+    let removed = removed_zones.read().count() > 0;
+    if !graph_settled.changed() && !model.is_changed() && changed_zones.is_empty() && !removed {
+        return;
+    }
+
+    let mut by_node: HashMap<Entity, ZoneDemand> = HashMap::new();
+    for zone in &zone_q {
+        let demand = by_node.entry(zone.node).or_insert(ZoneDemand { node: zone.node, homes: 0.0, jobs: 0.0 });
+        demand.homes += zone.homes() as f32;
+        demand.jobs += zone.jobs() as f32;
+    }
+    let mut zones: Vec<ZoneDemand> = by_node.into_values().collect();
+    zones.sort_by_key(|z| z.node);
+
+    daily.0 = gravity_model(&graph, &zones, &model);
+}
+
+fn update_hourly_demand_system(
+    clock: Res<SimClock>,
+    daily: Res<DailyDemand>,
+    profile: Res<DemandProfile>,
+    mut od: ResMut<OdMatrix>,
+    mut last_hour: Local<Option<usize>>,
+) {
+    let hour = clock.hour() as usize;
+    if *last_hour == Some(hour) && !daily.is_changed() && !profile.is_changed() {
+        return;
+    }
+    *last_hour = Some(hour);
+    *od = profile.for_hour(&daily.0, hour);
+}
+
+fn export_od_system(od: Res<OdMatrix>, graph: Res<RoadGraph>) {
+    let path = Path::new(OD_CSV_PATH);
+    if let Some(parent) = path.parent()
+        && let Err(e) = fs::create_dir_all(parent)
+    {
+        error!("Failed to create {}: {}", parent.display(), e);
+        return;
+    }
+    match fs::write(path, od.to_csv(&graph)) {
+        Ok(_) => info!("OD matrix exported to {}", path.display()),
+        Err(e) => error!("Failed to write {}: {}", path.display(), e),
+    }
+}
@@ -0,0 +1,385 @@
+//! Procedural road network generation.
+//!
+//! The generator only produces data (a `CityPlan` of node positions and edges),
+//! `game_setup` turns the plan into entities with the usual spawn helpers.
+//! It owns its own `SimpleRng` seeded from `CityGenParams::seed`, so the same
+//! parameters always give back the same city.
+
+use std::collections::VecDeque;
+use std::f32::consts::{FRAC_PI_2, TAU};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    city::{geometry::segments_cross, intersections::NodeControlPlan, services::ServicePlan, transit::TransitLinePlan, zones::LotPlan},
+    rng::SimpleRng,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CityLayout {
+    Grid,
+    Organic,
+    Radial,
+}
+
+#[derive(Resource, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CityGenParams {
+    pub seed: u64,
+    pub layout: CityLayout,
+    /// Full width/height of the map, centered on the origin
+    pub map_size: Vec2,
+    /// 0.0 is a sparse village, 1.0 a dense downtown
+    pub density: f32,
+    /// Grayscale PNG used instead of generated terrain
+    pub heightmap: Option<String>,
+}
+
+impl Default for CityGenParams {
+    fn default() -> Self {
+        Self {
+            seed: 111,
+            layout: CityLayout::Organic,
+            map_size: Vec2::splat(3000.0),
+            density: 0.5,
+            heightmap: None,
+        }
+    }
+}
+
+/// Output of the generator, edges, lots and transit stops index into `nodes`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CityPlan {
+    pub nodes: Vec<Vec2>,
+    pub edges: Vec<(usize, usize)>,
+    #[serde(default)]
+    pub lots: Vec<LotPlan>,
+    #[serde(default)]
+    pub transit: Vec<TransitLinePlan>,
+    #[serde(default)]
+    pub services: Vec<ServicePlan>,
+    /// Controls picked or edited by the player, other nodes get the default for their degree
+    #[serde(default)]
+    pub controls: Vec<NodeControlPlan>,
+}
+
+impl CityPlan {
+    fn add_node(&mut self, pos: Vec2) -> usize {
+        self.nodes.push(pos);
+        self.nodes.len() - 1
+    }
+
+    fn add_edge(&mut self, a: usize, b: usize) {
+        if a != b && !self.has_edge(a, b) {
+            self.edges.push((a, b));
+        }
+    }
+
+    pub fn has_edge(&self, a: usize, b: usize) -> bool {
+        self.edges.iter().any(|&(x, y)| (x, y) == (a, b) || (x, y) == (b, a))
+    }
+
+    /// Drops every node left without edges
+    pub fn drop_isolated_nodes(&mut self) {
+        let connected = (0..self.nodes.len()).map(|i| self.degree(i) > 0).collect();
+        self.keep_nodes(connected);
+    }
+
+    /// Drops the nodes rejected by `keep` and every edge touching them
+    pub fn retain_nodes(&mut self, keep: impl Fn(Vec2) -> bool) {
+        let kept: Vec<bool> = self.nodes.iter().map(|pos| keep(*pos)).collect();
+        self.edges.retain(|&(a, b)| kept[a] && kept[b]);
+        self.keep_nodes(kept);
+    }
+
+    fn keep_nodes(&mut self, kept: Vec<bool>) {
+        let mut remap = vec![None; self.nodes.len()];
+        let mut nodes = vec![];
+        for (i, pos) in self.nodes.iter().enumerate() {
+            if kept[i] {
+                remap[i] = Some(nodes.len());
+                nodes.push(*pos);
+            }
+        }
+        self.nodes = nodes;
+        for (a, b) in &mut self.edges {
+            *a = remap[*a].unwrap();
+            *b = remap[*b].unwrap();
+        }
+        self.lots.retain(|lot| remap[lot.node].is_some());
+        for lot in &mut self.lots {
+            lot.node = remap[lot.node].unwrap();
+        }
+        self.transit.retain(|line| line.stops.iter().all(|stop| remap[*stop].is_some()));
+        for line in &mut self.transit {
+            for stop in &mut line.stops {
+                *stop = remap[*stop].unwrap();
+            }
+        }
+        self.services.retain(|service| remap[service.node].is_some());
+        for service in &mut self.services {
+            service.node = remap[service.node].unwrap();
+        }
+        self.controls.retain(|control| remap[control.node].is_some());
+        for control in &mut self.controls {
+            control.node = remap[control.node].unwrap();
+        }
+    }
+
+    pub fn degree(&self, node: usize) -> usize {
+        self.edges.iter().filter(|&&(a, b)| a == node || b == node).count()
+    }
+
+    fn nearest_node(&self, pos: Vec2, max_dist: f32) -> Option<usize> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (i, p.distance_squared(pos)))
+            .filter(|(_, d)| *d <= max_dist * max_dist)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(i, _)| i)
+    }
+
+    /// Whether a new segment a->b would cross an existing edge (touching at a shared node is fine)
+    fn crosses_existing(&self, a: usize, b: Vec2) -> bool {
+        let pa = self.nodes[a];
+        self.edges.iter().any(|&(x, y)| {
+            x != a && y != a && segments_cross(pa, b, self.nodes[x], self.nodes[y])
+        })
+    }
+
+    /// Drops the edges picked by `drop`, except the ones needed to keep connected what
+    /// was connected before. Kept edges are joined first, then every picked edge that
+    /// still links two separate parts comes back, all in one union-find pass.
+    pub fn drop_edges_keeping_connected(&mut self, mut drop: impl FnMut(Vec2, Vec2) -> bool) {
+        let picked: Vec<bool> = self.edges.iter().map(|&(a, b)| drop(self.nodes[a], self.nodes[b])).collect();
+
+        let mut parent: Vec<usize> = (0..self.nodes.len()).collect();
+        // merges the parts of a and b, false when they already were one part
+        let mut join = |a: usize, b: usize| {
+            let (ra, rb) = (find_root(&mut parent, a), find_root(&mut parent, b));
+            parent[ra] = rb;
+            ra != rb
+        };
+
+        for (i, &(a, b)) in self.edges.iter().enumerate() {
+            if !picked[i] {
+                join(a, b);
+            }
+        }
+        let keep: Vec<bool> = self
+            .edges
+            .iter()
+            .enumerate()
+            .map(|(i, &(a, b))| !picked[i] || join(a, b))
+            .collect();
+
+        let mut keep = keep.into_iter();
+        self.edges.retain(|_| keep.next().unwrap());
+    }
+}
+
+/// Union-find root with path halving
+fn find_root(parent: &mut [usize], mut n: usize) -> usize {
+    while parent[n] != n {
+        parent[n] = parent[parent[n]];
+        n = parent[n];
+    }
+    n
+}
+
+pub fn generate_city(params: &CityGenParams) -> CityPlan {
+    let mut rng = SimpleRng::new(params.seed);
+    let half = params.map_size / 2.0;
+    let density = params.density.clamp(0.0, 1.0);
+
+    match params.layout {
+        CityLayout::Grid => grid_city(&mut rng, half, density),
+        CityLayout::Organic => organic_city(&mut rng, half, density),
+        CityLayout::Radial => radial_city(&mut rng, half, density),
+    }
+}
+
+// distance between intersections, denser cities get smaller blocks
+fn block_size(density: f32) -> f32 {
+    400.0 - 250.0 * density
+}
+
+fn grid_city(rng: &mut SimpleRng, half: Vec2, density: f32) -> CityPlan {
+    let spacing = block_size(density);
+    let cols = (half.x * 2.0 / spacing) as usize + 1;
+    let rows = (half.y * 2.0 / spacing) as usize + 1;
+    let jitter = spacing * 0.15;
+
+    let mut plan = CityPlan::default();
+    for r in 0..rows {
+        for c in 0..cols {
+            let base = Vec2::new(-half.x + c as f32 * spacing, -half.y + r as f32 * spacing);
+            let offset = Vec2::new(rng.next_range(-1.0, 1.0), rng.next_range(-1.0, 1.0)) * jitter;
+            plan.add_node(base + offset);
+        }
+    }
+
+    for r in 0..rows {
+        for c in 0..cols {
+            let i = r * cols + c;
+            if c + 1 < cols {
+                plan.add_edge(i, i + 1);
+            }
+            if r + 1 < rows {
+                plan.add_edge(i, i + cols);
+            }
+        }
+    }
+
+    // sparse cities lose some streets, but never in a way that splits the network
+    let drop_chance = 0.35 * (1.0 - density);
+    plan.drop_edges_keeping_connected(|_, _| rng.next_scaled() < drop_chance);
+
+    plan
+}
+
+// grows streets out of the center like an L-system: every segment either continues,
+// branches off at a right angle, or snaps onto an intersection that is already close
+fn organic_city(rng: &mut SimpleRng, half: Vec2, density: f32) -> CityPlan {
+    let step = block_size(density);
+    let max_nodes = (half.x * half.y * 4.0 / (step * step) * 0.8) as usize;
+    let branch_chance = 0.25 + 0.4 * density;
+
+    let mut plan = CityPlan::default();
+    let center = plan.add_node(Vec2::ZERO);
+
+    let mut queue = VecDeque::new();
+    let arms = 3 + (density * 3.0) as usize;
+    let start = rng.next_range(0.0, TAU);
+    for i in 0..arms {
+        queue.push_back((center, start + TAU * i as f32 / arms as f32));
+    }
+
+    while let Some((from, heading)) = queue.pop_front() {
+        if plan.nodes.len() >= max_nodes {
+            break;
+        }
+
+        let heading = heading + rng.next_range(-0.35, 0.35);
+        let length = step * rng.next_range(0.8, 1.2);
+        let pos = plan.nodes[from] + Vec2::from_angle(heading) * length;
+
+        if pos.x.abs() > half.x || pos.y.abs() > half.y {
+            continue;
+        }
+
+        if let Some(near) = plan.nearest_node(pos, step * 0.5) {
+            if !plan.crosses_existing(from, plan.nodes[near]) {
+                plan.add_edge(from, near);
+            }
+            continue;
+        }
+        if plan.crosses_existing(from, pos) {
+            continue;
+        }
+
+        let node = plan.add_node(pos);
+        plan.add_edge(from, node);
+
+        queue.push_back((node, heading));
+        if rng.next_scaled() < branch_chance {
+            queue.push_back((node, heading + FRAC_PI_2));
+        }
+        if rng.next_scaled() < branch_chance {
+            queue.push_back((node, heading - FRAC_PI_2));
+        }
+    }
+
+    plan
+}
+
+// concentric ring roads joined by spokes to the previous ring
+fn radial_city(rng: &mut SimpleRng, half: Vec2, density: f32) -> CityPlan {
+    let spacing = block_size(density);
+    let rings = (half.min_element() / spacing) as usize;
+
+    let mut plan = CityPlan::default();
+    let center = plan.add_node(Vec2::ZERO);
+    let mut previous_ring = vec![center];
+
+    for ring in 1..=rings {
+        let radius = ring as f32 * spacing;
+        let count = ((TAU * radius / spacing) as usize).max(6);
+        let offset = rng.next_range(0.0, TAU);
+
+        let ring_nodes: Vec<usize> = (0..count)
+            .map(|i| {
+                let angle = offset + TAU * i as f32 / count as f32 + rng.next_range(-0.05, 0.05);
+                plan.add_node(Vec2::from_angle(angle) * radius)
+            })
+            .collect();
+
+        for i in 0..count {
+            plan.add_edge(ring_nodes[i], ring_nodes[(i + 1) % count]);
+        }
+
+        // every node gets a spoke in dense cities, sparse ones skip some
+        for (i, &node) in ring_nodes.iter().enumerate() {
+            if i % 2 == 1 && rng.next_scaled() > density {
+                continue;
+            }
+            let pos = plan.nodes[node];
+            if let Some(&inner) = previous_ring.iter().min_by(|&&a, &&b| {
+                plan.nodes[a].distance_squared(pos).total_cmp(&plan.nodes[b].distance_squared(pos))
+            }) {
+                plan.add_edge(node, inner);
+            }
+        }
+
+        previous_ring = ring_nodes;
+    }
+
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUTS: [CityLayout; 3] = [CityLayout::Grid, CityLayout::Organic, CityLayout::Radial];
+
+    fn params(layout: CityLayout) -> CityGenParams {
+        CityGenParams { layout, map_size: Vec2::splat(2000.0), ..default() }
+    }
+
+    fn connected_parts(plan: &CityPlan) -> usize {
+        let mut parent: Vec<usize> = (0..plan.nodes.len()).collect();
+        for &(a, b) in &plan.edges {
+            let (ra, rb) = (find_root(&mut parent, a), find_root(&mut parent, b));
+            parent[ra] = rb;
+        }
+        (0..plan.nodes.len()).filter(|&n| find_root(&mut parent, n) == n).count()
+    }
+
+    #[test]
+    fn same_seed_same_city() {
+        for layout in LAYOUTS {
+            let first = generate_city(&params(layout));
+            assert!(!first.edges.is_empty());
+            assert_eq!(first, generate_city(&params(layout)), "{layout:?}");
+        }
+    }
+
+    #[test]
+    fn dropping_edges_keeps_the_network_connected() {
+        for layout in LAYOUTS {
+            let mut plan = generate_city(&params(layout));
+            assert_eq!(connected_parts(&plan), 1, "{layout:?} generated in pieces");
+
+            let mut rng = SimpleRng::new(7);
+            plan.drop_edges_keeping_connected(|_, _| rng.next_scaled() < 0.5);
+            assert_eq!(connected_parts(&plan), 1, "{layout:?} split by random drops");
+
+            // dropping everything leaves a spanning tree
+            plan.drop_edges_keeping_connected(|_, _| true);
+            assert_eq!(connected_parts(&plan), 1, "{layout:?} split by dropping every edge");
+            assert_eq!(plan.edges.len(), plan.nodes.len() - 1);
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! Road graph built from the `Line` entities, used for routing.
+//!
+//! The graph is rebuilt whenever roads are added, removed or moved, systems that
+//! cache anything derived from it should rerun on `resource_changed::<RoadGraph>`,
+//! or through `GraphSettled` when the work is too heavy to redo on every frame of a
+//! node drag.
+
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+use crate::{
+    game::{DragTarget, Draggable, Line, PlayState},
+    notifications::{Notification, NotificationKind},
+};
+
+pub fn graph_plugin(app: &mut App) {
+    app
+        .init_resource::<RoadGraph>()
+        .add_systems(
+            Update,
+            detect_disconnected_districts_system
+                .run_if(resource_changed::<RoadGraph>.and(in_state(PlayState::Play))),
+        )
+        .add_systems(PostUpdate, rebuild_road_graph_system);
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GraphEdge {
+    pub to: usize,
+    /// The `Line` entity this edge comes from
+    pub line: Entity,
+    pub length: f32,
+}
+
+#[derive(Resource, Debug, Clone, Default)]
+pub struct RoadGraph {
+    pub nodes: Vec<Entity>,
+    pub positions: Vec<Vec2>,
+    pub index: HashMap<Entity, usize>,
+    pub adjacency: Vec<Vec<GraphEdge>>,
+}
+
+/// Whether the graph changed since the system using this last acted on it, held
+/// back while a node is being dragged so the work happens once after the drop
+#[derive(SystemParam)]
+pub struct GraphSettled<'w, 's> {
+    graph: Res<'w, RoadGraph>,
+    drag_target: Res<'w, DragTarget>,
+    pending: Local<'s, bool>,
+}
+
+impl GraphSettled<'_, '_> {
+    pub fn changed(&mut self) -> bool {
+        *self.pending |= self.graph.is_changed();
+        let settled = *self.pending && self.drag_target.0.is_none();
+        if settled {
+            *self.pending = false;
+        }
+        settled
+    }
+}
+
+// min-heap entry for dijkstra
+#[derive(PartialEq)]
+struct Visit(f32, usize);
+
+impl Eq for Visit {}
+
+impl Ord for Visit {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.total_cmp(&self.0)
+    }
+}
+
+impl PartialOrd for Visit {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl RoadGraph {
+    pub fn add_node(&mut self, entity: Entity, pos: Vec2) -> usize {
+        let i = self.nodes.len();
+        self.nodes.push(entity);
+        self.positions.push(pos);
+        self.adjacency.push(vec![]);
+        self.index.insert(entity, i);
+        i
+    }
+
+    /// Adds a two way edge, its length is taken from the node positions
+    pub fn add_edge(&mut self, a: usize, b: usize, line: Entity) {
+        let length = self.positions[a].distance(self.positions[b]);
+        self.adjacency[a].push(GraphEdge { to: b, line, length });
+        self.adjacency[b].push(GraphEdge { to: a, line, length });
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn degree(&self, node: usize) -> usize {
+        self.adjacency[node].len()
+    }
+
+    pub fn nearest_node(&self, pos: Vec2, max_dist: f32) -> Option<usize> {
+        self.positions
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (i, p.distance_squared(pos)))
+            .filter(|(_, d)| *d <= max_dist * max_dist)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(i, _)| i)
+    }
+
+    /// Dijkstra from `source` with a custom edge cost, returns the cost to reach
+    /// every node (infinite when unreachable) and the predecessor of each node along
+    /// with the `Line` taken from it, which matters when two roads join the same nodes
+    pub fn dijkstra_with(
+        &self,
+        source: usize,
+        edge_cost: impl Fn(&GraphEdge) -> f32,
+        max_cost: f32,
+    ) -> (Vec<f32>, Vec<Option<(usize, Entity)>>) {
+        let mut cost = vec![f32::INFINITY; self.len()];
+        let mut previous = vec![None; self.len()];
+        let mut heap = BinaryHeap::new();
+
+        cost[source] = 0.0;
+        heap.push(Visit(0.0, source));
+        while let Some(Visit(c, node)) = heap.pop() {
+            if c > cost[node] {
+                continue;
+            }
+            for edge in &self.adjacency[node] {
+                let next = c + edge_cost(edge);
+                if next < cost[edge.to] && next <= max_cost {
+                    cost[edge.to] = next;
+                    previous[edge.to] = Some((node, edge.line));
+                    heap.push(Visit(next, edge.to));
+                }
+            }
+        }
+        (cost, previous)
+    }
+
+    /// Network distance from `source` to every node
+    pub fn distances_from(&self, source: usize) -> Vec<f32> {
+        self.dijkstra_with(source, |e| e.length, f32::INFINITY).0
+    }
+
+    /// Shortest path by length as a list of node indices, both ends included
+    pub fn shortest_path(&self, from: usize, to: usize) -> Option<Vec<usize>> {
+        self.shortest_path_with(from, to, |e| e.length)
+    }
+
+    pub fn shortest_path_with(
+        &self,
+        from: usize,
+        to: usize,
+        edge_cost: impl Fn(&GraphEdge) -> f32,
+    ) -> Option<Vec<usize>> {
+        let (cost, previous) = self.dijkstra_with(from, edge_cost, f32::INFINITY);
+        if !cost[to].is_finite() {
+            return None;
+        }
+        let mut path = vec![to];
+        let mut node = to;
+        while let Some((p, _)) = previous[node] {
+            path.push(p);
+            node = p;
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    /// Groups of nodes connected to each other, largest first
+    pub fn components(&self) -> Vec<Vec<usize>> {
+        let mut seen = vec![false; self.len()];
+        let mut components = vec![];
+        for start in 0..self.len() {
+            if seen[start] {
+                continue;
+            }
+            seen[start] = true;
+            let mut component = vec![start];
+            let mut i = 0;
+            while i < component.len() {
+                for edge in &self.adjacency[component[i]] {
+                    if !seen[edge.to] {
+                        seen[edge.to] = true;
+                        component.push(edge.to);
+                    }
+                }
+                i += 1;
+            }
+            components.push(component);
+        }
+        components.sort_by_key(|c| std::cmp::Reverse(c.len()));
+        components
+    }
+
+    /// The edge joining two adjacent nodes
+    pub fn edge_between(&self, a: usize, b: usize) -> Option<&GraphEdge> {
+        self.adjacency[a].iter().find(|e| e.to == b)
+    }
+}
+
+// Tells the player when an edit splits the network, pointing at the part that got cut
+// off. Lone nodes don't count, they are just roads waiting to be built.
+fn detect_disconnected_districts_system(
+    graph: Res<RoadGraph>,
+    mut previous: Local<Option<usize>>,
+    mut notifications: EventWriter<Notification>,
+) {
+    let components = graph.components();
+    let districts: Vec<&Vec<usize>> = components.iter().filter(|c| c.len() > 1).collect();
+    let count = districts.len();
+    // the first graph is the generated city, only later edits are news
+    if previous.replace(count).is_some_and(|p| count > p)
+        && let Some(cut_off) = districts.last()
+    {
+        let center = cut_off.iter().map(|i| graph.positions[*i]).sum::<Vec2>() / cut_off.len() as f32;
+        notifications.send(Notification::at(
+            NotificationKind::DisconnectedDistrict { nodes: cut_off.len() },
+            center,
+        ));
+    }
+}
+
+fn rebuild_road_graph_system(
+    mut graph: ResMut<RoadGraph>,
+    node_q: Query<(Entity, &Transform), With<Draggable>>,
+    line_q: Query<(Entity, &Line)>,
+    changed_nodes: Query<(), (Changed<Transform>, With<Draggable>)>,
+    added_lines: Query<(), Added<Line>>,
+    mut removed_lines: RemovedComponents<Line>,
+    mut removed_nodes: RemovedComponents<Draggable>,
+) {
+    let removed = removed_lines.read().count() + removed_nodes.read().count() > 0;
+    if !removed && changed_nodes.is_empty() && added_lines.is_empty() {
+        return;
+    }
+
+    let mut rebuilt = RoadGraph::default();
+    for (entity, transform) in &node_q {
+        rebuilt.add_node(entity, transform.translation.truncate());
+    }
+    for (entity, line) in &line_q {
+        if let (Some(&a), Some(&b)) = (rebuilt.index.get(&line.from), rebuilt.index.get(&line.to)) {
+            rebuilt.add_edge(a, b, entity);
+        }
+    }
+    *graph = rebuilt;
+}
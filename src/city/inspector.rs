@@ -0,0 +1,468 @@
+//! Inspector for the selected intersection, road, lot, service or vehicle.
+//!
+//! Clicking something selects it, dragging a node does too. Vehicles are picked
+//! first as they drive over everything else. The panel shows what
+//! the city knows about it followed by every reflected component on it, so new
+//! components show up here as soon as they are registered. Fields holding a plain
+//! enum get a button cycling through its variants, roads can be recoloured, signal
+//! phases and cycle lengths retimed and anything can be given a name.
+
+use std::any::TypeId;
+
+use bevy::{
+    input::{
+        keyboard::{Key, KeyboardInput},
+        InputSystem,
+    },
+    prelude::*,
+    reflect::{DynamicEnum, DynamicVariant, GetPath, ReflectRef, TypeInfo, VariantInfo},
+};
+
+use crate::{
+    city::{
+        geometry::point_segment_distance,
+        graph::RoadGraph,
+        intersections::{IntersectionControl, SignalEdit},
+        land_value::{LandValue, LAND_PRICE},
+        roads::{RoadColor, RoadCost},
+        services::ServiceBuilding,
+        tools::select_tool_active,
+        vehicles::Vehicle,
+        zones::{Zone, LOT_SIZE},
+    },
+    common::StageSelect,
+    game::{Draggable, DragTarget, Line, OnGameScreen, PlayState, Selection, NODE_RADIUS},
+    menus::ui::{SettingButton, NORMAL_BUTTON, TEXT_COLOR},
+};
+
+/// Real seconds between two refreshes of the values shown
+const REFRESH_SECONDS: f32 = 0.5;
+/// Clicks closer than this to a road select it
+const ROAD_PICK_DISTANCE: f32 = 8.0;
+/// Clicks closer than this to a vehicle select it
+const VEHICLE_PICK_DISTANCE: f32 = 12.0;
+/// Simulated minutes one press adds to or takes from a green or a cycle
+const SIGNAL_STEP: f32 = 0.05;
+/// Colours roads cycle through
+const PALETTE: [Color; 6] = [
+    Color::srgb(0.2, 0.2, 0.2),
+    Color::srgb(0.55, 0.55, 0.6),
+    Color::srgb(0.85, 0.75, 0.3),
+    Color::srgb(0.8, 0.35, 0.25),
+    Color::srgb(0.3, 0.5, 0.85),
+    Color::srgb(0.35, 0.7, 0.4),
+];
+/// Only our own components are listed, bevy's would drown them
+const CRATE_PATH: &str = "city_simulation";
+
+pub fn inspector_plugin(app: &mut App) {
+    app
+        .init_resource::<NameEdit>()
+        .add_systems(OnEnter(StageSelect::Game), inspector_setup)
+        .add_systems(PreUpdate, edit_name_system.after(InputSystem).run_if(in_state(PlayState::Play)))
+        .add_systems(
+            Update,
+            (select_entity_system.run_if(select_tool_active), inspector_system, draw_selection_system)
+                .chain()
+                .run_if(in_state(PlayState::Play)),
+        );
+}
+
+/// Name being typed for an entity, keyboard shortcuts are off meanwhile
+#[derive(Resource, Debug, Default)]
+pub struct NameEdit(pub Option<(Entity, String)>);
+
+#[derive(Component, Debug, Clone)]
+enum InspectorButton {
+    /// Next variant of an enum component, or of one of its fields
+    Cycle { component: TypeId, field: Option<String> },
+    Recolor,
+    Rename,
+    Signal(SignalEdit),
+}
+
+// Tag component for the inspector panel
+#[derive(Component)]
+struct InspectorPanel;
+
+fn inspector_setup(mut commands: Commands) {
+    commands.spawn((
+        InspectorPanel,
+        OnGameScreen,
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(10.0),
+            bottom: Val::Px(240.0),
+            width: Val::Px(320.0),
+            flex_direction: FlexDirection::Column,
+            row_gap: Val::Px(4.0),
+            padding: UiRect::all(Val::Px(8.0)),
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.7)),
+        Visibility::Hidden,
+    ));
+}
+
+// Dragging selects the dragged node, a click picks a vehicle, a building or a road
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn select_entity_system(
+    buttons: Res<ButtonInput<MouseButton>>,
+    windows: Query<&Window>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    drag_target: Res<DragTarget>,
+    ui_q: Query<&Interaction>,
+    vehicle_q: Query<(Entity, &Transform), With<Vehicle>>,
+    building_q: Query<(Entity, &Transform), Or<(With<Zone>, With<ServiceBuilding>)>>,
+    line_q: Query<(Entity, &Line)>,
+    node_q: Query<&Transform, With<Draggable>>,
+    mut selection: ResMut<Selection>,
+) {
+    if let Some(target) = drag_target.0 {
+        selection.set_if_neq(Selection(Some(target)));
+        return;
+    }
+    // clicks on the panels are not for the map
+    if !buttons.just_pressed(MouseButton::Left) || ui_q.iter().any(|i| *i != Interaction::None) {
+        return;
+    }
+    let Ok((camera, cam_transform)) = camera_q.get_single() else { return };
+    let Ok(window) = windows.get_single() else { return };
+    let Some(cursor) = window.cursor_position() else { return };
+    let Ok(world_pos) = camera.viewport_to_world_2d(cam_transform, cursor) else { return };
+
+    let vehicle = vehicle_q
+        .iter()
+        .map(|(entity, transform)| (entity, transform.translation.truncate().distance(world_pos)))
+        .filter(|(_, distance)| *distance < VEHICLE_PICK_DISTANCE)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(entity, _)| entity);
+    let building = || {
+        building_q
+            .iter()
+            .find(|(_, transform)| (transform.translation.truncate() - world_pos).abs().max_element() < LOT_SIZE / 2.0)
+            .map(|(entity, _)| entity)
+    };
+    let road = || {
+        line_q
+            .iter()
+            .find(|(_, line)| {
+                let (Ok(a), Ok(b)) = (node_q.get(line.from), node_q.get(line.to)) else { return false };
+                point_segment_distance(world_pos, a.translation.truncate(), b.translation.truncate()) < ROAD_PICK_DISTANCE
+            })
+            .map(|(entity, _)| entity)
+    };
+    selection.set_if_neq(Selection(vehicle.or_else(building).or_else(road)));
+}
+
+// Typing goes to the name, Enter keeps it and Escape gives up
+fn edit_name_system(
+    mut commands: Commands,
+    mut edit: ResMut<NameEdit>,
+    mut events: EventReader<KeyboardInput>,
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+) {
+    let Some((entity, name)) = edit.0.as_mut() else {
+        events.clear();
+        return;
+    };
+    let entity = *entity;
+    let mut done = None;
+    for event in events.read().filter(|e| e.state.is_pressed()) {
+        match &event.logical_key {
+            Key::Enter => done = Some(true),
+            Key::Escape => done = Some(false),
+            Key::Backspace => {
+                name.pop();
+            }
+            Key::Space => name.push(' '),
+            Key::Character(c) => name.push_str(c),
+            _ => {}
+        }
+    }
+    // nothing else gets to see the keys while typing
+    keys.reset_all();
+
+    match done {
+        Some(true) => {
+            let name = name.trim().to_string();
+            if let Some(mut entity_commands) = commands.get_entity(entity) {
+                if name.is_empty() {
+                    entity_commands.remove::<Name>();
+                } else {
+                    entity_commands.insert(Name::new(name));
+                }
+            }
+            edit.0 = None;
+        }
+        Some(false) => edit.0 = None,
+        None => {}
+    }
+}
+
+// Applies the buttons pressed and rebuilds the panel when the selection changes,
+// after an edit and every now and then to keep the numbers fresh
+fn inspector_system(world: &mut World, mut last: Local<(Option<Entity>, f32)>) {
+    let mut selected = world.resource::<Selection>().0;
+    if selected.is_some_and(|e| world.get_entity(e).is_err()) {
+        world.resource_mut::<Selection>().0 = None;
+        selected = None;
+    }
+
+    let pressed: Vec<InspectorButton> = world
+        .query_filtered::<(&Interaction, &InspectorButton), Changed<Interaction>>()
+        .iter(world)
+        .filter(|(interaction, _)| **interaction == Interaction::Pressed)
+        .map(|(_, button)| button.clone())
+        .collect();
+    let edited = !pressed.is_empty();
+    if let Some(entity) = selected {
+        for button in pressed {
+            apply_edit(world, entity, button);
+        }
+    }
+
+    let now = world.resource::<Time<Real>>().elapsed_secs();
+    let editing = world.resource::<NameEdit>().0.is_some();
+    if last.0 == selected && now - last.1 < REFRESH_SECONDS && !edited && !editing {
+        return;
+    }
+    *last = (selected, now);
+
+    let Ok(panel) = world.query_filtered::<Entity, With<InspectorPanel>>().get_single(world) else { return };
+    let Some(entity) = selected else {
+        world.entity_mut(panel).insert(Visibility::Hidden);
+        return;
+    };
+    let (lines, buttons) = describe(world, entity);
+
+    let mut panel = world.entity_mut(panel);
+    panel.insert(Visibility::Inherited);
+    panel.despawn_descendants();
+    panel.with_children(|parent| {
+        parent.spawn((
+            Text::new(lines.join("\n")),
+            TextFont { font_size: 14.0, ..default() },
+            TextColor(TEXT_COLOR),
+        ));
+        for (label, button) in buttons {
+            parent
+                .spawn((
+                    Button,
+                    SettingButton,
+                    button,
+                    Node { padding: UiRect::axes(Val::Px(6.0), Val::Px(3.0)), ..default() },
+                    BackgroundColor(NORMAL_BUTTON),
+                ))
+                .with_child((
+                    Text::new(label),
+                    TextFont { font_size: 14.0, ..default() },
+                    TextColor(TEXT_COLOR),
+                ));
+        }
+    });
+}
+
+/// Text lines about an entity and the edit buttons it gets
+fn describe(world: &mut World, entity: Entity) -> (Vec<String>, Vec<(String, InspectorButton)>) {
+    let mut lines = vec![];
+    let mut buttons = vec![];
+
+    let name = world.get::<Name>(entity).map(|n| n.as_str().to_string());
+    match &world.resource::<NameEdit>().0 {
+        Some((editing, text)) if *editing == entity => lines.push(format!("Name: {}_", text)),
+        _ => lines.push(format!("Name: {}", name.as_deref().unwrap_or("-"))),
+    }
+    buttons.push(("Rename".to_string(), InspectorButton::Rename));
+
+    let graph = world.resource::<RoadGraph>();
+    if let Some(&node) = graph.index.get(&entity) {
+        lines.push(format!("Intersection, {} roads", graph.degree(node)));
+        for edge in &graph.adjacency[node] {
+            lines.push(format!("  road {} ({:.0} long)", edge.line, edge.length));
+        }
+        if let Some(control) = world.get::<IntersectionControl>(entity) {
+            lines.push(format!("Control: {}", control_name(control)));
+        }
+        if let Some(IntersectionControl::Signal(plan)) = world.get::<IntersectionControl>(entity) {
+            lines.push(format!("Cycle {:.2} min, {} timing", plan.cycle(), if plan.auto { "auto" } else { "fixed" }));
+            buttons.push(("Cycle -".to_string(), InspectorButton::Signal(SignalEdit::Cycle(-SIGNAL_STEP))));
+            buttons.push(("Cycle +".to_string(), InspectorButton::Signal(SignalEdit::Cycle(SIGNAL_STEP))));
+            buttons.push(("Toggle auto timing".to_string(), InspectorButton::Signal(SignalEdit::ToggleAuto)));
+            for (i, phase) in plan.phases.iter().enumerate() {
+                lines.push(format!("  phase {}: {:.2} min green, {} roads", i + 1, phase.green, phase.approaches.len()));
+                for (label, delta) in [("-", -SIGNAL_STEP), ("+", SIGNAL_STEP)] {
+                    buttons.push((
+                        format!("Phase {} green {}", i + 1, label),
+                        InspectorButton::Signal(SignalEdit::Green { phase: i, delta }),
+                    ));
+                }
+            }
+            for edge in &graph.adjacency[node] {
+                let phase = plan.phase_of(edge.line).map_or(0, |i| i + 1);
+                buttons.push((
+                    format!("Road {}: phase {}", edge.line, phase),
+                    InspectorButton::Signal(SignalEdit::MoveApproach(edge.line)),
+                ));
+            }
+        }
+    }
+    if let Some(line) = world.get::<Line>(entity) {
+        let ends = (world.get::<Transform>(line.from), world.get::<Transform>(line.to));
+        if let (Some(a), Some(b)) = ends {
+            lines.push(format!("Road, {:.0} long", a.translation.distance(b.translation)));
+        }
+        if let Some(cost) = world.get::<RoadCost>(entity) {
+            lines.push(format!(
+                "Grade {:.1}%, cost {:.0}{}",
+                cost.grade * 100.0,
+                cost.cost,
+                if cost.bridge { ", bridge" } else { "" }
+            ));
+        }
+        buttons.push(("Next colour".to_string(), InspectorButton::Recolor));
+    }
+    if let Some(vehicle) = world.get::<Vehicle>(entity) {
+        lines.push(format!(
+            "Vehicle, {:.0} per minute, {} nodes to go",
+            vehicle.speed,
+            vehicle.route.len().saturating_sub(vehicle.next)
+        ));
+    }
+    if let (Some(zone), Some(value)) = (world.get::<Zone>(entity), world.get::<LandValue>(entity)) {
+        lines.push(format!(
+            "{:?} lot, {}/{} occupants, worth ${:.0}",
+            zone.kind,
+            value.occupants,
+            zone.capacity(),
+            value.value * LAND_PRICE
+        ));
+    }
+
+    let registry = world.resource::<AppTypeRegistry>().clone();
+    let registry = registry.read();
+    let entity_ref = world.entity(entity);
+    for component_id in entity_ref.archetype().components() {
+        let Some(type_id) = world.components().get_info(component_id).and_then(|info| info.type_id()) else {
+            continue;
+        };
+        let Some(registration) = registry.get(type_id) else { continue };
+        let type_info = registration.type_info();
+        if !type_info.type_path().starts_with(CRATE_PATH) {
+            continue;
+        }
+        let Some(reflect_component) = registration.data::<ReflectComponent>() else { continue };
+        let Some(reflected) = reflect_component.reflect(entity_ref) else { continue };
+        let short = type_info.type_path_table().short_path();
+
+        lines.push(format!("{}:", short));
+        if let Some(variant) = unit_variant(reflected.as_partial_reflect()) {
+            lines.push(format!("  {}", variant));
+            buttons.push((format!("{}: {}", short, variant), InspectorButton::Cycle { component: type_id, field: None }));
+            continue;
+        }
+        match reflected.reflect_ref() {
+            ReflectRef::Struct(fields) => {
+                for i in 0..fields.field_len() {
+                    let (Some(name), Some(field)) = (fields.name_at(i), fields.field_at(i)) else { continue };
+                    lines.push(format!("  {}: {}", name, format_value(field)));
+                    if let Some(variant) = unit_variant(field) {
+                        buttons.push((
+                            format!("{} {}: {}", short, name, variant),
+                            InspectorButton::Cycle { component: type_id, field: Some(name.to_string()) },
+                        ));
+                    }
+                }
+            }
+            ReflectRef::TupleStruct(fields) => {
+                for field in fields.iter_fields() {
+                    lines.push(format!("  {}", format_value(field)));
+                }
+            }
+            _ => lines.push(format!("  {}", format_value(reflected.as_partial_reflect()))),
+        }
+    }
+    (lines, buttons)
+}
+
+fn control_name(control: &IntersectionControl) -> &'static str {
+    match control {
+        IntersectionControl::Uncontrolled => "none",
+        IntersectionControl::StopSign => "stop signs",
+        IntersectionControl::Signal(_) => "traffic signals",
+        IntersectionControl::Roundabout => "roundabout",
+    }
+}
+
+/// Current variant of an enum whose variants all carry no data
+fn unit_variant(value: &dyn PartialReflect) -> Option<&str> {
+    let Some(TypeInfo::Enum(info)) = value.get_represented_type_info() else { return None };
+    if !info.iter().all(|variant| matches!(variant, VariantInfo::Unit(_))) {
+        return None;
+    }
+    match value.reflect_ref() {
+        ReflectRef::Enum(value) => Some(value.variant_name()),
+        _ => None,
+    }
+}
+
+fn format_value(value: &dyn PartialReflect) -> String {
+    if let Some(x) = value.try_downcast_ref::<f32>() {
+        return format!("{:.2}", x);
+    }
+    if let Some(variant) = unit_variant(value) {
+        return variant.to_string();
+    }
+    format!("{:?}", value)
+}
+
+fn apply_edit(world: &mut World, entity: Entity, button: InspectorButton) {
+    match button {
+        InspectorButton::Rename => {
+            let name = world.get::<Name>(entity).map(|n| n.as_str().to_string()).unwrap_or_default();
+            world.resource_mut::<NameEdit>().0 = Some((entity, name));
+        }
+        InspectorButton::Recolor => {
+            let current = world.get::<RoadColor>(entity).and_then(|c| PALETTE.iter().position(|p| *p == c.0));
+            let next = current.map_or(0, |i| (i + 1) % PALETTE.len());
+            world.entity_mut(entity).insert(RoadColor(PALETTE[next]));
+        }
+        InspectorButton::Cycle { component, field } => cycle_variant(world, entity, component, field.as_deref()),
+        InspectorButton::Signal(edit) => {
+            if let Some(mut control) = world.get_mut::<IntersectionControl>(entity)
+                && let IntersectionControl::Signal(plan) = control.as_mut()
+            {
+                plan.apply_edit(&edit);
+            }
+        }
+    }
+}
+
+// Writes the next variant back through reflection, whatever the component is
+fn cycle_variant(world: &mut World, entity: Entity, component: TypeId, field: Option<&str>) {
+    let registry = world.resource::<AppTypeRegistry>().clone();
+    let registry = registry.read();
+    let Some(reflect_component) = registry.get_type_data::<ReflectComponent>(component) else { return };
+    let mut entity_mut = world.entity_mut(entity);
+    let Some(reflected) = reflect_component.reflect_mut(&mut entity_mut) else { return };
+    let reflected = reflected.into_inner();
+    let target = match field {
+        Some(path) => match reflected.reflect_path_mut(path) {
+            Ok(target) => target,
+            Err(_) => return,
+        },
+        None => reflected.as_partial_reflect_mut(),
+    };
+
+    let Some(TypeInfo::Enum(info)) = target.get_represented_type_info() else { return };
+    let ReflectRef::Enum(current) = target.reflect_ref() else { return };
+    let next = (current.variant_index() + 1) % info.variant_len();
+    let Some(variant) = info.variant_at(next) else { return };
+    target.apply(&DynamicEnum::new(variant.name(), DynamicVariant::Unit));
+}
+
+fn draw_selection_system(selection: Res<Selection>, transform_q: Query<&GlobalTransform>, mut gizmos: Gizmos) {
+    let Some(entity) = selection.0 else { return };
+    let Ok(transform) = transform_q.get(entity) else { return };
+    gizmos.circle_2d(transform.translation().truncate(), NODE_RADIUS * 2.5, Color::srgb(1.0, 0.9, 0.2));
+}
@@ -0,0 +1,484 @@
+//! Intersection control: stop signs, traffic signals and roundabouts.
+//!
+//! Every node gets a control when the road graph changes (signals on 4+ way
+//! junctions, stop signs on T junctions). Before vehicles move, the control of the
+//! node they are heading to decides whether they are `held` at the stop line.
+//! Hover a node and press C to cycle its control, Shift+C toggles automatic signal
+//! timing which splits the green time by the measured approach volumes. The phase
+//! plan and cycle length of a selected signal are edited from the inspector.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    city::{
+        clock::SimClock,
+        graph::RoadGraph,
+        vehicles::{move_vehicles_system, NodeReached, Vehicle, STOP_LINE},
+    },
+    game::{PlayState, NODE_RADIUS},
+    settings::bindings::{Actions, InputAction},
+};
+
+/// Vehicles closer than this to a node start obeying its control
+const APPROACH_DISTANCE: f32 = NODE_RADIUS * 5.0;
+/// Simulated minutes a vehicle has to stand at a stop sign
+const STOP_SIGN_WAIT: f32 = 0.05;
+/// Simulated minutes of all-red between two signal phases
+const ALL_RED: f32 = 0.05;
+const MIN_GREEN: f32 = 0.15;
+const DEFAULT_GREEN: f32 = 0.6;
+
+pub fn intersections_plugin(app: &mut App) {
+    app
+        .register_type::<IntersectionControl>()
+        .add_systems(
+            Update,
+            (
+                assign_default_controls_system.run_if(resource_changed::<RoadGraph>),
+                count_approach_volumes_system,
+                auto_signal_timing_system,
+                intersection_control_system,
+            )
+                .chain()
+                .before(move_vehicles_system)
+                .run_if(in_state(PlayState::Play)),
+        )
+        .add_systems(
+            Update,
+            (cycle_control_system, draw_controls_system).run_if(in_state(PlayState::Play)),
+        );
+}
+
+#[derive(Reflect, Clone, Debug, PartialEq)]
+pub struct SignalPhase {
+    /// `Line`s whose traffic gets green in this phase
+    pub approaches: Vec<Entity>,
+    /// Simulated minutes of green
+    pub green: f32,
+}
+
+#[derive(Reflect, Clone, Debug, PartialEq)]
+pub struct SignalPlan {
+    pub phases: Vec<SignalPhase>,
+    /// Retime the greens from the measured volumes every cycle
+    pub auto: bool,
+}
+
+impl SignalPlan {
+    /// Two phases splitting the approaches into a mostly east-west and a mostly north-south group
+    pub fn for_node(graph: &RoadGraph, node: usize) -> Self {
+        let mut east_west = vec![];
+        let mut north_south = vec![];
+        for edge in &graph.adjacency[node] {
+            let direction = graph.positions[edge.to] - graph.positions[node];
+            if direction.x.abs() >= direction.y.abs() {
+                east_west.push(edge.line);
+            } else {
+                north_south.push(edge.line);
+            }
+        }
+
+        let phases = [east_west, north_south]
+            .into_iter()
+            .filter(|approaches| !approaches.is_empty())
+            .map(|approaches| SignalPhase { approaches, green: DEFAULT_GREEN })
+            .collect();
+        Self { phases, auto: false }
+    }
+
+    pub fn cycle(&self) -> f32 {
+        self.phases.iter().map(|p| p.green + ALL_RED).sum()
+    }
+
+    /// The phase showing green at a given time, None during all-red
+    pub fn active_phase(&self, minutes: f32) -> Option<usize> {
+        let cycle = self.cycle();
+        if cycle <= 0.0 {
+            return None;
+        }
+        let mut t = minutes % cycle;
+        for (i, phase) in self.phases.iter().enumerate() {
+            if t < phase.green {
+                return Some(i);
+            }
+            t -= phase.green + ALL_RED;
+            if t < 0.0 {
+                return None;
+            }
+        }
+        None
+    }
+
+    pub fn is_green(&self, approach: Entity, minutes: f32) -> bool {
+        self.active_phase(minutes)
+            .is_some_and(|i| self.phases[i].approaches.contains(&approach))
+    }
+
+    /// Phase giving green to an approach
+    pub fn phase_of(&self, approach: Entity) -> Option<usize> {
+        self.phases.iter().position(|phase| phase.approaches.contains(&approach))
+    }
+
+    pub fn apply_edit(&mut self, edit: &SignalEdit) {
+        match *edit {
+            SignalEdit::Green { phase, delta } => {
+                if let Some(phase) = self.phases.get_mut(phase) {
+                    phase.green = (phase.green + delta).max(MIN_GREEN);
+                    self.auto = false;
+                }
+            }
+            SignalEdit::Cycle(delta) => {
+                let green_time: f32 = self.phases.iter().map(|p| p.green).sum();
+                if green_time <= 0.0 {
+                    return;
+                }
+                let min_green_time = MIN_GREEN * self.phases.len() as f32;
+                let scale = (green_time + delta).max(min_green_time) / green_time;
+                for phase in &mut self.phases {
+                    phase.green = (phase.green * scale).max(MIN_GREEN);
+                }
+            }
+            SignalEdit::MoveApproach(line) => {
+                let Some(from) = self.phase_of(line) else { return };
+                let alone = self.phases[from].approaches.len() == 1;
+                self.phases[from].approaches.retain(|a| *a != line);
+                match self.phases.get_mut(from + 1) {
+                    Some(next) => next.approaches.push(line),
+                    None if alone => self.phases[0].approaches.push(line),
+                    None => self.phases.push(SignalPhase { approaches: vec![line], green: DEFAULT_GREEN }),
+                }
+                self.phases.retain(|phase| !phase.approaches.is_empty());
+            }
+            SignalEdit::ToggleAuto => self.auto = !self.auto,
+        }
+    }
+
+    /// Whether the plan covers exactly the roads meeting at a node
+    fn matches_node(&self, graph: &RoadGraph, node: usize) -> bool {
+        let approaches: usize = self.phases.iter().map(|phase| phase.approaches.len()).sum();
+        approaches == graph.adjacency[node].len()
+            && graph.adjacency[node].iter().all(|edge| self.phase_of(edge.line).is_some())
+    }
+}
+
+/// A change to a signal plan made from the inspector
+#[derive(Clone, Debug, PartialEq)]
+pub enum SignalEdit {
+    /// Lengthens or shortens the green of one phase, the timing turns manual
+    Green { phase: usize, delta: f32 },
+    /// Stretches or squeezes every green so the cycle changes by about `delta`
+    Cycle(f32),
+    /// Moves an approach to the next phase, past the last one it gets a phase of its own
+    MoveApproach(Entity),
+    ToggleAuto,
+}
+
+#[derive(Component, Reflect, Clone, Debug, PartialEq)]
+#[reflect(Component)]
+pub enum IntersectionControl {
+    Uncontrolled,
+    StopSign,
+    Signal(SignalPlan),
+    Roundabout,
+}
+
+/// A signal phase as stored in a `CityPlan`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PhasePlan {
+    /// Indices of the approaches in `CityPlan::edges`
+    pub approaches: Vec<usize>,
+    pub green: f32,
+}
+
+/// An intersection control as stored in a `CityPlan`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ControlPlan {
+    Uncontrolled,
+    StopSign,
+    Signal { phases: Vec<PhasePlan>, auto: bool },
+    Roundabout,
+}
+
+impl ControlPlan {
+    /// None when a signal has an approach that isn't one of `edges`
+    pub fn from_control(control: &IntersectionControl, edges: &HashMap<Entity, usize>) -> Option<Self> {
+        Some(match control {
+            IntersectionControl::Uncontrolled => ControlPlan::Uncontrolled,
+            IntersectionControl::StopSign => ControlPlan::StopSign,
+            IntersectionControl::Roundabout => ControlPlan::Roundabout,
+            IntersectionControl::Signal(plan) => {
+                let phases = plan
+                    .phases
+                    .iter()
+                    .map(|phase| {
+                        let approaches: Option<Vec<usize>> = phase.approaches.iter().map(|line| edges.get(line).copied()).collect();
+                        approaches.map(|approaches| PhasePlan { approaches, green: phase.green })
+                    })
+                    .collect::<Option<_>>()?;
+                ControlPlan::Signal { phases, auto: plan.auto }
+            }
+        })
+    }
+
+    /// Control with its approaches looked up in the spawned `Line`s
+    pub fn to_control(&self, lines: &[Entity]) -> IntersectionControl {
+        match self {
+            ControlPlan::Uncontrolled => IntersectionControl::Uncontrolled,
+            ControlPlan::StopSign => IntersectionControl::StopSign,
+            ControlPlan::Roundabout => IntersectionControl::Roundabout,
+            ControlPlan::Signal { phases, auto } => IntersectionControl::Signal(SignalPlan {
+                phases: phases
+                    .iter()
+                    .map(|phase| SignalPhase {
+                        approaches: phase.approaches.iter().map(|edge| lines[*edge]).collect(),
+                        green: phase.green,
+                    })
+                    .collect(),
+                auto: *auto,
+            }),
+        }
+    }
+}
+
+/// The control of one node in a `CityPlan`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NodeControlPlan {
+    /// Index of the node in `CityPlan::nodes`
+    pub node: usize,
+    pub control: ControlPlan,
+}
+
+/// Vehicles that entered a node per approach `Line`, decayed every signal cycle
+#[derive(Component, Clone, Debug, Default)]
+pub struct ApproachCounts {
+    pub counts: HashMap<Entity, f32>,
+    pub last_retime: f32,
+}
+
+/// Time a vehicle has spent standing at a stop sign
+#[derive(Component, Clone, Copy, Debug)]
+struct StopSignWait {
+    node: Entity,
+    waited: f32,
+}
+
+fn default_control(graph: &RoadGraph, node: usize) -> IntersectionControl {
+    match graph.degree(node) {
+        0..=2 => IntersectionControl::Uncontrolled,
+        3 => IntersectionControl::StopSign,
+        _ => IntersectionControl::Signal(SignalPlan::for_node(graph, node)),
+    }
+}
+
+// New nodes get a control matching their degree. Signals only get their phases
+// redone when roads were added or removed around them, moving a node keeps the plan.
+fn assign_default_controls_system(
+    mut commands: Commands,
+    graph: Res<RoadGraph>,
+    mut control_q: Query<&mut IntersectionControl>,
+) {
+    for (i, node) in graph.nodes.iter().enumerate() {
+        match control_q.get_mut(*node) {
+            Ok(mut control) => {
+                if let IntersectionControl::Signal(plan) = control.as_mut()
+                    && !plan.matches_node(&graph, i)
+                {
+                    let auto = plan.auto;
+                    *plan = SignalPlan::for_node(&graph, i);
+                    plan.auto = auto;
+                }
+            }
+            Err(_) => {
+                commands.entity(*node).insert((default_control(&graph, i), ApproachCounts::default()));
+            }
+        }
+    }
+}
+
+/// The `Line` a vehicle uses to arrive at `route[index]`
+fn approach_line(graph: &RoadGraph, vehicle: &Vehicle, index: usize) -> Option<Entity> {
+    let previous = graph.index.get(vehicle.route.get(index.checked_sub(1)?)?)?;
+    let node = graph.index.get(vehicle.route.get(index)?)?;
+    graph.edge_between(*previous, *node).map(|e| e.line)
+}
+
+fn count_approach_volumes_system(
+    graph: Res<RoadGraph>,
+    mut reached: EventReader<NodeReached>,
+    vehicle_q: Query<&Vehicle>,
+    mut counts_q: Query<&mut ApproachCounts>,
+) {
+    for event in reached.read() {
+        let Ok(vehicle) = vehicle_q.get(event.vehicle) else { continue };
+        let Some(line) = approach_line(&graph, vehicle, event.index) else { continue };
+        if let Ok(mut counts) = counts_q.get_mut(event.node) {
+            *counts.counts.entry(line).or_default() += 1.0;
+        }
+    }
+}
+
+// Once per cycle, split the green time of automatic signals proportionally to the
+// busiest approach of each phase
+fn auto_signal_timing_system(
+    clock: Res<SimClock>,
+    mut signal_q: Query<(&mut IntersectionControl, &mut ApproachCounts)>,
+) {
+    for (mut control, mut counts) in &mut signal_q {
+        let IntersectionControl::Signal(plan) = control.as_mut() else { continue };
+        let cycle = plan.cycle();
+        if !plan.auto || clock.minutes - counts.last_retime < cycle {
+            continue;
+        }
+        counts.last_retime = clock.minutes;
+
+        let demand: Vec<f32> = plan
+            .phases
+            .iter()
+            .map(|phase| {
+                phase
+                    .approaches
+                    .iter()
+                    .map(|a| counts.counts.get(a).copied().unwrap_or(0.0))
+                    .fold(0.0, f32::max)
+            })
+            .collect();
+        let total: f32 = demand.iter().sum();
+
+        if total > 0.0 {
+            let green_time = cycle - ALL_RED * plan.phases.len() as f32;
+            let spare = green_time - MIN_GREEN * plan.phases.len() as f32;
+            for (phase, demand) in plan.phases.iter_mut().zip(demand) {
+                phase.green = MIN_GREEN + spare.max(0.0) * demand / total;
+            }
+        }
+
+        for count in counts.counts.values_mut() {
+            *count *= 0.5;
+        }
+    }
+}
+
+// Decides which vehicles have to wait at the stop line of the node they are heading to
+fn intersection_control_system(
+    mut commands: Commands,
+    clock: Res<SimClock>,
+    graph: Res<RoadGraph>,
+    control_q: Query<&IntersectionControl>,
+    mut vehicle_q: Query<(Entity, &mut Vehicle, &Transform, Option<&mut StopSignWait>)>,
+) {
+    let positions: Vec<(Entity, Vec2)> = vehicle_q
+        .iter()
+        .map(|(entity, _, transform, _)| (entity, transform.translation.truncate()))
+        .collect();
+
+    for (entity, mut vehicle, transform, wait) in &mut vehicle_q {
+        vehicle.held = false;
+        if vehicle.finished() || vehicle.next == 0 {
+            continue;
+        }
+        let node = vehicle.route[vehicle.next];
+        let (Ok(control), Some(&index)) = (control_q.get(node), graph.index.get(&node)) else { continue };
+
+        let node_pos = graph.positions[index];
+        let distance = transform.translation.truncate().distance(node_pos);
+        // already inside the junction, or still far away
+        if !(STOP_LINE - 1.0..=APPROACH_DISTANCE).contains(&distance) {
+            continue;
+        }
+
+        vehicle.held = match control {
+            IntersectionControl::Uncontrolled => false,
+            IntersectionControl::StopSign => {
+                let mut waited = match wait {
+                    Some(wait) if wait.node == node => wait.waited,
+                    _ => 0.0,
+                };
+                if distance <= STOP_LINE + 1.0 {
+                    waited += clock.delta;
+                }
+                commands.entity(entity).insert(StopSignWait { node, waited });
+                waited < STOP_SIGN_WAIT
+            }
+            IntersectionControl::Signal(plan) => match approach_line(&graph, &vehicle, vehicle.next) {
+                Some(line) => !plan.is_green(line, clock.minutes),
+                None => false,
+            },
+            // yield to anyone already circulating
+            IntersectionControl::Roundabout => positions
+                .iter()
+                .any(|(other, pos)| *other != entity && pos.distance(node_pos) < STOP_LINE - 1.0),
+        };
+    }
+}
+
+// C cycles the control of the hovered node, Shift+C toggles automatic signal timing
+fn cycle_control_system(
+    keys: Res<ButtonInput<KeyCode>>,
+    actions: Actions,
+    windows: Query<&Window>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    graph: Res<RoadGraph>,
+    mut control_q: Query<&mut IntersectionControl>,
+) {
+    if !actions.just_pressed(InputAction::CycleControl) {
+        return;
+    }
+    let Ok((camera, cam_transform)) = camera_q.get_single() else { return };
+    let Ok(window) = windows.get_single() else { return };
+    let Some(cursor) = window.cursor_position() else { return };
+    let Ok(world_pos) = camera.viewport_to_world_2d(cam_transform, cursor) else { return };
+
+    let Some(index) = graph.nearest_node(world_pos, NODE_RADIUS * 2.0) else { return };
+    let Ok(mut control) = control_q.get_mut(graph.nodes[index]) else { return };
+
+    let shift = keys.pressed(KeyCode::ShiftLeft) || keys.pressed(KeyCode::ShiftRight);
+    if shift {
+        if let IntersectionControl::Signal(plan) = control.as_mut() {
+            plan.auto = !plan.auto;
+            info!("Automatic signal timing {}", if plan.auto { "on" } else { "off" });
+        }
+        return;
+    }
+
+    *control = match *control {
+        IntersectionControl::Uncontrolled => IntersectionControl::StopSign,
+        IntersectionControl::StopSign => IntersectionControl::Signal(SignalPlan::for_node(&graph, index)),
+        IntersectionControl::Signal(_) => IntersectionControl::Roundabout,
+        IntersectionControl::Roundabout => IntersectionControl::Uncontrolled,
+    };
+}
+
+// Signal heads sit on each approach showing its current colour
+fn draw_controls_system(
+    clock: Res<SimClock>,
+    graph: Res<RoadGraph>,
+    control_q: Query<&IntersectionControl>,
+    mut gizmos: Gizmos,
+) {
+    let red = Color::srgb(0.9, 0.1, 0.1);
+    let green = Color::srgb(0.1, 0.9, 0.2);
+
+    for (i, node) in graph.nodes.iter().enumerate() {
+        let Ok(control) = control_q.get(*node) else { continue };
+        let pos = graph.positions[i];
+        match control {
+            IntersectionControl::Uncontrolled => {}
+            IntersectionControl::StopSign => {
+                gizmos.circle_2d(pos, NODE_RADIUS * 0.6, red).resolution(8);
+            }
+            IntersectionControl::Signal(plan) => {
+                for edge in &graph.adjacency[i] {
+                    let direction = (graph.positions[edge.to] - pos).normalize_or_zero();
+                    let color = if plan.is_green(edge.line, clock.minutes) { green } else { red };
+                    gizmos.circle_2d(pos + direction * (NODE_RADIUS + 6.0), 3.0, color);
+                }
+            }
+            IntersectionControl::Roundabout => {
+                gizmos.circle_2d(pos, NODE_RADIUS * 1.8, Color::WHITE);
+            }
+        }
+    }
+}
@@ -0,0 +1,310 @@
+//! Land value of the zoned lots.
+//!
+//! Every half hour of simulated time each lot is valued from how many jobs and shops
+//! are reachable by road, the services covering it, pollution and noise, the view
+//! from higher ground and how close it is to the water. Valuable lots that are
+//! full get denser, neglected ones lose a level until they are abandoned, and the
+//! residents and workers a smaller lot has no room for move away or lose their job.
+//! G shows the values on the map and hovering a lot shows what its value is made of.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    city::{
+        citizens::Citizen,
+        clock::SimClock,
+        graph::RoadGraph,
+        pollution::Pollution,
+        services::{ServiceCoverage, ServiceKind},
+        terrain::Terrain,
+        water::Water,
+        zones::{Zone, ZoneKind, LOT_SIZE},
+    },
+    common::StageSelect,
+    game::{OnGameScreen, PlayState},
+    menus::ui::TEXT_COLOR,
+    notifications::{Notification, NotificationKind},
+    settings::bindings::{Actions, InputAction},
+};
+
+/// Simulated minutes between two valuations
+const VALUATION_INTERVAL: f32 = 30.0;
+/// Network distance over which the pull of jobs and shops fades
+const ACCESS_DISTANCE: f32 = 1500.0;
+/// Distance within which water still adds to the value
+const WATERFRONT_DISTANCE: f32 = 300.0;
+pub const MAX_LEVEL: u32 = 3;
+/// Level 1 lots worth less than this are left empty
+const ABANDON_VALUE: f32 = 0.08;
+const ABANDONED_COLOR: Color = Color::srgba(0.35, 0.35, 0.35, 0.8);
+/// Price of a lot worth 1.0, for display
+pub const LAND_PRICE: f32 = 250_000.0;
+
+pub fn land_value_plugin(app: &mut App) {
+    app
+        .register_type::<Zone>()
+        .register_type::<LandValue>()
+        .init_resource::<ShowLandValue>()
+        .add_systems(OnEnter(StageSelect::Game), lot_panel_setup)
+        .add_systems(
+            Update,
+            (
+                land_value_system,
+                toggle_land_value_view_system,
+                color_lots_system,
+                update_lot_panel_system,
+            )
+                .chain()
+                .run_if(in_state(PlayState::Play)),
+        );
+}
+
+/// Value of a lot from 0 to 1 and the parts it is made of
+#[derive(Component, Reflect, Debug, Clone, Copy, Default)]
+#[reflect(Component)]
+pub struct LandValue {
+    pub value: f32,
+    /// Jobs and shops within reach, 0 to 1
+    pub accessibility: f32,
+    /// Average coverage of the services, 0 to 1
+    pub services: f32,
+    /// Share lost to pollution and noise, 0 to 1
+    pub pollution: f32,
+    /// Elevation, 0 to 1
+    pub terrain: f32,
+    /// Closeness to the water, 0 to 1
+    pub water: f32,
+    /// Residents or workers on the lot
+    pub occupants: u32,
+}
+
+impl LandValue {
+    fn compute(&mut self) {
+        let value = 0.15 + 0.35 * self.accessibility + 0.25 * self.services + 0.1 * self.terrain + 0.15 * self.water;
+        self.value = (value * (1.0 - 0.6 * self.pollution)).clamp(0.0, 1.0);
+    }
+}
+
+/// Occupants of each lot, homes for residents and workplaces for workers
+pub fn lot_occupants<'a>(citizens: impl Iterator<Item = &'a Citizen>) -> HashMap<Entity, u32> {
+    let mut occupants = HashMap::new();
+    for citizen in citizens {
+        *occupants.entry(citizen.home).or_default() += 1;
+        if let Some(work) = citizen.workplace {
+            *occupants.entry(work).or_default() += 1;
+        }
+    }
+    occupants
+}
+
+#[allow(clippy::too_many_arguments)]
+fn land_value_system(
+    mut commands: Commands,
+    clock: Res<SimClock>,
+    mut since_valuation: Local<Option<f32>>,
+    graph: Res<RoadGraph>,
+    coverage: Res<ServiceCoverage>,
+    pollution: Res<Pollution>,
+    terrain: Res<Terrain>,
+    water: Res<Water>,
+    mut citizen_q: Query<(Entity, &mut Citizen)>,
+    mut lot_q: Query<(Entity, &mut Zone, &mut Transform, Option<&mut LandValue>)>,
+    mut notifications: EventWriter<Notification>,
+) {
+    // the first valuation happens right away
+    let since = since_valuation.get_or_insert(VALUATION_INTERVAL);
+    *since += clock.delta;
+    if *since < VALUATION_INTERVAL {
+        return;
+    }
+    *since = 0.0;
+
+    // jobs within reach of each road node with a lot on it
+    let destinations: Vec<(usize, f32)> = lot_q
+        .iter()
+        .filter(|(_, zone, _, _)| zone.kind != ZoneKind::Residential)
+        .filter_map(|(_, zone, _, _)| graph.index.get(&zone.node).map(|i| (*i, zone.jobs() as f32)))
+        .collect();
+    let total_jobs: f32 = destinations.iter().map(|(_, jobs)| jobs).sum::<f32>().max(1.0);
+    let mut access: HashMap<usize, f32> = HashMap::new();
+    for (_, zone, _, _) in &lot_q {
+        let Some(&node) = graph.index.get(&zone.node) else { continue };
+        access.entry(node).or_insert_with(|| {
+            let (distances, _) = graph.dijkstra_with(node, |e| e.length, ACCESS_DISTANCE * 3.0);
+            let reachable: f32 = destinations
+                .iter()
+                .map(|(to, jobs)| jobs * (-distances[*to] / ACCESS_DISTANCE).exp())
+                .sum();
+            // a third of all the jobs nearby is as good as it gets
+            (reachable / total_jobs * 3.0).min(1.0)
+        });
+    }
+
+    let occupants = lot_occupants(citizen_q.iter().map(|(_, citizen)| citizen));
+    // lots that lost a level and how many occupants they still have room for
+    let mut shrunk: HashMap<Entity, u32> = HashMap::new();
+    for (entity, mut zone, mut transform, land_value) in &mut lot_q {
+        let pos = transform.translation.truncate();
+        let node = graph.index.get(&zone.node).copied();
+        let mut value = land_value.as_deref().copied().unwrap_or_default();
+        value.accessibility = node.and_then(|n| access.get(&n)).copied().unwrap_or(0.0);
+        value.services = node
+            .map(|n| ServiceKind::ALL.iter().map(|kind| coverage.coverage(*kind, n)).sum::<f32>() / 4.0)
+            .unwrap_or(0.0);
+        value.pollution = pollution.land_value_penalty(pos);
+        value.terrain = if terrain.relief > 0.0 { (terrain.height_at(pos) / terrain.relief).clamp(0.0, 1.0) } else { 0.0 };
+        value.water = (1.0 - water.distance(pos) / WATERFRONT_DISTANCE).max(0.0);
+        value.occupants = occupants.get(&entity).copied().unwrap_or(0);
+        value.compute();
+
+        // full and sought after lots build up, unloved ones fall back,
+        // abandoned lots are level 0 and get rebuilt once the area is worth it again
+        let full = value.occupants as f32 >= zone.capacity() as f32 * 0.9;
+        if full && zone.level < MAX_LEVEL && value.value >= 0.35 + 0.15 * zone.level as f32 {
+            zone.level += 1;
+        } else if zone.level > 1 && value.value < 0.15 * (zone.level - 1) as f32 {
+            zone.level -= 1;
+            shrunk.insert(entity, zone.capacity());
+        } else if zone.level == 1 && value.value < ABANDON_VALUE {
+            zone.level = 0;
+            shrunk.insert(entity, 0);
+            notifications.send(Notification::at(NotificationKind::BuildingAbandoned, pos));
+        }
+        value.occupants = value.occupants.min(zone.capacity());
+        let scale = 0.9 + 0.1 * zone.level as f32;
+        if transform.scale.x != scale {
+            transform.scale = Vec3::new(scale, scale, 1.0);
+        }
+
+        match land_value {
+            Some(mut land_value) => *land_value = value,
+            None => {
+                commands.entity(entity).insert(value);
+            }
+        }
+    }
+
+    // whoever no longer fits moves out of town, or loses the job
+    let mut kept: HashMap<Entity, u32> = HashMap::new();
+    for (entity, mut citizen) in &mut citizen_q {
+        if let Some(&room) = shrunk.get(&citizen.home) {
+            let count = kept.entry(citizen.home).or_default();
+            if *count >= room {
+                commands.entity(entity).despawn_recursive();
+                continue;
+            }
+            *count += 1;
+        }
+        if let Some(work) = citizen.workplace
+            && let Some(&room) = shrunk.get(&work)
+        {
+            let count = kept.entry(work).or_default();
+            if *count >= room {
+                citizen.workplace = None;
+            } else {
+                *count += 1;
+            }
+        }
+    }
+}
+
+/// Lots coloured by value instead of by zone
+#[derive(Resource, Debug, Clone, Copy, Default)]
+pub struct ShowLandValue(pub bool);
+
+fn toggle_land_value_view_system(actions: Actions, mut show: ResMut<ShowLandValue>) {
+    if actions.just_pressed(InputAction::LandValueView) {
+        show.0 = !show.0;
+    }
+}
+
+// From dark red (worthless) to bright green (prime land)
+#[allow(clippy::type_complexity)]
+fn color_lots_system(
+    show: Res<ShowLandValue>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    lot_q: Query<(&Zone, Option<&LandValue>, &MeshMaterial2d<ColorMaterial>)>,
+    changed: Query<(), Or<(Changed<LandValue>, Changed<Zone>)>>,
+) {
+    if !show.is_changed() && changed.is_empty() {
+        return;
+    }
+    for (zone, land_value, material) in &lot_q {
+        let Some(material) = materials.get_mut(material) else { continue };
+        material.color = match (show.0, land_value) {
+            (true, Some(land_value)) => {
+                let low = Vec3::new(0.5, 0.1, 0.1);
+                let high = Vec3::new(0.3, 0.95, 0.3);
+                let c = low.lerp(high, land_value.value);
+                Color::srgba(c.x, c.y, c.z, 0.9)
+            }
+            _ if zone.level == 0 => ABANDONED_COLOR,
+            _ => zone.kind.color(),
+        };
+    }
+}
+
+// Tag component for the hovered lot text
+#[derive(Component)]
+struct LotPanelText;
+
+fn lot_panel_setup(mut commands: Commands) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(10.0),
+                // left of the minimap
+                right: Val::Px(240.0),
+                padding: UiRect::all(Val::Px(8.0)),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+            OnGameScreen,
+        ))
+        .with_child((
+            LotPanelText,
+            Text::new(""),
+            TextFont { font_size: 16.0, ..default() },
+            TextColor(TEXT_COLOR),
+        ));
+}
+
+fn update_lot_panel_system(
+    windows: Query<&Window>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    lot_q: Query<(&Zone, &Transform, &LandValue)>,
+    mut text_q: Query<(&mut Text, &Parent), With<LotPanelText>>,
+    mut visibility_q: Query<&mut Visibility>,
+) {
+    let Ok((mut text, parent)) = text_q.get_single_mut() else { return };
+    let hovered = (|| {
+        let (camera, cam_transform) = camera_q.get_single().ok()?;
+        let cursor = windows.get_single().ok()?.cursor_position()?;
+        let world_pos = camera.viewport_to_world_2d(cam_transform, cursor).ok()?;
+        lot_q
+            .iter()
+            .find(|(_, transform, _)| (transform.translation.truncate() - world_pos).abs().max_element() < LOT_SIZE / 2.0)
+    })();
+
+    if let Ok(mut visibility) = visibility_q.get_mut(parent.get()) {
+        let wanted = if hovered.is_some() { Visibility::Inherited } else { Visibility::Hidden };
+        visibility.set_if_neq(wanted);
+    }
+    let Some((zone, _, value)) = hovered else { return };
+    text.0 = format!(
+        "{:?} lot, level {}\nOccupants {}/{}\nValue ${:.0}\n  access {:.0}%\n  services {:.0}%\n  pollution -{:.0}%\n  elevation {:.0}%\n  waterfront {:.0}%",
+        zone.kind,
+        zone.level,
+        value.occupants,
+        zone.capacity(),
+        value.value * LAND_PRICE,
+        value.accessibility * 100.0,
+        value.services * 100.0,
+        value.pollution * 100.0,
+        value.terrain * 100.0,
+        value.water * 100.0,
+    );
+}
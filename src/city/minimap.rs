@@ -0,0 +1,198 @@
+//! Minimap in the bottom right corner.
+//!
+//! Shows the roads, the intersections and the zoned lots of the whole city with the
+//! area the camera currently sees. Clicking or dragging on it moves the camera there.
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+    ui::RelativeCursorPosition,
+};
+
+use crate::{
+    city::{graph::RoadGraph, terrain::Terrain, zones::Zone},
+    common::StageSelect,
+    game::{OnGameScreen, PlayState},
+};
+
+/// Side of the minimap in pixels, the image has one texel per pixel
+const MINIMAP_SIZE: u32 = 220;
+/// Real seconds between two redraws, vehicles don't show so nothing moves faster
+const REDRAW_SECONDS: f32 = 1.0;
+const BACKGROUND: [u8; 4] = [20, 28, 22, 220];
+const ROAD: [u8; 4] = [200, 200, 200, 255];
+const NODE: [u8; 4] = [255, 255, 255, 255];
+
+pub fn minimap_plugin(app: &mut App) {
+    app
+        .add_systems(OnEnter(StageSelect::Game), minimap_setup)
+        .add_systems(
+            Update,
+            (minimap_navigation_system, draw_minimap_system, update_view_rect_system)
+                .chain()
+                .run_if(in_state(PlayState::Play)),
+        );
+}
+
+/// Area the minimap (and the camera) covers: the terrain, or the roads without one
+pub fn city_bounds(terrain: &Terrain, graph: &RoadGraph) -> Rect {
+    let roads = graph
+        .positions
+        .iter()
+        .fold(None, |rect: Option<Rect>, p| Some(rect.map_or(Rect::from_center_size(*p, Vec2::ZERO), |r| r.union_point(*p))));
+    match (terrain.heights.is_empty(), roads) {
+        (false, Some(roads)) => terrain.bounds().union(roads),
+        (false, None) => terrain.bounds(),
+        (true, Some(roads)) => roads,
+        (true, None) => Rect::from_center_size(Vec2::ZERO, Vec2::splat(1000.0)),
+    }
+}
+
+#[derive(Component)]
+struct Minimap;
+
+// Outline of what the camera sees
+#[derive(Component)]
+struct MinimapViewRect;
+
+fn minimap_setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let image = Image::new_fill(
+        Extent3d { width: MINIMAP_SIZE, height: MINIMAP_SIZE, depth_or_array_layers: 1 },
+        TextureDimension::D2,
+        &BACKGROUND,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD,
+    );
+    commands
+        .spawn((
+            Minimap,
+            OnGameScreen,
+            ImageNode::new(images.add(image)),
+            Node {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(10.0),
+                right: Val::Px(10.0),
+                width: Val::Px(MINIMAP_SIZE as f32),
+                height: Val::Px(MINIMAP_SIZE as f32),
+                overflow: Overflow::clip(),
+                ..default()
+            },
+            Interaction::default(),
+            RelativeCursorPosition::default(),
+        ))
+        .with_child((
+            MinimapViewRect,
+            Node {
+                position_type: PositionType::Absolute,
+                border: UiRect::all(Val::Px(1.0)),
+                ..default()
+            },
+            BorderColor(Color::WHITE),
+        ));
+}
+
+// Redraws the whole city into the minimap image
+fn draw_minimap_system(
+    time: Res<Time<Real>>,
+    mut last_redraw: Local<f32>,
+    mut images: ResMut<Assets<Image>>,
+    terrain: Res<Terrain>,
+    graph: Res<RoadGraph>,
+    zone_q: Query<(&Zone, &Transform)>,
+    minimap_q: Query<&ImageNode, With<Minimap>>,
+) {
+    if time.elapsed_secs() - *last_redraw < REDRAW_SECONDS && !graph.is_changed() {
+        return;
+    }
+    *last_redraw = time.elapsed_secs();
+    let Ok(node) = minimap_q.get_single() else { return };
+    let Some(image) = images.get_mut(&node.image) else { return };
+
+    let bounds = city_bounds(&terrain, &graph);
+    let size = MINIMAP_SIZE as i32;
+    let to_pixel = |pos: Vec2| {
+        let t = (pos - bounds.min) / bounds.size();
+        IVec2::new((t.x * size as f32) as i32, ((1.0 - t.y) * size as f32) as i32)
+    };
+    let data = &mut image.data;
+    let mut plot = |p: IVec2, color: [u8; 4]| {
+        if p.x >= 0 && p.y >= 0 && p.x < size && p.y < size {
+            let i = (p.y * size + p.x) as usize * 4;
+            data[i..i + 4].copy_from_slice(&color);
+        }
+    };
+
+    for y in 0..size {
+        for x in 0..size {
+            plot(IVec2::new(x, y), BACKGROUND);
+        }
+    }
+    for (zone, transform) in &zone_q {
+        let color = zone.kind.color().to_srgba().to_u8_array();
+        let p = to_pixel(transform.translation.truncate());
+        for offset in [IVec2::ZERO, IVec2::X, IVec2::Y, IVec2::ONE] {
+            plot(p + offset, color);
+        }
+    }
+    for (a, edges) in graph.adjacency.iter().enumerate() {
+        for edge in edges.iter().filter(|e| e.to > a) {
+            // walk the line one pixel at a time
+            let (from, to) = (to_pixel(graph.positions[a]), to_pixel(graph.positions[edge.to]));
+            let steps = (to - from).abs().max_element().max(1);
+            for i in 0..=steps {
+                let p = from.as_vec2().lerp(to.as_vec2(), i as f32 / steps as f32).round().as_ivec2();
+                plot(p, ROAD);
+            }
+        }
+    }
+    for pos in &graph.positions {
+        plot(to_pixel(*pos), NODE);
+    }
+}
+
+fn update_view_rect_system(
+    terrain: Res<Terrain>,
+    graph: Res<RoadGraph>,
+    windows: Query<&Window>,
+    camera_q: Query<&Transform, With<Camera2d>>,
+    mut rect_q: Query<&mut Node, With<MinimapViewRect>>,
+) {
+    let (Ok(window), Ok(camera), Ok(mut node)) = (windows.get_single(), camera_q.get_single(), rect_q.get_single_mut()) else {
+        return;
+    };
+    let bounds = city_bounds(&terrain, &graph);
+    let view = Rect::from_center_size(camera.translation.truncate(), window.size() * camera.scale.truncate());
+    let scale = MINIMAP_SIZE as f32 / bounds.size();
+
+    node.left = Val::Px((view.min.x - bounds.min.x) * scale.x);
+    node.top = Val::Px((bounds.max.y - view.max.y) * scale.y);
+    node.width = Val::Px(view.width() * scale.x);
+    node.height = Val::Px(view.height() * scale.y);
+}
+
+// Holding the mouse down on the minimap keeps the camera centered under the cursor
+fn minimap_navigation_system(
+    terrain: Res<Terrain>,
+    graph: Res<RoadGraph>,
+    minimap_q: Query<(&Interaction, &RelativeCursorPosition), With<Minimap>>,
+    mut camera_q: Query<&mut Transform, With<Camera2d>>,
+) {
+    let Ok((interaction, cursor)) = minimap_q.get_single() else { return };
+    if *interaction != Interaction::Pressed {
+        return;
+    }
+    let Some(normalized) = cursor.normalized else { return };
+    let bounds = city_bounds(&terrain, &graph);
+    let normalized = normalized.clamp(Vec2::ZERO, Vec2::ONE);
+    let target = Vec2::new(
+        bounds.min.x + normalized.x * bounds.width(),
+        bounds.max.y - normalized.y * bounds.height(),
+    );
+    for mut transform in &mut camera_q {
+        transform.translation.x = target.x;
+        transform.translation.y = target.y;
+    }
+}
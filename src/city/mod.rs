@@ -0,0 +1,25 @@
+pub mod ambience;
+pub mod assignment;
+pub mod budget;
+pub mod camera;
+pub mod citizens;
+pub mod clock;
+pub mod demand;
+pub mod generator;
+pub mod geometry;
+pub mod graph;
+pub mod inspector;
+pub mod intersections;
+pub mod land_value;
+pub mod minimap;
+pub mod pollution;
+pub mod render_quality;
+pub mod roads;
+pub mod save;
+pub mod services;
+pub mod terrain;
+pub mod tools;
+pub mod transit;
+pub mod vehicles;
+pub mod water;
+pub mod zones;
@@ -0,0 +1,380 @@
+//! Air pollution and noise.
+//!
+//! Both are scalar grids laid over the terrain. Industry and busy roads emit into
+//! them, every tick the values spread to the neighbouring cells and decay, and air
+//! pollution also drifts with the wind. P cycles the overlay (drawn finer at higher
+//! display quality), F7 exports both grids as PNG.
+
+use std::{fs, path::Path};
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+
+use crate::{
+    city::{
+        assignment::{AssignmentParams, EdgeFlow},
+        clock::SimClock,
+        terrain::Terrain,
+        zones::{Zone, ZoneKind},
+    },
+    game::{CityEntity, Line, OnGameScreen, PlayState},
+    settings::{
+        bindings::{action_just_pressed, Actions, InputAction},
+        quality::EffectiveQuality,
+    },
+};
+
+pub const AIR_PNG_PATH: &str = "saves/air_pollution.png";
+pub const NOISE_PNG_PATH: &str = "saves/noise.png";
+const CELL_SIZE: f32 = 50.0;
+/// Simulated minutes between two diffusion steps
+const TICK: f32 = 2.0;
+/// Share of the difference with the neighbours evened out per tick
+const DIFFUSION: f32 = 0.2;
+const AIR_DECAY: f32 = 0.02;
+/// Noise does not linger, it is mostly what is being emitted right now
+const NOISE_DECAY: f32 = 0.3;
+/// World units per simulated minute
+const WIND_SPEED: f32 = 15.0;
+
+pub fn pollution_plugin(app: &mut App) {
+    app
+        .init_resource::<Pollution>()
+        .init_resource::<PollutionOverlay>()
+        .add_systems(
+            Update,
+            (
+                resize_pollution_system.run_if(resource_changed::<Terrain>),
+                pollution_tick_system,
+                toggle_pollution_overlay_system,
+                update_pollution_overlay_system,
+                export_pollution_system.run_if(action_just_pressed(InputAction::ExportPollution)),
+            )
+                .chain()
+                .run_if(in_state(PlayState::Play)),
+        );
+}
+
+/// Values on a regular grid, row major starting from the bottom row like `Terrain`
+#[derive(Clone, Debug, Default)]
+pub struct ScalarGrid {
+    pub width: usize,
+    pub height: usize,
+    pub cell_size: f32,
+    /// World position of the bottom left cell center
+    pub origin: Vec2,
+    pub values: Vec<f32>,
+}
+
+impl ScalarGrid {
+    pub fn new(origin: Vec2, size: Vec2, cell_size: f32) -> Self {
+        let width = (size.x / cell_size).ceil() as usize + 1;
+        let height = (size.y / cell_size).ceil() as usize + 1;
+        Self { width, height, cell_size, origin, values: vec![0.0; width * height] }
+    }
+
+    pub fn size(&self) -> Vec2 {
+        Vec2::new(self.width as f32, self.height as f32) * self.cell_size
+    }
+
+    /// Cell containing a position, None outside the grid
+    pub fn cell_at(&self, pos: Vec2) -> Option<usize> {
+        let local = ((pos - self.origin) / self.cell_size).round();
+        let inside = local.x >= 0.0 && local.y >= 0.0 && (local.x as usize) < self.width && (local.y as usize) < self.height;
+        inside.then(|| local.y as usize * self.width + local.x as usize)
+    }
+
+    fn get(&self, x: usize, y: usize) -> f32 {
+        self.values[y.min(self.height - 1) * self.width + x.min(self.width - 1)]
+    }
+
+    /// Bilinear value at a world position, 0.0 outside the grid
+    pub fn sample(&self, pos: Vec2) -> f32 {
+        if self.values.is_empty() {
+            return 0.0;
+        }
+        let local = (pos - self.origin) / self.cell_size;
+        let max = Vec2::new((self.width - 1) as f32, (self.height - 1) as f32);
+        if local.x < 0.0 || local.y < 0.0 || local.x > max.x || local.y > max.y {
+            return 0.0;
+        }
+        let (x0, y0) = (local.x as usize, local.y as usize);
+        let (tx, ty) = (local.x.fract(), local.y.fract());
+        let bottom = self.get(x0, y0).lerp(self.get(x0 + 1, y0), tx);
+        let top = self.get(x0, y0 + 1).lerp(self.get(x0 + 1, y0 + 1), tx);
+        bottom.lerp(top, ty)
+    }
+
+    pub fn add(&mut self, pos: Vec2, amount: f32) {
+        if let Some(cell) = self.cell_at(pos) {
+            self.values[cell] += amount;
+        }
+    }
+
+    /// Adds `amount` per cell along a segment
+    pub fn add_segment(&mut self, a: Vec2, b: Vec2, amount: f32) {
+        let steps = (a.distance(b) / self.cell_size).ceil().max(1.0) as usize;
+        for i in 0..=steps {
+            self.add(a.lerp(b, i as f32 / steps as f32), amount);
+        }
+    }
+
+    /// One step of spreading towards the four neighbours, carried by `drift` (in world
+    /// units) and losing `decay` of the value
+    pub fn step(&mut self, diffusion: f32, decay: f32, drift: Vec2) {
+        if self.values.is_empty() {
+            return;
+        }
+        let mut next = vec![0.0; self.values.len()];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let pos = self.origin + Vec2::new(x as f32, y as f32) * self.cell_size;
+                // semi lagrangian: whatever was upwind is here now
+                let here = if drift == Vec2::ZERO { self.get(x, y) } else { self.sample(pos - drift) };
+                let neighbours = self.get(x + 1, y)
+                    + self.get(x.saturating_sub(1), y)
+                    + self.get(x, y + 1)
+                    + self.get(x, y.saturating_sub(1));
+                let spread = here + diffusion * (neighbours / 4.0 - self.get(x, y));
+                next[y * self.width + x] = (spread * (1.0 - decay)).max(0.0);
+            }
+        }
+        self.values = next;
+    }
+
+    /// Transparent where clean, through yellow to red from `scale` on
+    fn pixel(value: f32, scale: f32) -> [u8; 4] {
+        let t = (value / scale).clamp(0.0, 1.0);
+        let color = Vec3::new(1.0, 0.9, 0.2).lerp(Vec3::new(0.85, 0.1, 0.05), t);
+        [
+            (color.x * 255.0) as u8,
+            (color.y * 255.0) as u8,
+            (color.z * 255.0) as u8,
+            (t.sqrt() * 200.0) as u8,
+        ]
+    }
+
+    /// One pixel per cell, top row first
+    pub fn to_rgba(&self, scale: f32) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.width * self.height * 4);
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                data.extend_from_slice(&Self::pixel(self.get(x, y), scale));
+            }
+        }
+        data
+    }
+
+    /// `upsample` pixels per cell side, interpolated between the cells, top row first
+    pub fn to_rgba_upsampled(&self, scale: f32, upsample: usize) -> Vec<u8> {
+        if upsample <= 1 {
+            return self.to_rgba(scale);
+        }
+        let (width, height) = (self.width * upsample, self.height * upsample);
+        let max = Vec2::new((self.width - 1) as f32, (self.height - 1) as f32);
+        let mut data = Vec::with_capacity(width * height * 4);
+        for y in (0..height).rev() {
+            for x in 0..width {
+                let local = ((Vec2::new(x as f32, y as f32) + 0.5) / upsample as f32 - 0.5).clamp(Vec2::ZERO, max);
+                let value = self.sample(self.origin + local * self.cell_size);
+                data.extend_from_slice(&Self::pixel(value, scale));
+            }
+        }
+        data
+    }
+
+    pub fn to_image(&self, scale: f32, upsample: usize) -> Image {
+        Image::new(
+            Extent3d {
+                width: (self.width * upsample.max(1)) as u32,
+                height: (self.height * upsample.max(1)) as u32,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            self.to_rgba_upsampled(scale, upsample),
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::RENDER_WORLD,
+        )
+    }
+
+    pub fn save_png(&self, path: &Path, scale: f32) -> Result<(), String> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        image::save_buffer(
+            path,
+            &self.to_rgba(scale),
+            self.width as u32,
+            self.height as u32,
+            image::ExtendedColorType::Rgba8,
+        )
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+}
+
+#[derive(Resource, Clone, Debug, Default)]
+pub struct Pollution {
+    pub air: ScalarGrid,
+    pub noise: ScalarGrid,
+    /// World units per simulated minute the air moves
+    pub wind: Vec2,
+}
+
+impl Pollution {
+    /// Air pollution considered fully unhealthy
+    pub const AIR_SCALE: f32 = 4.0;
+    /// Noise considered unbearable
+    pub const NOISE_SCALE: f32 = 2.0;
+
+    pub fn air_at(&self, pos: Vec2) -> f32 {
+        (self.air.sample(pos) / Self::AIR_SCALE).min(1.0)
+    }
+
+    pub fn noise_at(&self, pos: Vec2) -> f32 {
+        (self.noise.sample(pos) / Self::NOISE_SCALE).min(1.0)
+    }
+
+    /// Share of the land value lost to pollution and noise, from 0 to 1
+    pub fn land_value_penalty(&self, pos: Vec2) -> f32 {
+        (0.5 * self.air_at(pos) + 0.3 * self.noise_at(pos)).min(1.0)
+    }
+}
+
+// The grids cover the terrain, a new terrain starts with clean air
+fn resize_pollution_system(terrain: Res<Terrain>, mut pollution: ResMut<Pollution>) {
+    if terrain.heights.is_empty() {
+        return;
+    }
+    pollution.air = ScalarGrid::new(terrain.origin, terrain.size(), CELL_SIZE);
+    pollution.noise = ScalarGrid::new(terrain.origin, terrain.size(), CELL_SIZE);
+}
+
+fn pollution_tick_system(
+    clock: Res<SimClock>,
+    params: Res<AssignmentParams>,
+    mut pollution: ResMut<Pollution>,
+    mut since_tick: Local<f32>,
+    zone_q: Query<(&Zone, &Transform)>,
+    line_q: Query<(&Line, &EdgeFlow)>,
+    node_q: Query<&Transform>,
+) {
+    *since_tick += clock.delta;
+    if *since_tick < TICK {
+        return;
+    }
+    *since_tick -= TICK;
+
+    // the wind slowly turns over the day
+    let angle = 0.8 + (clock.minutes / 600.0).sin() * 1.2;
+    pollution.wind = Vec2::from_angle(angle) * WIND_SPEED;
+
+    for (zone, transform) in &zone_q {
+        let pos = transform.translation.truncate();
+        let level = zone.level as f32;
+        match zone.kind {
+            ZoneKind::Industrial => {
+                pollution.air.add(pos, 0.25 * level);
+                pollution.noise.add(pos, 0.3 * level);
+            }
+            ZoneKind::Commercial => pollution.noise.add(pos, 0.05 * level),
+            ZoneKind::Residential => {}
+        }
+    }
+
+    for (line, flow) in &line_q {
+        let busy = flow.volume / params.capacity;
+        if busy < 0.1 {
+            continue;
+        }
+        let (Ok(a), Ok(b)) = (node_q.get(line.from), node_q.get(line.to)) else { continue };
+        let (a, b) = (a.translation.truncate(), b.translation.truncate());
+        pollution.noise.add_segment(a, b, 0.15 * busy);
+        pollution.air.add_segment(a, b, 0.02 * busy);
+    }
+
+    let drift = pollution.wind * TICK;
+    pollution.air.step(DIFFUSION, AIR_DECAY, drift);
+    pollution.noise.step(DIFFUSION, NOISE_DECAY, Vec2::ZERO);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PollutionKind {
+    Air,
+    Noise,
+}
+
+/// Grid drawn over the city
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct PollutionOverlay(pub Option<PollutionKind>);
+
+// Tag component for the overlay sprite
+#[derive(Component)]
+struct PollutionOverlaySprite;
+
+fn toggle_pollution_overlay_system(actions: Actions, mut overlay: ResMut<PollutionOverlay>) {
+    if !actions.just_pressed(InputAction::PollutionOverlay) {
+        return;
+    }
+    overlay.0 = match overlay.0 {
+        None => Some(PollutionKind::Air),
+        Some(PollutionKind::Air) => Some(PollutionKind::Noise),
+        Some(PollutionKind::Noise) => None,
+    };
+}
+
+// Keeps the overlay sprite in sync with the grid, redrawn each tick while shown
+fn update_pollution_overlay_system(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    pollution: Res<Pollution>,
+    overlay: Res<PollutionOverlay>,
+    quality: Res<EffectiveQuality>,
+    sprite_q: Query<Entity, With<PollutionOverlaySprite>>,
+) {
+    if !overlay.is_changed() && !(overlay.0.is_some() && (pollution.is_changed() || quality.is_changed())) {
+        return;
+    }
+    for entity in &sprite_q {
+        commands.entity(entity).despawn_recursive();
+    }
+    let (grid, scale) = match overlay.0 {
+        Some(PollutionKind::Air) => (&pollution.air, Pollution::AIR_SCALE),
+        Some(PollutionKind::Noise) => (&pollution.noise, Pollution::NOISE_SCALE),
+        None => return,
+    };
+    if grid.values.is_empty() {
+        return;
+    }
+
+    let size = grid.size();
+    // cells are centered on their sample, the image starts half a cell earlier
+    let center = grid.origin - Vec2::splat(grid.cell_size / 2.0) + size / 2.0;
+    commands.spawn((
+        OnGameScreen,
+        CityEntity,
+        PollutionOverlaySprite,
+        Sprite {
+            image: images.add(grid.to_image(scale, quality.overlay_upsample())),
+            custom_size: Some(size),
+            ..default()
+        },
+        Transform::from_translation(center.extend(45.0)),
+    ));
+}
+
+fn export_pollution_system(pollution: Res<Pollution>) {
+    for (grid, scale, path) in [
+        (&pollution.air, Pollution::AIR_SCALE, AIR_PNG_PATH),
+        (&pollution.noise, Pollution::NOISE_SCALE, NOISE_PNG_PATH),
+    ] {
+        match grid.save_png(Path::new(path), scale) {
+            Ok(_) => info!("Pollution exported to {}", path),
+            Err(e) => error!("{}", e),
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! The city's side of `DisplayQuality`, drawn at the `EffectiveQuality`.
+//!
+//! Intersection circles are rebuilt with the quality's segment count, buildings get
+//! drop shadows when enabled and only the vehicles nearest the camera are drawn up
+//! to the quality's cap. Everything follows quality changes while playing.
+
+use bevy::prelude::*;
+
+use crate::{
+    city::{vehicles::Vehicle, zones::{Zone, LOT_SIZE}},
+    game::{Draggable, PlayState},
+    settings::quality::EffectiveQuality,
+};
+
+/// Seconds between picking which vehicles to draw
+const AGENT_CULL_INTERVAL: f32 = 0.25;
+const SHADOW_OFFSET: Vec2 = Vec2::new(6.0, -6.0);
+const SHADOW_COLOR: Color = Color::srgba(0.0, 0.0, 0.0, 0.35);
+
+pub fn render_quality_plugin(app: &mut App) {
+    app.add_systems(
+        Update,
+        (circle_quality_system, lot_shadow_system, agent_cap_system).run_if(in_state(PlayState::Play)),
+    );
+}
+
+// Tag component for the shadow under a lot, a child of the lot
+#[derive(Component)]
+struct LotShadow;
+
+// Tag component for lots that have their shadow
+#[derive(Component)]
+struct Shadowed;
+
+// Rebuilds circles when they appear or the segment count changes
+fn circle_quality_system(
+    quality: Res<EffectiveQuality>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut circle_q: Query<(Ref<Draggable>, &mut Mesh2d)>,
+) {
+    let segments = quality.circle_segments();
+    for (draggable, mut mesh) in &mut circle_q {
+        let Draggable::Circle(radius) = *draggable else { continue };
+        if quality.is_changed() || draggable.is_added() {
+            mesh.0 = meshes.add(Circle::new(radius).mesh().resolution(segments));
+        }
+    }
+}
+
+// Adds shadows under lots that lack one, or takes them all away
+#[allow(clippy::too_many_arguments)]
+fn lot_shadow_system(
+    mut commands: Commands,
+    quality: Res<EffectiveQuality>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    lot_q: Query<Entity, (With<Zone>, Without<Shadowed>)>,
+    shadowed_q: Query<Entity, With<Shadowed>>,
+    shadow_q: Query<Entity, With<LotShadow>>,
+    mut handles: Local<Option<(Handle<Mesh>, Handle<ColorMaterial>)>>,
+) {
+    if !quality.shadows() {
+        for entity in &shadow_q {
+            commands.entity(entity).despawn_recursive();
+        }
+        for lot in &shadowed_q {
+            commands.entity(lot).remove::<Shadowed>();
+        }
+        return;
+    }
+
+    let (mesh, material) = handles
+        .get_or_insert_with(|| {
+            (
+                meshes.add(Rectangle::new(LOT_SIZE * 0.8, LOT_SIZE * 0.8)),
+                materials.add(SHADOW_COLOR),
+            )
+        })
+        .clone();
+    for lot in &lot_q {
+        let shadow = commands
+            .spawn((
+                LotShadow,
+                Mesh2d(mesh.clone()),
+                MeshMaterial2d(material.clone()),
+                // Just below the lot itself
+                Transform::from_translation(SHADOW_OFFSET.extend(-0.5)),
+            ))
+            .id();
+        commands.entity(lot).insert(Shadowed).add_child(shadow);
+    }
+}
+
+// Draws the vehicles nearest to the view center, up to the quality's cap
+fn agent_cap_system(
+    time: Res<Time>,
+    quality: Res<EffectiveQuality>,
+    camera_q: Query<&Transform, With<Camera2d>>,
+    mut vehicle_q: Query<(&Transform, &mut Visibility), With<Vehicle>>,
+    mut since_cull: Local<f32>,
+) {
+    *since_cull += time.delta_secs();
+    if *since_cull < AGENT_CULL_INTERVAL && !quality.is_changed() {
+        return;
+    }
+    *since_cull = 0.0;
+
+    let Ok(camera) = camera_q.get_single() else { return };
+    let center = camera.translation.truncate();
+    let cap = quality.agent_cap();
+
+    let mut vehicles: Vec<_> = vehicle_q
+        .iter_mut()
+        .map(|(transform, visibility)| (transform.translation.truncate().distance_squared(center), visibility))
+        .collect();
+    if vehicles.len() > cap {
+        vehicles.sort_by(|a, b| a.0.total_cmp(&b.0));
+    }
+    for (index, (_, mut visibility)) in vehicles.into_iter().enumerate() {
+        visibility.set_if_neq(if index < cap { Visibility::Inherited } else { Visibility::Hidden });
+    }
+}
@@ -0,0 +1,147 @@
+//! Road construction costs, driven by the terrain slope and by water crossings,
+//! and the look of each road: its type and an optional custom colour.
+
+use bevy::prelude::*;
+
+use crate::{
+    city::{terrain::Terrain, water::Water},
+    game::{Draggable, Line},
+};
+
+pub fn roads_plugin(app: &mut App) {
+    app
+        .init_resource::<ConstructionRules>()
+        .register_type::<RoadType>()
+        .register_type::<RoadColor>()
+        .add_systems(Update, update_road_costs_system);
+}
+
+/// How much roads cost to build and how steep they are allowed to be
+#[derive(Resource, Clone, Debug)]
+pub struct ConstructionRules {
+    pub cost_per_unit: f32,
+    /// Extra cost per unit of grade, a 10% grade with 20.0 triples the price
+    pub slope_cost: f32,
+    pub max_grade: f32,
+    /// Cost multiplier for roads that have to bridge water
+    pub bridge_cost: f32,
+}
+
+impl Default for ConstructionRules {
+    fn default() -> Self {
+        Self {
+            cost_per_unit: 1.0,
+            slope_cost: 20.0,
+            max_grade: 0.12,
+            bridge_cost: 4.0,
+        }
+    }
+}
+
+impl ConstructionRules {
+    pub fn road_cost(&self, terrain: &Terrain, water: &Water, a: Vec2, b: Vec2) -> RoadCost {
+        let length = a.distance(b);
+        let grade = terrain.max_grade(a, b);
+        let bridge = water.crosses(a, b);
+        let multiplier = if bridge { self.bridge_cost } else { 1.0 };
+        RoadCost {
+            length,
+            grade,
+            cost: length * self.cost_per_unit * (1.0 + self.slope_cost * grade) * multiplier,
+            buildable: grade <= self.max_grade,
+            bridge,
+        }
+    }
+}
+
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct RoadCost {
+    pub length: f32,
+    pub grade: f32,
+    pub cost: f32,
+    pub buildable: bool,
+    pub bridge: bool,
+}
+
+#[derive(Component, Reflect, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[reflect(Component)]
+pub enum RoadType {
+    #[default]
+    Street,
+    Avenue,
+    Highway,
+}
+
+impl RoadType {
+    /// Drawn width in world units
+    pub fn width(&self) -> f32 {
+        match self {
+            RoadType::Street => 4.0,
+            RoadType::Avenue => 7.0,
+            RoadType::Highway => 10.0,
+        }
+    }
+}
+
+/// Colour picked for a road instead of the default one, too steep roads still show red
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq)]
+#[reflect(Component)]
+pub struct RoadColor(pub Color);
+
+// Marker for roads crossing water
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Bridge;
+
+pub const ROAD_COLOR: Color = Color::BLACK;
+pub const BRIDGE_COLOR: Color = Color::srgb(0.55, 0.42, 0.28);
+pub const TOO_STEEP_COLOR: Color = Color::srgb(0.8, 0.1, 0.1);
+
+fn road_color(cost: &RoadCost) -> Color {
+    match (cost.buildable, cost.bridge) {
+        (false, _) => TOO_STEEP_COLOR,
+        (true, true) => BRIDGE_COLOR,
+        (true, false) => ROAD_COLOR,
+    }
+}
+
+// Recomputes the cost of new roads and roads whose ends moved, steep ones turn red
+// and the ones crossing water become bridges
+#[allow(clippy::type_complexity)]
+fn update_road_costs_system(
+    mut commands: Commands,
+    terrain: Res<Terrain>,
+    water: Res<Water>,
+    rules: Res<ConstructionRules>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    line_q: Query<(Entity, &Line, Option<&RoadCost>, Option<Ref<RoadColor>>, &MeshMaterial2d<ColorMaterial>)>,
+    node_q: Query<Ref<Transform>, With<Draggable>>,
+) {
+    let world_changed = terrain.is_changed() || water.is_changed() || rules.is_changed();
+
+    for (entity, line, old_cost, custom, material) in &line_q {
+        let Ok(from) = node_q.get(line.from) else { continue };
+        let Ok(to) = node_q.get(line.to) else { continue };
+        let recolored = custom.as_ref().is_some_and(|c| c.is_changed());
+        if old_cost.is_some() && !world_changed && !from.is_changed() && !to.is_changed() && !recolored {
+            continue;
+        }
+
+        let cost = rules.road_cost(&terrain, &water, from.translation.truncate(), to.translation.truncate());
+        if (recolored || old_cost.map(road_color) != Some(road_color(&cost)))
+            && let Some(material) = materials.get_mut(&material.0)
+        {
+            material.color = match custom {
+                Some(custom) if cost.buildable => custom.0,
+                _ => road_color(&cost),
+            };
+        }
+
+        let mut line_commands = commands.entity(entity);
+        line_commands.insert(cost);
+        if cost.bridge {
+            line_commands.insert(Bridge);
+        } else {
+            line_commands.remove::<Bridge>();
+        }
+    }
+}
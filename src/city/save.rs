@@ -0,0 +1,286 @@
+//! Saving and loading the whole city to a json file.
+//!
+//! F5 saves and F9 loads, loading replaces every `CityEntity` with the ones
+//! described by the save. Transit lines, service buildings and intersection
+//! controls are part of the plan, camera bookmarks are stored alongside the city.
+
+use std::{collections::HashMap, fs, path::Path};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    city::{
+        camera::CameraBookmarks,
+        intersections::{ControlPlan, IntersectionControl, NodeControlPlan},
+        services::{ServiceBuilding, ServicePlan},
+        generator::{CityGenParams, CityPlan},
+        terrain::Terrain,
+        transit::{TransitLine, TransitLinePlan},
+        water::Water,
+        zones::{LotPlan, Zone},
+    },
+    game::{spawn_city, CityEntity, Draggable, Line, PlayState},
+    notifications::{Notification, NotificationKind},
+    rng::SimpleRng,
+    settings::bindings::{action_just_pressed, InputAction},
+};
+
+pub const CITY_SAVE_PATH: &str = "saves/city.json";
+
+pub fn save_plugin(app: &mut App) {
+    app.add_systems(
+        Update,
+        (
+            save_city_system.run_if(action_just_pressed(InputAction::SaveCity)),
+            load_city_system.run_if(action_just_pressed(InputAction::LoadCity)),
+        )
+            .run_if(in_state(PlayState::Play)),
+    );
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CitySave {
+    pub params: CityGenParams,
+    pub plan: CityPlan,
+    pub terrain: Terrain,
+    #[serde(default)]
+    pub water: Water,
+    #[serde(default)]
+    pub bookmarks: CameraBookmarks,
+}
+
+/// Reads the current road network back out of the world
+pub fn collect_plan(world: &mut World) -> CityPlan {
+    let mut plan = CityPlan::default();
+    let mut index = HashMap::new();
+
+    let mut nodes = world.query_filtered::<(Entity, &Transform), With<Draggable>>();
+    for (entity, transform) in nodes.iter(world) {
+        index.insert(entity, plan.nodes.len());
+        plan.nodes.push(transform.translation.truncate());
+    }
+
+    let mut edges = HashMap::new();
+    let mut lines = world.query::<(Entity, &Line)>();
+    for (entity, line) in lines.iter(world) {
+        if let (Some(&a), Some(&b)) = (index.get(&line.from), index.get(&line.to)) {
+            edges.insert(entity, plan.edges.len());
+            plan.edges.push((a, b));
+        }
+    }
+
+    let mut lots = world.query::<(&Zone, &Transform)>();
+    for (zone, transform) in lots.iter(world) {
+        if let Some(&node) = index.get(&zone.node) {
+            plan.lots.push(LotPlan {
+                pos: transform.translation.truncate(),
+                kind: zone.kind,
+                node,
+                level: zone.level,
+            });
+        }
+    }
+
+    let mut transit = world.query::<&TransitLine>();
+    for line in transit.iter(world) {
+        let stops: Option<Vec<usize>> = line.stops.iter().map(|stop| index.get(stop).copied()).collect();
+        if let Some(stops) = stops {
+            plan.transit.push(TransitLinePlan {
+                name: line.name.clone(),
+                mode: line.mode,
+                color: line.color,
+                stops,
+                headway: line.headway,
+            });
+        }
+    }
+
+    let mut services = world.query::<&ServiceBuilding>();
+    for service in services.iter(world) {
+        if let Some(&node) = index.get(&service.node) {
+            plan.services.push(ServicePlan { kind: service.kind, node });
+        }
+    }
+
+    let mut controls = world.query::<(Entity, &IntersectionControl)>();
+    for (entity, control) in controls.iter(world) {
+        if let Some(&node) = index.get(&entity)
+            && let Some(control) = ControlPlan::from_control(control, &edges)
+        {
+            plan.controls.push(NodeControlPlan { node, control });
+        }
+    }
+    plan
+}
+
+pub fn save_city(world: &mut World, path: &Path) -> Result<(), String> {
+    let plan = collect_plan(world);
+    let save = CitySave {
+        params: world.resource::<CityGenParams>().clone(),
+        plan,
+        terrain: world.resource::<Terrain>().clone(),
+        water: world.resource::<Water>().clone(),
+        bookmarks: world.resource::<CameraBookmarks>().clone(),
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create save directory: {}", e))?;
+    }
+    let json = serde_json::to_string(&save).map_err(|e| format!("Failed to serialize city: {}", e))?;
+    fs::write(path, json).map_err(|e| format!("Failed to write city to {}: {}", path.display(), e))
+}
+
+pub fn load_city(path: &Path) -> Result<CitySave, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read city from {}: {}", path.display(), e))?;
+    serde_json::from_str(&contents).map_err(|e| format!("Failed to parse city from {}: {}", path.display(), e))
+}
+
+fn save_city_system(world: &mut World) {
+    match save_city(world, Path::new(CITY_SAVE_PATH)) {
+        Ok(_) => {
+            world.send_event(Notification::new(NotificationKind::SaveComplete { path: CITY_SAVE_PATH.to_string() }));
+        }
+        Err(e) => error!("{}", e),
+    }
+}
+
+fn load_city_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+    mut rng: ResMut<SimpleRng>,
+    city_q: Query<Entity, With<CityEntity>>,
+) {
+    let save = match load_city(Path::new(CITY_SAVE_PATH)) {
+        Ok(save) => save,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+
+    for entity in &city_q {
+        commands.entity(entity).despawn_recursive();
+    }
+    spawn_city(&mut commands, &mut meshes, &mut materials, &mut images, &mut rng, &save.plan, &save.terrain);
+    commands.insert_resource(save.params);
+    commands.insert_resource(save.terrain);
+    commands.insert_resource(save.water);
+    commands.insert_resource(save.bookmarks);
+    info!("City loaded from {}", CITY_SAVE_PATH);
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::city::{intersections::PhasePlan, services::ServiceKind};
+
+    fn spawn_plan(world: &mut World, plan: CityPlan, terrain: Terrain) {
+        world
+            .run_system_once(
+                move |mut commands: Commands,
+                      mut meshes: ResMut<Assets<Mesh>>,
+                      mut materials: ResMut<Assets<ColorMaterial>>,
+                      mut images: ResMut<Assets<Image>>,
+                      mut rng: ResMut<SimpleRng>| {
+                    spawn_city(&mut commands, &mut meshes, &mut materials, &mut images, &mut rng, &plan, &terrain);
+                },
+            )
+            .unwrap();
+    }
+
+    /// Spawns the plan, saves it, clears the city and spawns it again from the file
+    fn save_and_load(plan: CityPlan, name: &str) -> World {
+        let params = CityGenParams { map_size: Vec2::splat(400.0), ..default() };
+        let terrain = Terrain::generate(params.seed, params.map_size);
+        let mut world = World::new();
+        world.init_resource::<Assets<Mesh>>();
+        world.init_resource::<Assets<ColorMaterial>>();
+        world.init_resource::<Assets<Image>>();
+        world.insert_resource(SimpleRng::new(1));
+        world.insert_resource(params);
+        world.insert_resource(terrain.clone());
+        world.insert_resource(Water::default());
+        world.insert_resource(CameraBookmarks::default());
+        spawn_plan(&mut world, plan, terrain);
+
+        let path = std::env::temp_dir().join(name);
+        save_city(&mut world, &path).unwrap();
+        let city: Vec<Entity> = world.query_filtered::<Entity, With<CityEntity>>().iter(&world).collect();
+        for entity in city {
+            world.entity_mut(entity).despawn_recursive();
+        }
+        let save = load_city(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        spawn_plan(&mut world, save.plan, save.terrain);
+        world
+    }
+
+    fn position(world: &World, node: Entity) -> Vec2 {
+        world.get::<Transform>(node).unwrap().translation.truncate()
+    }
+
+    #[test]
+    fn services_come_back_after_loading() {
+        let plan = CityPlan {
+            nodes: vec![Vec2::ZERO, Vec2::new(100.0, 0.0), Vec2::new(100.0, 100.0)],
+            edges: vec![(0, 1), (1, 2)],
+            services: vec![
+                ServicePlan { kind: ServiceKind::Fire, node: 1 },
+                ServicePlan { kind: ServiceKind::School, node: 2 },
+            ],
+            ..default()
+        };
+        let mut world = save_and_load(plan, "city_simulation_services_test.json");
+
+        let mut services: Vec<(ServiceKind, Vec2)> = world
+            .query::<&ServiceBuilding>()
+            .iter(&world)
+            .map(|service| (service.kind, position(&world, service.node)))
+            .collect();
+        services.sort_by_key(|(kind, _)| *kind as usize);
+        assert_eq!(services, vec![(ServiceKind::Fire, Vec2::new(100.0, 0.0)), (ServiceKind::School, Vec2::new(100.0, 100.0))]);
+    }
+
+    #[test]
+    fn edited_controls_come_back_after_loading() {
+        let signal = ControlPlan::Signal {
+            phases: vec![
+                PhasePlan { approaches: vec![0], green: 0.3 },
+                PhasePlan { approaches: vec![1, 2], green: 1.2 },
+            ],
+            auto: true,
+        };
+        let plan = CityPlan {
+            nodes: vec![Vec2::ZERO, Vec2::new(-100.0, 0.0), Vec2::new(100.0, 0.0), Vec2::new(0.0, 100.0)],
+            edges: vec![(0, 1), (0, 2), (0, 3)],
+            controls: vec![
+                NodeControlPlan { node: 0, control: signal.clone() },
+                NodeControlPlan { node: 3, control: ControlPlan::StopSign },
+            ],
+            ..default()
+        };
+        let mut world = save_and_load(plan, "city_simulation_controls_test.json");
+
+        let mut edges = HashMap::new();
+        for (entity, line) in world.query::<(Entity, &Line)>().iter(&world) {
+            let ends = (position(&world, line.from), position(&world, line.to));
+            let far = if ends.0 == Vec2::ZERO { ends.1 } else { ends.0 };
+            let edge = [Vec2::new(-100.0, 0.0), Vec2::new(100.0, 0.0), Vec2::new(0.0, 100.0)].iter().position(|p| *p == far);
+            edges.insert(entity, edge.unwrap());
+        }
+        let controls: Vec<(Vec2, ControlPlan)> = world
+            .query::<(Entity, &IntersectionControl)>()
+            .iter(&world)
+            .map(|(node, control)| (position(&world, node), ControlPlan::from_control(control, &edges).unwrap()))
+            .collect();
+        assert_eq!(controls.len(), 2);
+        assert!(controls.contains(&(Vec2::ZERO, signal)));
+        assert!(controls.contains(&(Vec2::new(0.0, 100.0), ControlPlan::StopSign)));
+    }
+}
@@ -0,0 +1,281 @@
+//! Fire stations, police stations, hospitals and schools.
+//!
+//! A service covers whatever its vehicles can reach along the roads within its
+//! response time, so coverage is an isochrone on the road graph rather than a circle.
+//! The service tool (B, again for the next kind) places the selected service at the
+//! clicked node or removes it when there's one already, O cycles the coverage overlay.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    city::{
+        assignment::AssignmentParams,
+        graph::{GraphSettled, RoadGraph},
+        tools::{cursor_world_pos, Tool},
+    },
+    game::{CityEntity, OnGameScreen, PlayState, NODE_RADIUS},
+    settings::bindings::{Actions, InputAction},
+};
+
+const SERVICE_SIZE: f32 = 36.0;
+
+pub fn services_plugin(app: &mut App) {
+    app
+        .register_type::<ServiceBuilding>()
+        .init_resource::<ServiceCoverage>()
+        .init_resource::<ServiceBrush>()
+        .init_resource::<CoverageOverlay>()
+        .add_systems(
+            Update,
+            (
+                place_service_system.run_if(in_state(Tool::Service)),
+                remove_orphan_services_system,
+                update_coverage_system,
+                toggle_coverage_overlay_system,
+                draw_coverage_system.run_if(|overlay: Res<CoverageOverlay>| overlay.0.is_some()),
+            )
+                .chain()
+                .run_if(in_state(PlayState::Play)),
+        );
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect, Serialize, Deserialize)]
+pub enum ServiceKind {
+    Fire,
+    Police,
+    Hospital,
+    School,
+}
+
+impl ServiceKind {
+    pub const ALL: [ServiceKind; 4] = [ServiceKind::Fire, ServiceKind::Police, ServiceKind::Hospital, ServiceKind::School];
+
+    pub fn color(&self) -> Color {
+        match self {
+            ServiceKind::Fire => Color::srgb(0.9, 0.25, 0.15),
+            ServiceKind::Police => Color::srgb(0.2, 0.35, 0.95),
+            ServiceKind::Hospital => Color::srgb(0.95, 0.95, 0.95),
+            ServiceKind::School => Color::srgb(0.95, 0.8, 0.2),
+        }
+    }
+
+    /// Simulated minutes of driving the service still counts as covering a place
+    pub fn response_time(&self) -> f32 {
+        match self {
+            ServiceKind::Fire => 4.0,
+            ServiceKind::Police => 5.0,
+            ServiceKind::Hospital => 8.0,
+            ServiceKind::School => 10.0,
+        }
+    }
+
+    /// Running costs per simulated hour
+    pub fn upkeep(&self) -> f32 {
+        match self {
+            ServiceKind::Fire => 60.0,
+            ServiceKind::Police => 60.0,
+            ServiceKind::Hospital => 120.0,
+            ServiceKind::School => 80.0,
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+
+    pub fn next(&self) -> ServiceKind {
+        ServiceKind::ALL[(self.index() + 1) % ServiceKind::ALL.len()]
+    }
+}
+
+#[derive(Component, Reflect, Debug, Clone, Copy)]
+#[reflect(Component)]
+pub struct ServiceBuilding {
+    pub kind: ServiceKind,
+    /// Road node the service drives out from
+    pub node: Entity,
+}
+
+/// A service building as stored in a `CityPlan`
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ServicePlan {
+    pub kind: ServiceKind,
+    /// Index of the road node in `CityPlan::nodes`
+    pub node: usize,
+}
+
+/// Travel time from the nearest service of each kind to every road node
+#[derive(Resource, Debug, Clone, Default)]
+pub struct ServiceCoverage {
+    /// Indexed by `ServiceKind`, then by road graph node
+    pub travel_times: [Vec<f32>; 4],
+}
+
+impl ServiceCoverage {
+    /// 1 right next to a service, falling to 0 at the edge of its response time
+    pub fn coverage(&self, kind: ServiceKind, node: usize) -> f32 {
+        let time = self.travel_times[kind.index()].get(node).copied().unwrap_or(f32::INFINITY);
+        (1.0 - time / kind.response_time()).max(0.0)
+    }
+}
+
+/// Kind placed by the service tool
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct ServiceBrush(pub ServiceKind);
+
+impl Default for ServiceBrush {
+    fn default() -> Self {
+        Self(ServiceKind::Fire)
+    }
+}
+
+/// Service whose coverage is drawn over the roads
+#[derive(Resource, Debug, Clone, Copy, Default)]
+pub struct CoverageOverlay(pub Option<ServiceKind>);
+
+pub fn spawn_service(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<ColorMaterial>,
+    service: ServiceBuilding,
+    position: Vec2,
+) -> Entity {
+    commands
+        .spawn((
+            OnGameScreen,
+            CityEntity,
+            service,
+            Mesh2d(meshes.add(Rectangle::new(SERVICE_SIZE, SERVICE_SIZE))),
+            MeshMaterial2d(materials.add(service.kind.color())),
+            Transform::from_translation((position + Vec2::splat(NODE_RADIUS)).extend(30.0)),
+        ))
+        .id()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn place_service_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    buttons: Res<ButtonInput<MouseButton>>,
+    windows: Query<&Window>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    ui_q: Query<&Interaction>,
+    graph: Res<RoadGraph>,
+    brush: Res<ServiceBrush>,
+    service_q: Query<(Entity, &ServiceBuilding)>,
+) {
+    if !buttons.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let Some(world_pos) = cursor_world_pos(&windows, &camera_q, &ui_q) else { return };
+    let Some(index) = graph.nearest_node(world_pos, NODE_RADIUS * 2.0) else { return };
+    let node = graph.nodes[index];
+
+    let existing = service_q.iter().find(|(_, s)| s.node == node && s.kind == brush.0);
+    match existing {
+        Some((entity, _)) => commands.entity(entity).despawn_recursive(),
+        None => {
+            spawn_service(
+                &mut commands,
+                &mut meshes,
+                &mut materials,
+                ServiceBuilding { kind: brush.0, node },
+                graph.positions[index],
+            );
+        }
+    }
+}
+
+// A service whose road node was deleted goes with it
+fn remove_orphan_services_system(
+    mut commands: Commands,
+    graph: Res<RoadGraph>,
+    service_q: Query<(Entity, &ServiceBuilding)>,
+) {
+    if !graph.is_changed() {
+        return;
+    }
+    for (entity, service) in &service_q {
+        if !graph.index.contains_key(&service.node) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+// Isochrones are redone for new or removed services, and for new roads after a drag
+fn update_coverage_system(
+    graph: Res<RoadGraph>,
+    mut graph_settled: GraphSettled,
+    params: Res<AssignmentParams>,
+    mut coverage: ResMut<ServiceCoverage>,
+    service_q: Query<&ServiceBuilding>,
+    changed: Query<(), Changed<ServiceBuilding>>,
+    mut removed: RemovedComponents<ServiceBuilding>,
+) {
+    let removed = removed.read().count() > 0;
+    if !graph_settled.changed() && changed.is_empty() && !removed {
+        return;
+    }
+
+    for kind in ServiceKind::ALL {
+        let mut times = vec![f32::INFINITY; graph.len()];
+        for service in service_q.iter().filter(|s| s.kind == kind) {
+            let Some(&source) = graph.index.get(&service.node) else { continue };
+            // nodes past the response time are not covered, no need to search further
+            let (costs, _) = graph.dijkstra_with(source, |e| e.length / params.free_speed, kind.response_time());
+            for (time, cost) in times.iter_mut().zip(costs) {
+                *time = time.min(cost);
+            }
+        }
+        coverage.travel_times[kind.index()] = times;
+    }
+}
+
+fn toggle_coverage_overlay_system(actions: Actions, mut overlay: ResMut<CoverageOverlay>) {
+    if !actions.just_pressed(InputAction::CoverageOverlay) {
+        return;
+    }
+    overlay.0 = match overlay.0 {
+        None => Some(ServiceKind::Fire),
+        Some(kind) => ServiceKind::ALL.get(kind.index() + 1).copied(),
+    };
+}
+
+// Covered roads in the service colour, fading out towards the edge of the isochrone.
+// Roads only partly reachable are drawn up to where the response time runs out.
+fn draw_coverage_system(
+    graph: Res<RoadGraph>,
+    params: Res<AssignmentParams>,
+    coverage: Res<ServiceCoverage>,
+    overlay: Res<CoverageOverlay>,
+    service_q: Query<(&ServiceBuilding, &Transform)>,
+    mut gizmos: Gizmos,
+) {
+    let Some(kind) = overlay.0 else { return };
+    let times = &coverage.travel_times[kind.index()];
+    let reach = kind.response_time();
+    let color = kind.color();
+
+    for (a, edges) in graph.adjacency.iter().enumerate() {
+        let Some(&time) = times.get(a) else { continue };
+        if time >= reach {
+            continue;
+        }
+        for edge in edges {
+            let edge_time = edge.length / params.free_speed;
+            let fraction = ((reach - time) / edge_time).min(1.0);
+            let (from, to) = (graph.positions[a], graph.positions[edge.to]);
+            gizmos.line_2d(
+                from,
+                from.lerp(to, fraction),
+                color.with_alpha(0.3 + 0.7 * coverage.coverage(kind, a)),
+            );
+        }
+    }
+
+    for (_, transform) in service_q.iter().filter(|(s, _)| s.kind == kind) {
+        gizmos.circle_2d(transform.translation.truncate(), SERVICE_SIZE, color);
+    }
+}
@@ -0,0 +1,250 @@
+//! Terrain heightmap and its shaded background.
+//!
+//! Heights are stored normalized to 0..1 and scaled by `relief` when sampled,
+//! so a PNG heightmap and the noise generator go through the same code.
+
+use std::path::Path;
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+use serde::{Deserialize, Serialize};
+
+use crate::rng::SimpleRng;
+
+/// Keeps the terrain noise independent from the road layout drawn with the same seed
+const TERRAIN_SEED_SALT: u64 = 0x7E22_A1B5_0C3D_9F11;
+/// World distance between two height samples
+const CELL_SIZE: f32 = 20.0;
+
+pub fn terrain_plugin(app: &mut App) {
+    app.init_resource::<Terrain>();
+}
+
+#[derive(Resource, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Terrain {
+    /// Number of samples along x
+    pub width: usize,
+    /// Number of samples along y
+    pub height: usize,
+    /// World distance between two samples
+    pub cell_size: f32,
+    /// World position of the bottom left sample
+    pub origin: Vec2,
+    /// World elevation of a normalized height of 1.0
+    pub relief: f32,
+    /// Normalized heights, row major starting from the bottom row
+    pub heights: Vec<f32>,
+}
+
+impl Terrain {
+    /// Value noise with a few octaves, the same seed always gives the same terrain
+    pub fn generate(seed: u64, map_size: Vec2) -> Self {
+        let mut rng = SimpleRng::new(seed ^ TERRAIN_SEED_SALT);
+        let cell_size = CELL_SIZE;
+        let width = (map_size.x / cell_size) as usize + 1;
+        let height = (map_size.y / cell_size) as usize + 1;
+
+        let mut heights = vec![0.0; width * height];
+        let mut amplitude = 1.0;
+        let mut total = 0.0;
+        for octave in 0..4 {
+            let cells = 2usize << octave;
+            let lattice: Vec<f32> = (0..(cells + 1) * (cells + 1)).map(|_| rng.next_scaled()).collect();
+            let at = |x: usize, y: usize| lattice[y * (cells + 1) + x];
+
+            for y in 0..height {
+                for x in 0..width {
+                    let fx = x as f32 / (width - 1).max(1) as f32 * cells as f32;
+                    let fy = y as f32 / (height - 1).max(1) as f32 * cells as f32;
+                    let (x0, y0) = ((fx as usize).min(cells - 1), (fy as usize).min(cells - 1));
+                    let tx = smoothstep(fx - x0 as f32);
+                    let ty = smoothstep(fy - y0 as f32);
+
+                    let bottom = at(x0, y0).lerp(at(x0 + 1, y0), tx);
+                    let top = at(x0, y0 + 1).lerp(at(x0 + 1, y0 + 1), tx);
+                    heights[y * width + x] += bottom.lerp(top, ty) * amplitude;
+                }
+            }
+            total += amplitude;
+            amplitude *= 0.5;
+        }
+        for h in &mut heights {
+            *h /= total;
+        }
+
+        Self {
+            width,
+            height,
+            cell_size,
+            origin: -map_size / 2.0,
+            relief: 150.0,
+            heights,
+        }
+    }
+
+    /// Loads a grayscale PNG stretched over the map, white is the highest point
+    pub fn from_grayscale_png(path: &Path, map_size: Vec2) -> Result<Self, String> {
+        let image = image::open(path)
+            .map_err(|e| format!("Failed to read heightmap {}: {}", path.display(), e))?
+            .to_luma8();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        if width < 2 || height < 2 {
+            return Err(format!("Heightmap {} is too small", path.display()));
+        }
+
+        // image rows go top to bottom, ours go bottom to top
+        let pixel = |x: usize, y: usize| image.get_pixel(x as u32, (height - 1 - y) as u32).0[0] as f32 / 255.0;
+
+        // resampled onto square cells, so each image axis stretches over its own map axis
+        let cols = (map_size.x / CELL_SIZE) as usize + 1;
+        let rows = (map_size.y / CELL_SIZE) as usize + 1;
+        let mut heights = Vec::with_capacity(cols * rows);
+        for y in 0..rows {
+            for x in 0..cols {
+                let fx = x as f32 / (cols - 1).max(1) as f32 * (width - 1) as f32;
+                let fy = y as f32 / (rows - 1).max(1) as f32 * (height - 1) as f32;
+                let (x0, y0) = ((fx as usize).min(width - 2), (fy as usize).min(height - 2));
+                let (tx, ty) = (fx - x0 as f32, fy - y0 as f32);
+
+                let bottom = pixel(x0, y0).lerp(pixel(x0 + 1, y0), tx);
+                let top = pixel(x0, y0 + 1).lerp(pixel(x0 + 1, y0 + 1), tx);
+                heights.push(bottom.lerp(top, ty));
+            }
+        }
+
+        Ok(Self {
+            width: cols,
+            height: rows,
+            cell_size: CELL_SIZE,
+            origin: -map_size / 2.0,
+            relief: 150.0,
+            heights,
+        })
+    }
+
+    pub fn size(&self) -> Vec2 {
+        Vec2::new(
+            (self.width.max(1) - 1) as f32,
+            (self.height.max(1) - 1) as f32,
+        ) * self.cell_size
+    }
+
+    /// World area covered by the terrain
+    pub fn bounds(&self) -> Rect {
+        Rect::from_corners(self.origin, self.origin + self.size())
+    }
+
+    /// Normalized height of a sample, flat (0.0) for a terrain without samples
+    fn sample(&self, x: usize, y: usize) -> f32 {
+        if self.heights.is_empty() {
+            return 0.0;
+        }
+        self.heights[y.min(self.height - 1) * self.width + x.min(self.width - 1)]
+    }
+
+    /// Bilinear elevation in world units, flat (0.0) outside the map
+    pub fn height_at(&self, pos: Vec2) -> f32 {
+        if self.heights.is_empty() {
+            return 0.0;
+        }
+        let local = (pos - self.origin) / self.cell_size;
+        let max = Vec2::new((self.width - 1) as f32, (self.height - 1) as f32);
+        let local = local.clamp(Vec2::ZERO, max);
+
+        let (x0, y0) = (local.x as usize, local.y as usize);
+        let (tx, ty) = (local.x.fract(), local.y.fract());
+        let bottom = self.sample(x0, y0).lerp(self.sample(x0 + 1, y0), tx);
+        let top = self.sample(x0, y0 + 1).lerp(self.sample(x0 + 1, y0 + 1), tx);
+        bottom.lerp(top, ty) * self.relief
+    }
+
+    /// Steepest rise over run found when walking from a to b
+    pub fn max_grade(&self, a: Vec2, b: Vec2) -> f32 {
+        let length = a.distance(b);
+        if self.heights.is_empty() || length <= f32::EPSILON {
+            return 0.0;
+        }
+        let steps = (length / self.cell_size).ceil().max(1.0) as usize;
+        let step = length / steps as f32;
+
+        let mut previous = self.height_at(a);
+        let mut grade: f32 = 0.0;
+        for i in 1..=steps {
+            let current = self.height_at(a.lerp(b, i as f32 / steps as f32));
+            grade = grade.max((current - previous).abs() / step);
+            previous = current;
+        }
+        grade
+    }
+
+    /// Elevation tinted and hill shaded with light coming from the north west
+    pub fn to_image(&self) -> Image {
+        // a single flat pixel stands in for a terrain without samples
+        let (width, height) = (self.width.max(1), self.height.max(1));
+        let light = Vec3::new(-1.0, 1.0, 1.5).normalize();
+        let mut data = Vec::with_capacity(width * height * 4);
+
+        for y in (0..height).rev() {
+            for x in 0..width {
+                let h = self.sample(x, y);
+                let dx = (self.sample(x + 1, y) - self.sample(x.saturating_sub(1), y)) * self.relief;
+                let dy = (self.sample(x, y + 1) - self.sample(x, y.saturating_sub(1))) * self.relief;
+                let normal = Vec3::new(-dx, -dy, 2.0 * self.cell_size).normalize();
+                let shade = 0.6 + 0.4 * normal.dot(light).max(0.0);
+
+                let low = Vec3::new(0.36, 0.52, 0.30);
+                let high = Vec3::new(0.62, 0.54, 0.40);
+                let color = low.lerp(high, h) * shade;
+                data.extend_from_slice(&[
+                    (color.x * 255.0) as u8,
+                    (color.y * 255.0) as u8,
+                    (color.z * 255.0) as u8,
+                    255,
+                ]);
+            }
+        }
+
+        Image::new(
+            Extent3d {
+                width: width as u32,
+                height: height as u32,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::RENDER_WORLD,
+        )
+    }
+}
+
+fn smoothstep(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Tag component for the shaded terrain sprite
+#[derive(Component)]
+pub struct TerrainBackground;
+
+pub fn spawn_terrain_background(
+    commands: &mut Commands,
+    images: &mut Assets<Image>,
+    terrain: &Terrain,
+) -> Entity {
+    commands
+        .spawn((
+            TerrainBackground,
+            Sprite {
+                image: images.add(terrain.to_image()),
+                custom_size: Some(terrain.size()),
+                ..default()
+            },
+            Transform::from_translation((terrain.origin + terrain.size() / 2.0).extend(-100.0)),
+        ))
+        .id()
+}
@@ -0,0 +1,601 @@
+//! Build toolbar and the tools it switches between.
+//!
+//! The active `Tool` is a state, so each tool's input systems only run while it is
+//! selected and a click only ever goes to one of them. Select (I) drags and inspects,
+//! Road (N) joins two intersections, Node (J) places an intersection, Zone (Z) zones a
+//! lot, Bulldoze (X) removes whatever is clicked, Measure (M) compares straight and
+//! road distances, Water (R) draws rivers and lakes, Transit (T) draws bus and tram
+//! lines and Service (B) places fire stations, hospitals and the like. Picking a tool
+//! with kinds again switches to the next kind. Every game starts with Select. The keys
+//! are the default bindings, the buttons show whatever they are bound to.
+
+use bevy::{
+    ecs::{component::Tick, system::SystemParam},
+    prelude::*,
+};
+
+use crate::{
+    city::{
+        budget::Budget,
+        geometry::point_segment_distance,
+        graph::RoadGraph,
+        roads::ConstructionRules,
+        services::{ServiceBrush, ServiceBuilding},
+        terrain::Terrain,
+        transit::{TransitBrush, TransitMode},
+        water::{Water, WaterBrush, WaterBrushKind},
+        zones::{spawn_lot, Zone, ZoneKind, LOT_SIZE},
+    },
+    common::StageSelect,
+    game::{
+        spawn_circle, spawn_line, CityEntity, DragTarget, Draggable, Line, OnGameScreen, PlayState, Selection,
+        NODE_COLOR, NODE_RADIUS,
+    },
+    menus::{
+        navigation::NoMenuFocus,
+        ui::{SelectedOption, SettingButton, NORMAL_BUTTON, TEXT_COLOR},
+    },
+    settings::bindings::{binding_name, Actions, InputAction, KeyBindings},
+};
+
+/// Lots further than this from any intersection can't be zoned
+const MAX_LOT_DISTANCE: f32 = 150.0;
+const PREVIEW_COLOR: Color = Color::srgb(1.0, 0.9, 0.2);
+
+pub fn tools_plugin(app: &mut App) {
+    app
+        .init_state::<Tool>()
+        .init_resource::<ToolInput>()
+        .init_resource::<ZoneBrush>()
+        .add_systems(OnEnter(StageSelect::Game), toolbar_setup)
+        .add_systems(
+            Update,
+            (
+                tool_hotkey_system,
+                tool_button_system,
+                reset_tool_input_system.run_if(state_changed::<Tool>),
+                sync_toolbar_system,
+            )
+                .chain()
+                .run_if(in_state(PlayState::Play)),
+        )
+        .add_systems(
+            Update,
+            (
+                road_tool_system.run_if(in_state(Tool::Road)),
+                node_tool_system.run_if(in_state(Tool::Node)),
+                zone_tool_system.run_if(in_state(Tool::Zone)),
+                bulldoze_tool_system.run_if(in_state(Tool::Bulldoze)),
+                measure_tool_system.run_if(in_state(Tool::Measure)),
+                draw_tool_preview_system,
+            )
+                .after(sync_toolbar_system)
+                .run_if(in_state(PlayState::Play)),
+        );
+}
+
+#[derive(States, Component, Clone, Copy, Default, Eq, PartialEq, Debug, Hash)]
+pub enum Tool {
+    #[default]
+    Select,
+    Road,
+    Node,
+    Zone,
+    Bulldoze,
+    Measure,
+    Water,
+    Transit,
+    Service,
+}
+
+impl Tool {
+    pub const ALL: [Tool; 9] = [
+        Tool::Select,
+        Tool::Road,
+        Tool::Node,
+        Tool::Zone,
+        Tool::Bulldoze,
+        Tool::Measure,
+        Tool::Water,
+        Tool::Transit,
+        Tool::Service,
+    ];
+
+    pub fn action(&self) -> InputAction {
+        match self {
+            Tool::Select => InputAction::SelectTool,
+            Tool::Road => InputAction::RoadTool,
+            Tool::Node => InputAction::NodeTool,
+            Tool::Zone => InputAction::ZoneTool,
+            Tool::Bulldoze => InputAction::BulldozeTool,
+            Tool::Measure => InputAction::MeasureTool,
+            Tool::Water => InputAction::WaterTool,
+            Tool::Transit => InputAction::TransitTool,
+            Tool::Service => InputAction::ServiceTool,
+        }
+    }
+}
+
+/// Run condition for the systems of the select tool
+pub fn select_tool_active(tool: Res<State<Tool>>) -> bool {
+    *tool.get() == Tool::Select
+}
+
+/// Clicks collected by the active tool, cleared when switching tools
+#[derive(Resource, Debug, Default)]
+pub struct ToolInput {
+    /// Intersection a new road starts from
+    pub road_start: Option<Entity>,
+    /// Points clicked with the measure tool
+    pub points: Vec<Vec2>,
+}
+
+/// Kind of lot the zone tool places
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct ZoneBrush(pub ZoneKind);
+
+impl Default for ZoneBrush {
+    fn default() -> Self {
+        Self(ZoneKind::Residential)
+    }
+}
+
+// Tag component for the measure readout
+#[derive(Component)]
+struct MeasureText;
+
+/// Kinds of the tools that have them, and the bindings shown on the buttons
+#[derive(SystemParam)]
+pub struct ToolBrushes<'w> {
+    bindings: Res<'w, KeyBindings>,
+    zone: ResMut<'w, ZoneBrush>,
+    water: ResMut<'w, WaterBrush>,
+    transit: ResMut<'w, TransitBrush>,
+    service: ResMut<'w, ServiceBrush>,
+}
+
+impl ToolBrushes<'_> {
+    fn next_kind(&mut self, tool: Tool) {
+        match tool {
+            Tool::Zone => self.zone.0 = next_zone_kind(self.zone.0),
+            Tool::Water => {
+                self.water.kind = match self.water.kind {
+                    WaterBrushKind::River => WaterBrushKind::Lake,
+                    WaterBrushKind::Lake => WaterBrushKind::River,
+                }
+            }
+            Tool::Transit => {
+                self.transit.mode = match self.transit.mode {
+                    TransitMode::Bus => TransitMode::Tram,
+                    TransitMode::Tram => TransitMode::Bus,
+                }
+            }
+            Tool::Service => self.service.0 = self.service.0.next(),
+            _ => {}
+        }
+    }
+
+    // Half drawn rivers and lines are dropped when switching tools
+    fn clear(&mut self) {
+        self.water.points.clear();
+        self.transit.stops.clear();
+    }
+
+    fn is_changed(&self) -> bool {
+        self.bindings.is_changed()
+            || self.zone.is_changed() || self.water.is_changed() || self.transit.is_changed() || self.service.is_changed()
+    }
+
+    fn label(&self, tool: Tool) -> String {
+        let name = tool.action().label();
+        let key = binding_name(self.bindings.binding(tool.action()));
+        match tool {
+            Tool::Zone => format!("{}: {:?} ({})", name, self.zone.0, key),
+            Tool::Water => format!("{}: {:?} ({})", name, self.water.kind, key),
+            Tool::Transit => format!("{}: {:?} ({})", name, self.transit.mode, key),
+            Tool::Service => format!("{}: {:?} ({})", name, self.service.0, key),
+            _ => format!("{} ({})", name, key),
+        }
+    }
+}
+
+// Tag component for the text of a tool button, tools with kinds show the kind too
+#[derive(Component)]
+struct ToolButtonText(Tool);
+
+fn toolbar_setup(mut commands: Commands, mut next_tool: ResMut<NextState<Tool>>, brushes: ToolBrushes) {
+    next_tool.set(Tool::Select);
+
+    commands
+        .spawn((
+            OnGameScreen,
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.0),
+                width: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                row_gap: Val::Px(4.0),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(Node { column_gap: Val::Px(4.0), ..default() })
+                .with_children(|bar| {
+                    for tool in Tool::ALL {
+                        let mut button = bar.spawn((
+                            Button,
+                            SettingButton,
+                            NoMenuFocus,
+                            tool,
+                            Node { padding: UiRect::axes(Val::Px(10.0), Val::Px(6.0)), ..default() },
+                            BackgroundColor(NORMAL_BUTTON),
+                        ));
+                        if tool == Tool::Select {
+                            button.insert(SelectedOption);
+                        }
+                        button.with_children(|button| {
+                            button.spawn((
+                                ToolButtonText(tool),
+                                Text::new(brushes.label(tool)),
+                                TextFont { font_size: 16.0, ..default() },
+                                TextColor(TEXT_COLOR),
+                            ));
+                        });
+                    }
+                });
+            parent.spawn((
+                MeasureText,
+                Text::new(""),
+                TextFont { font_size: 16.0, ..default() },
+                TextColor(TEXT_COLOR),
+            ));
+        });
+}
+
+fn tool_hotkey_system(
+    actions: Actions,
+    tool: Res<State<Tool>>,
+    mut next_tool: ResMut<NextState<Tool>>,
+    mut brushes: ToolBrushes,
+) {
+    for candidate in Tool::ALL {
+        if !actions.just_pressed(candidate.action()) {
+            continue;
+        }
+        if candidate == *tool.get() {
+            brushes.next_kind(candidate);
+        }
+        next_tool.set(candidate);
+    }
+}
+
+fn next_zone_kind(kind: ZoneKind) -> ZoneKind {
+    match kind {
+        ZoneKind::Residential => ZoneKind::Commercial,
+        ZoneKind::Commercial => ZoneKind::Industrial,
+        ZoneKind::Industrial => ZoneKind::Residential,
+    }
+}
+
+// Same as `setting_button` but for the tool state
+#[allow(clippy::type_complexity)]
+fn tool_button_system(
+    interaction_q: Query<(&Interaction, &Tool), (Changed<Interaction>, With<SettingButton>)>,
+    tool: Res<State<Tool>>,
+    mut next_tool: ResMut<NextState<Tool>>,
+    mut brushes: ToolBrushes,
+) {
+    for (interaction, button_tool) in &interaction_q {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        if *button_tool == *tool.get() {
+            brushes.next_kind(*button_tool);
+        }
+        next_tool.set(*button_tool);
+    }
+}
+
+fn reset_tool_input_system(
+    mut input: ResMut<ToolInput>,
+    mut drag_target: ResMut<DragTarget>,
+    mut brushes: ToolBrushes,
+) {
+    *input = ToolInput::default();
+    drag_target.0 = None;
+    brushes.clear();
+}
+
+// Hotkeys change the tool too, so the highlighted button follows the state
+fn sync_toolbar_system(
+    mut commands: Commands,
+    tool: Res<State<Tool>>,
+    brushes: ToolBrushes,
+    button_q: Query<(Entity, &Tool, Has<SelectedOption>), With<SettingButton>>,
+    mut text_q: Query<(&mut Text, &ToolButtonText)>,
+) {
+    if tool.is_changed() {
+        for (entity, button_tool, selected) in &button_q {
+            match (*button_tool == *tool.get(), selected) {
+                (true, false) => {
+                    commands.entity(entity).insert(SelectedOption);
+                }
+                (false, true) => {
+                    commands.entity(entity).remove::<SelectedOption>().insert(BackgroundColor(NORMAL_BUTTON));
+                }
+                _ => {}
+            }
+        }
+    }
+    if brushes.is_changed() {
+        for (mut text, button) in &mut text_q {
+            text.0 = brushes.label(button.0);
+        }
+    }
+}
+
+/// World position under the cursor, None over the HUD or outside the window
+pub fn cursor_world_pos(
+    windows: &Query<&Window>,
+    camera_q: &Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    ui_q: &Query<&Interaction>,
+) -> Option<Vec2> {
+    if ui_q.iter().any(|i| *i != Interaction::None) {
+        return None;
+    }
+    let (camera, cam_transform) = camera_q.get_single().ok()?;
+    let cursor = windows.get_single().ok()?.cursor_position()?;
+    camera.viewport_to_world_2d(cam_transform, cursor).ok()
+}
+
+// First click picks where the road starts, the second where it ends. Roads too steep
+// to build are refused, the others are paid for right away.
+#[allow(clippy::too_many_arguments)]
+fn road_tool_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    buttons: Res<ButtonInput<MouseButton>>,
+    windows: Query<&Window>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    ui_q: Query<&Interaction>,
+    graph: Res<RoadGraph>,
+    rules: Res<ConstructionRules>,
+    terrain: Res<Terrain>,
+    water: Res<Water>,
+    mut budget: ResMut<Budget>,
+    mut input: ResMut<ToolInput>,
+) {
+    if buttons.just_pressed(MouseButton::Right) {
+        input.road_start = None;
+    }
+    if !buttons.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let Some(world_pos) = cursor_world_pos(&windows, &camera_q, &ui_q) else { return };
+    let Some(clicked) = graph.nearest_node(world_pos, NODE_RADIUS * 2.0) else { return };
+
+    let Some(start) = input.road_start.and_then(|e| graph.index.get(&e).copied()) else {
+        input.road_start = Some(graph.nodes[clicked]);
+        return;
+    };
+    if start == clicked || graph.edge_between(start, clicked).is_some() {
+        input.road_start = Some(graph.nodes[clicked]);
+        return;
+    }
+
+    let (a, b) = (graph.positions[start], graph.positions[clicked]);
+    let cost = rules.road_cost(&terrain, &water, a, b);
+    if !cost.buildable {
+        info!("Too steep to build a road here ({:.0}% grade)", cost.grade * 100.0);
+        return;
+    }
+    spawn_line(&mut commands, &mut meshes, &mut materials, graph.nodes[start], graph.nodes[clicked], a, b);
+    budget.balance -= cost.cost;
+    // keep building from where this road ended
+    input.road_start = Some(graph.nodes[clicked]);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn node_tool_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    buttons: Res<ButtonInput<MouseButton>>,
+    windows: Query<&Window>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    ui_q: Query<&Interaction>,
+    graph: Res<RoadGraph>,
+    water: Res<Water>,
+) {
+    if !buttons.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let Some(world_pos) = cursor_world_pos(&windows, &camera_q, &ui_q) else { return };
+    if water.contains(world_pos) || graph.nearest_node(world_pos, NODE_RADIUS * 3.0).is_some() {
+        return;
+    }
+    spawn_circle(&mut commands, &mut meshes, &mut materials, world_pos, 40.0, NODE_RADIUS, NODE_COLOR);
+}
+
+// Lots go on free land next to the roads, tied to the closest intersection
+#[allow(clippy::too_many_arguments)]
+fn zone_tool_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    buttons: Res<ButtonInput<MouseButton>>,
+    windows: Query<&Window>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    ui_q: Query<&Interaction>,
+    graph: Res<RoadGraph>,
+    water: Res<Water>,
+    brush: Res<ZoneBrush>,
+    lot_q: Query<&Transform, With<Zone>>,
+) {
+    if !buttons.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let Some(pos) = cursor_world_pos(&windows, &camera_q, &ui_q) else { return };
+    let Some(node) = graph.nearest_node(pos, MAX_LOT_DISTANCE) else { return };
+
+    let on_road = graph.adjacency.iter().enumerate().any(|(a, edges)| {
+        edges
+            .iter()
+            .any(|e| point_segment_distance(pos, graph.positions[a], graph.positions[e.to]) < LOT_SIZE * 0.6)
+    });
+    let blocked = water.contains(pos)
+        || on_road
+        || graph.positions.iter().any(|p| p.distance(pos) < LOT_SIZE)
+        || lot_q.iter().any(|t| t.translation.truncate().distance(pos) < LOT_SIZE);
+    if blocked {
+        return;
+    }
+
+    let zone = Zone { kind: brush.0, node: graph.nodes[node], level: 1 };
+    let lot = spawn_lot(&mut commands, &mut meshes, &mut materials, zone, pos);
+    commands.entity(lot).insert((OnGameScreen, CityEntity));
+}
+
+// Buildings first, then roads, then intersections along with everything attached to them
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn bulldoze_tool_system(
+    mut commands: Commands,
+    buttons: Res<ButtonInput<MouseButton>>,
+    windows: Query<&Window>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    ui_q: Query<&Interaction>,
+    graph: Res<RoadGraph>,
+    building_q: Query<(Entity, &Transform), Or<(With<Zone>, With<ServiceBuilding>)>>,
+    attached_q: Query<(Entity, Option<&Zone>, Option<&ServiceBuilding>), Or<(With<Zone>, With<ServiceBuilding>)>>,
+    line_q: Query<(Entity, &Line)>,
+    mut selection: ResMut<Selection>,
+) {
+    if !buttons.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let Some(pos) = cursor_world_pos(&windows, &camera_q, &ui_q) else { return };
+
+    let building = building_q
+        .iter()
+        .find(|(_, t)| (t.translation.truncate() - pos).abs().max_element() < LOT_SIZE / 2.0)
+        .map(|(e, _)| e);
+    let road = || {
+        graph.adjacency.iter().enumerate().find_map(|(a, edges)| {
+            edges
+                .iter()
+                .find(|e| point_segment_distance(pos, graph.positions[a], graph.positions[e.to]) < NODE_RADIUS / 2.0)
+                .map(|e| e.line)
+        })
+    };
+    let node = graph.nearest_node(pos, NODE_RADIUS).map(|i| graph.nodes[i]);
+
+    let mut doomed = vec![];
+    if let Some(building) = building {
+        doomed.push(building);
+    } else if let Some(node) = node {
+        doomed.push(node);
+        doomed.extend(line_q.iter().filter(|(_, l)| l.from == node || l.to == node).map(|(e, _)| e));
+        doomed.extend(attached_q.iter().filter_map(|(e, zone, service)| {
+            let attached = zone.is_some_and(|z| z.node == node) || service.is_some_and(|s| s.node == node);
+            attached.then_some(e)
+        }));
+    } else if let Some(road) = road() {
+        doomed.push(road);
+    }
+
+    for entity in doomed {
+        if selection.0 == Some(entity) {
+            selection.0 = None;
+        }
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn measure_tool_system(
+    buttons: Res<ButtonInput<MouseButton>>,
+    windows: Query<&Window>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    ui_q: Query<&Interaction>,
+    mut input: ResMut<ToolInput>,
+) {
+    if !buttons.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let Some(pos) = cursor_world_pos(&windows, &camera_q, &ui_q) else { return };
+    if input.points.len() >= 2 {
+        input.points.clear();
+    }
+    input.points.push(pos);
+}
+
+/// Road distance between the last two nodes measured, valid while the graph is unchanged
+#[derive(Clone, Copy, Debug)]
+struct MeasuredRoad {
+    from: usize,
+    to: usize,
+    graph_changed: Tick,
+    distance: f32,
+}
+
+// Shows what the active tool is about to do, and the measure readout
+#[allow(clippy::too_many_arguments)]
+fn draw_tool_preview_system(
+    tool: Res<State<Tool>>,
+    input: Res<ToolInput>,
+    graph: Res<RoadGraph>,
+    windows: Query<&Window>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    ui_q: Query<&Interaction>,
+    node_q: Query<&Transform, With<Draggable>>,
+    mut text_q: Query<&mut Text, With<MeasureText>>,
+    mut measured: Local<Option<MeasuredRoad>>,
+    mut gizmos: Gizmos,
+) {
+    let cursor = cursor_world_pos(&windows, &camera_q, &ui_q);
+    let Ok(mut text) = text_q.get_single_mut() else { return };
+
+    match tool.get() {
+        Tool::Road => {
+            let start = input.road_start.and_then(|e| node_q.get(e).ok());
+            if let (Some(start), Some(cursor)) = (start, cursor) {
+                gizmos.line_2d(start.translation.truncate(), cursor, PREVIEW_COLOR);
+            }
+        }
+        Tool::Measure => {
+            let mut points = input.points.clone();
+            if points.len() == 1 {
+                points.extend(cursor);
+            }
+            if let [a, b] = points[..] {
+                gizmos.line_2d(a, b, PREVIEW_COLOR);
+                let road = match (graph.nearest_node(a, f32::INFINITY), graph.nearest_node(b, f32::INFINITY)) {
+                    (Some(from), Some(to)) => {
+                        let graph_changed = graph.last_changed();
+                        match *measured {
+                            Some(m) if (m.from, m.to, m.graph_changed) == (from, to, graph_changed) => m.distance,
+                            _ => {
+                                let distance = graph.distances_from(from)[to];
+                                *measured = Some(MeasuredRoad { from, to, graph_changed, distance });
+                                distance
+                            }
+                        }
+                    }
+                    _ => f32::INFINITY,
+                };
+                let road = if road.is_finite() { format!("{:.0}", road) } else { "unreachable".to_string() };
+                let readout = format!("Straight {:.0}, by road {}", a.distance(b), road);
+                if text.0 != readout {
+                    text.0 = readout;
+                }
+                return;
+            }
+        }
+        _ => {}
+    }
+    if !text.0.is_empty() {
+        text.0.clear();
+    }
+}
@@ -0,0 +1,527 @@
+//! Bus and tram lines.
+//!
+//! A line is an ordered list of stops on the road graph. Vehicles leave the first
+//! stop every `headway` minutes, drive out to the last stop and back, and pick up
+//! passengers waiting at the stops. The transit tool (T, again to switch between bus
+//! and tram) draws a line: click the nodes to stop at, Backspace removes the last
+//! stop and Enter creates it.
+
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    city::{
+        citizens::TripEnded,
+        clock::SimClock,
+        graph::{GraphSettled, RoadGraph},
+        tools::Tool,
+        vehicles::{spawn_vehicle, NodeReached, Vehicle},
+    },
+    common::StageSelect,
+    game::{CityEntity, Draggable, OnGameScreen, PlayState, NODE_RADIUS},
+    menus::ui::TEXT_COLOR,
+    rng::SimpleRng,
+    settings::bindings::{binding_name, Actions, InputAction, KeyBindings},
+};
+
+/// Passengers showing up at each stop per simulated minute
+const PASSENGER_RATE: f32 = 0.3;
+/// Minutes a vehicle waits at a stop where people get on or off
+const STOP_DWELL: f32 = 0.5;
+
+const LINE_COLORS: [Color; 5] = [
+    Color::srgb(0.9, 0.2, 0.2),
+    Color::srgb(0.1, 0.6, 0.2),
+    Color::srgb(0.95, 0.6, 0.1),
+    Color::srgb(0.6, 0.2, 0.8),
+    Color::srgb(0.1, 0.7, 0.8),
+];
+
+pub fn transit_plugin(app: &mut App) {
+    app
+        .init_resource::<TransitBrush>()
+        .add_systems(OnEnter(StageSelect::Game), transit_panel_setup)
+        .add_systems(
+            Update,
+            (
+                remove_bulldozed_stops_system,
+                refresh_transit_routes_system,
+                dispatch_transit_system,
+                generate_passengers_system,
+                transit_stop_system,
+                update_transit_panel_system,
+                draw_transit_lines_system,
+            )
+                .chain()
+                .run_if(in_state(PlayState::Play)),
+        )
+        .add_systems(
+            Update,
+            (transit_brush_system, draw_transit_brush_system)
+                .chain()
+                .run_if(in_state(PlayState::Play).and(in_state(Tool::Transit))),
+        );
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransitMode {
+    #[default]
+    Bus,
+    Tram,
+}
+
+impl TransitMode {
+    /// World units per simulated minute
+    pub fn speed(&self) -> f32 {
+        match self {
+            TransitMode::Bus => 330.0,
+            TransitMode::Tram => 400.0,
+        }
+    }
+
+    pub fn capacity(&self) -> u32 {
+        match self {
+            TransitMode::Bus => 60,
+            TransitMode::Tram => 150,
+        }
+    }
+}
+
+#[derive(Component, Debug, Clone)]
+pub struct TransitLine {
+    pub name: String,
+    pub mode: TransitMode,
+    pub color: Color,
+    /// Nodes the line stops at, in order
+    pub stops: Vec<Entity>,
+    /// Every node driven through, out along the stops and back again,
+    /// empty while the stops can't be connected
+    pub route: Vec<Entity>,
+    /// For each entry of `route`, the stop served there if any
+    pub route_stops: Vec<Option<usize>>,
+    /// Simulated minutes between departures
+    pub headway: f32,
+    pub capacity: u32,
+    pub next_departure: f32,
+    /// Passengers waiting at each stop
+    pub waiting: Vec<Vec<Passenger>>,
+    /// Total number of boardings
+    pub ridership: u32,
+}
+
+impl TransitLine {
+    pub fn new(name: String, mode: TransitMode, color: Color, stops: Vec<Entity>) -> Self {
+        Self {
+            name,
+            mode,
+            color,
+            waiting: vec![vec![]; stops.len()],
+            stops,
+            route: vec![],
+            route_stops: vec![],
+            headway: 10.0,
+            capacity: mode.capacity(),
+            next_departure: 0.0,
+            ridership: 0,
+        }
+    }
+
+    /// Routes the line over the road graph, out through every stop and back
+    pub fn plan_route(&mut self, graph: &RoadGraph) -> bool {
+        self.route.clear();
+        self.route_stops.clear();
+
+        let order: Vec<usize> = (0..self.stops.len()).chain((0..self.stops.len().saturating_sub(1)).rev()).collect();
+        for leg in order.windows(2) {
+            let (Some(&from), Some(&to)) = (
+                graph.index.get(&self.stops[leg[0]]),
+                graph.index.get(&self.stops[leg[1]]),
+            ) else {
+                self.route.clear();
+                return false;
+            };
+            let Some(path) = graph.shortest_path(from, to) else {
+                self.route.clear();
+                return false;
+            };
+
+            // legs share their end node with the start of the next one
+            if self.route.is_empty() {
+                self.route.push(graph.nodes[path[0]]);
+                self.route_stops.push(Some(leg[0]));
+            }
+            for (i, node) in path.iter().enumerate().skip(1) {
+                self.route.push(graph.nodes[*node]);
+                self.route_stops.push((i == path.len() - 1).then_some(leg[1]));
+            }
+        }
+        !self.route.is_empty()
+    }
+
+    /// Keeps the stops that have a new index in `remap`, passengers waiting at or
+    /// heading for a dropped stop are returned. The route is planned again once the
+    /// road graph has been rebuilt.
+    fn remove_stops(&mut self, remap: &[Option<usize>]) -> Vec<Passenger> {
+        let mut stranded = vec![];
+        let mut kept = remap.iter();
+        self.stops.retain(|_| kept.next().unwrap().is_some());
+        let waiting = std::mem::replace(&mut self.waiting, vec![vec![]; self.stops.len()]);
+        for (stop, passengers) in waiting.into_iter().enumerate() {
+            for passenger in passengers {
+                match (remap[stop], remap[passenger.destination]) {
+                    (Some(stop), Some(destination)) => self.waiting[stop].push(Passenger { destination, ..passenger }),
+                    _ => stranded.push(passenger),
+                }
+            }
+        }
+        self.route.clear();
+        self.route_stops.clear();
+        stranded
+    }
+}
+
+/// A transit line as stored in a `CityPlan`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransitLinePlan {
+    pub name: String,
+    pub mode: TransitMode,
+    pub color: Color,
+    /// Indices of the stops in `CityPlan::nodes`
+    pub stops: Vec<usize>,
+    pub headway: f32,
+}
+
+impl TransitLinePlan {
+    /// Line with its stops looked up in the spawned nodes, the route is planned once
+    /// the road graph has been rebuilt
+    pub fn to_line(&self, nodes: &[Entity]) -> TransitLine {
+        let stops = self.stops.iter().map(|stop| nodes[*stop]).collect();
+        TransitLine { headway: self.headway, ..TransitLine::new(self.name.clone(), self.mode, self.color, stops) }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Passenger {
+    /// Index of the stop they get off at
+    pub destination: usize,
+    /// Set for citizens on their way somewhere, other riders are background demand
+    pub citizen: Option<Entity>,
+}
+
+#[derive(Component, Debug, Clone)]
+pub struct TransitVehicle {
+    pub line: Entity,
+    pub passengers: Vec<Passenger>,
+    /// The line's `route_stops` when the vehicle set out, its route is a copy from
+    /// then too and replanning the line must not shift where it stops
+    pub route_stops: Vec<Option<usize>>,
+}
+
+// Stops on a bulldozed node leave their line, a line left with fewer than two stops
+// goes. Anyone waiting at or riding to a lost stop gets off where they are.
+fn remove_bulldozed_stops_system(
+    mut commands: Commands,
+    mut removed_nodes: RemovedComponents<Draggable>,
+    mut trips_ended: EventWriter<TripEnded>,
+    mut line_q: Query<(Entity, &mut TransitLine)>,
+    mut vehicle_q: Query<&mut TransitVehicle>,
+) {
+    let removed: HashSet<Entity> = removed_nodes.read().collect();
+    if removed.is_empty() {
+        return;
+    }
+    for (line_entity, mut line) in &mut line_q {
+        if !line.stops.iter().any(|stop| removed.contains(stop)) {
+            continue;
+        }
+        let mut next = 0;
+        let remap: Vec<Option<usize>> = line
+            .stops
+            .iter()
+            .map(|stop| {
+                (!removed.contains(stop)).then(|| {
+                    next += 1;
+                    next - 1
+                })
+            })
+            .collect();
+
+        let mut stranded = line.remove_stops(&remap);
+        for mut vehicle in vehicle_q.iter_mut().filter(|vehicle| vehicle.line == line_entity) {
+            for stop in &mut vehicle.route_stops {
+                *stop = stop.and_then(|stop| remap[stop]);
+            }
+            let (riding, dropped): (Vec<Passenger>, Vec<Passenger>) =
+                vehicle.passengers.iter().partition(|p| remap[p.destination].is_some());
+            vehicle.passengers = riding.into_iter().map(|p| Passenger { destination: remap[p.destination].unwrap(), ..p }).collect();
+            stranded.extend(dropped);
+        }
+        if line.stops.len() < 2 {
+            stranded.extend(line.waiting.drain(..).flatten());
+            commands.entity(line_entity).despawn_recursive();
+        }
+        for citizen in stranded.iter().filter_map(|p| p.citizen) {
+            trips_ended.send(TripEnded { citizen });
+        }
+    }
+}
+
+// Replans once a change to the graph has settled, not on every frame of a drag
+fn refresh_transit_routes_system(
+    mut graph_settled: GraphSettled,
+    graph: Res<RoadGraph>,
+    mut line_q: Query<&mut TransitLine>,
+) {
+    if !graph_settled.changed() {
+        return;
+    }
+    for mut line in &mut line_q {
+        if !line.plan_route(&graph) {
+            warn!("{} can no longer reach all of its stops", line.name);
+        }
+    }
+}
+
+fn dispatch_transit_system(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    clock: Res<SimClock>,
+    graph: Res<RoadGraph>,
+    mut line_q: Query<(Entity, &mut TransitLine)>,
+) {
+    for (line_entity, mut line) in &mut line_q {
+        if line.next_departure > clock.minutes {
+            continue;
+        }
+        line.next_departure = clock.minutes + line.headway;
+
+        let Some(start) = line.route.first().and_then(|n| graph.index.get(n)) else { continue };
+        let vehicle = spawn_vehicle(
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            Vehicle::new(line.route.clone(), line.mode.speed()),
+            graph.positions[*start],
+            line.color,
+        );
+        commands.entity(vehicle).insert((
+            OnGameScreen,
+            CityEntity,
+            TransitVehicle { line: line_entity, passengers: vec![], route_stops: line.route_stops.clone() },
+        ));
+    }
+}
+
+fn generate_passengers_system(
+    clock: Res<SimClock>,
+    mut rng: ResMut<SimpleRng>,
+    mut line_q: Query<&mut TransitLine>,
+) {
+    for mut line in &mut line_q {
+        let stops = line.stops.len();
+        if stops < 2 || line.route.is_empty() {
+            continue;
+        }
+        for stop in 0..stops {
+            if rng.next_scaled() < PASSENGER_RATE * clock.delta {
+                let destination = (stop + 1 + rng.next_u32() as usize % (stops - 1)) % stops;
+                line.waiting[stop].push(Passenger { destination, citizen: None });
+            }
+        }
+    }
+}
+
+// Passengers get off at their stop and the ones waiting get on while there is room
+fn transit_stop_system(
+    mut commands: Commands,
+    mut reached: EventReader<NodeReached>,
+    mut trips_ended: EventWriter<TripEnded>,
+    mut vehicle_q: Query<(&mut Vehicle, &mut TransitVehicle)>,
+    mut line_q: Query<&mut TransitLine>,
+) {
+    for event in reached.read() {
+        let Ok((mut vehicle, mut transit)) = vehicle_q.get_mut(event.vehicle) else { continue };
+        let Ok(mut line) = line_q.get_mut(transit.line) else {
+            leave_service(&mut commands, &mut trips_ended, event.vehicle, &transit);
+            continue;
+        };
+
+        if let Some(Some(stop)) = transit.route_stops.get(event.index).copied() {
+            let (alighting, staying): (Vec<Passenger>, Vec<Passenger>) =
+                transit.passengers.iter().partition(|p| p.destination == stop);
+            transit.passengers = staying;
+            let alighted = alighting.len();
+            for citizen in alighting.iter().filter_map(|p| p.citizen) {
+                trips_ended.send(TripEnded { citizen });
+            }
+
+            // only board people whose stop is still ahead on this run
+            let ahead: Vec<usize> = transit.route_stops[event.index + 1..].iter().flatten().copied().collect();
+            let mut room = (line.capacity as usize).saturating_sub(transit.passengers.len());
+            let mut boarding = 0;
+            let mut waiting = std::mem::take(&mut line.waiting[stop]);
+            waiting.retain(|passenger| {
+                if room > 0 && ahead.contains(&passenger.destination) {
+                    transit.passengers.push(*passenger);
+                    room -= 1;
+                    boarding += 1;
+                    false
+                } else {
+                    true
+                }
+            });
+            line.waiting[stop] = waiting;
+            line.ridership += boarding as u32;
+
+            if alighted + boarding > 0 {
+                vehicle.dwell = STOP_DWELL;
+            }
+        }
+
+        if event.last {
+            leave_service(&mut commands, &mut trips_ended, event.vehicle, &transit);
+        }
+    }
+}
+
+/// Takes a vehicle off the line, whoever is still aboard gets off where it stands
+fn leave_service(
+    commands: &mut Commands,
+    trips_ended: &mut EventWriter<TripEnded>,
+    vehicle: Entity,
+    transit: &TransitVehicle,
+) {
+    for citizen in transit.passengers.iter().filter_map(|p| p.citizen) {
+        trips_ended.send(TripEnded { citizen });
+    }
+    commands.entity(vehicle).despawn_recursive();
+}
+
+fn draw_transit_lines_system(graph: Res<RoadGraph>, line_q: Query<&TransitLine>, mut gizmos: Gizmos) {
+    for line in &line_q {
+        let points = line.route.iter().filter_map(|n| graph.index.get(n)).map(|i| graph.positions[*i]);
+        gizmos.linestrip_2d(points, line.color);
+        for stop in line.stops.iter().filter_map(|n| graph.index.get(n)) {
+            gizmos.circle_2d(graph.positions[*stop], NODE_RADIUS * 1.5, line.color);
+        }
+    }
+}
+
+// Tag component for the ridership panel text
+#[derive(Component)]
+struct TransitPanelText;
+
+fn transit_panel_setup(mut commands: Commands) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.0),
+                right: Val::Px(10.0),
+                padding: UiRect::all(Val::Px(8.0)),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+            OnGameScreen,
+        ))
+        .with_child((
+            TransitPanelText,
+            Text::new(""),
+            TextFont { font_size: 16.0, ..default() },
+            TextColor(TEXT_COLOR),
+        ));
+}
+
+fn update_transit_panel_system(
+    line_q: Query<(Entity, &TransitLine)>,
+    vehicle_q: Query<&TransitVehicle>,
+    mut text_q: Query<&mut Text, With<TransitPanelText>>,
+    bindings: Res<KeyBindings>,
+) {
+    let Ok(mut text) = text_q.get_single_mut() else { return };
+
+    let mut lines: Vec<_> = line_q.iter().collect();
+    lines.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name));
+
+    let mut report = String::from("Transit ridership");
+    if lines.is_empty() {
+        let key = binding_name(bindings.binding(InputAction::TransitTool));
+        report.push_str(&format!("\nno lines yet, press {key} to draw one"));
+    }
+    for (entity, line) in lines {
+        let on_board: usize = vehicle_q.iter().filter(|v| v.line == entity).map(|v| v.passengers.len()).sum();
+        let waiting: usize = line.waiting.iter().map(Vec::len).sum();
+        report.push_str(&format!(
+            "\n{} ({:?}): {} riders, {} on board, {} waiting",
+            line.name, line.mode, line.ridership, on_board, waiting
+        ));
+    }
+    text.0 = report;
+}
+
+/// Transit line being drawn with the transit tool
+#[derive(Resource, Default, Debug)]
+pub struct TransitBrush {
+    pub mode: TransitMode,
+    pub stops: Vec<Entity>,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn transit_brush_system(
+    mut commands: Commands,
+    actions: Actions,
+    buttons: Res<ButtonInput<MouseButton>>,
+    windows: Query<&Window>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    graph: Res<RoadGraph>,
+    clock: Res<SimClock>,
+    line_q: Query<&TransitLine>,
+    mut brush: ResMut<TransitBrush>,
+) {
+    if buttons.just_pressed(MouseButton::Left) {
+        let Ok((camera, cam_transform)) = camera_q.get_single() else { return };
+        let Ok(window) = windows.get_single() else { return };
+        let Some(cursor) = window.cursor_position() else { return };
+        let Ok(world_pos) = camera.viewport_to_world_2d(cam_transform, cursor) else { return };
+
+        if let Some(node) = graph.nearest_node(world_pos, NODE_RADIUS * 2.0) {
+            let node = graph.nodes[node];
+            if brush.stops.last() != Some(&node) {
+                brush.stops.push(node);
+            }
+        }
+    }
+
+    if actions.just_pressed(InputAction::UndoPoint) {
+        brush.stops.pop();
+    }
+
+    if actions.just_pressed(InputAction::ConfirmDrawing) && brush.stops.len() >= 2 {
+        let count = line_q.iter().count();
+        let mut line = TransitLine::new(
+            format!("Line {}", count + 1),
+            brush.mode,
+            LINE_COLORS[count % LINE_COLORS.len()],
+            brush.stops.clone(),
+        );
+        if !line.plan_route(&graph) {
+            warn!("{} has stops that are not connected by road", line.name);
+            return;
+        }
+        line.next_departure = clock.minutes;
+        commands.spawn((OnGameScreen, CityEntity, line));
+        brush.stops.clear();
+    }
+}
+
+fn draw_transit_brush_system(brush: Res<TransitBrush>, graph: Res<RoadGraph>, mut gizmos: Gizmos) {
+    let color = Color::srgb(1.0, 1.0, 0.3);
+    let stops: Vec<Vec2> = brush.stops.iter().filter_map(|n| graph.index.get(n)).map(|i| graph.positions[*i]).collect();
+    gizmos.linestrip_2d(stops.iter().copied(), color);
+    for stop in stops {
+        gizmos.circle_2d(stop, NODE_RADIUS * 1.5, color);
+    }
+}
@@ -0,0 +1,128 @@
+//! Vehicle agents driving along a route of road nodes.
+//!
+//! Movement is shared by every kind of vehicle, owners react to `NodeReached`
+//! to stop at a node (by setting `dwell`) or to despawn the vehicle at the end.
+
+use bevy::prelude::*;
+
+use crate::{
+    city::clock::SimClock,
+    game::{Draggable, PlayState, NODE_RADIUS},
+};
+
+/// Held vehicles wait this far from the center of the node
+pub const STOP_LINE: f32 = NODE_RADIUS * 2.0;
+
+pub fn vehicles_plugin(app: &mut App) {
+    app
+        .add_event::<NodeReached>()
+        .add_systems(Update, move_vehicles_system.run_if(in_state(PlayState::Play)));
+}
+
+#[derive(Component, Debug, Clone)]
+pub struct Vehicle {
+    /// Road nodes to drive through in order
+    pub route: Vec<Entity>,
+    /// Index in `route` of the node the vehicle is heading to
+    pub next: usize,
+    /// World units per simulated minute
+    pub speed: f32,
+    /// Simulated minutes left standing still
+    pub dwell: f32,
+    /// Whether the next node's intersection control makes it wait at the stop line
+    pub held: bool,
+}
+
+impl Vehicle {
+    pub fn new(route: Vec<Entity>, speed: f32) -> Self {
+        Self { route, next: 0, speed, dwell: 0.0, held: false }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.next >= self.route.len()
+    }
+}
+
+/// Sent every time a vehicle arrives at a node of its route
+#[derive(Event, Debug, Clone, Copy)]
+pub struct NodeReached {
+    pub vehicle: Entity,
+    pub node: Entity,
+    /// Index of the node in the vehicle's route
+    pub index: usize,
+    /// Whether this was the end of the route
+    pub last: bool,
+}
+
+/// Vehicles are drawn as small rectangles of this size
+pub const VEHICLE_SIZE: Vec2 = Vec2::new(16.0, 8.0);
+
+pub fn spawn_vehicle(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<ColorMaterial>,
+    vehicle: Vehicle,
+    position: Vec2,
+    color: Color,
+) -> Entity {
+    commands
+        .spawn((
+            vehicle,
+            Mesh2d(meshes.add(Rectangle::new(VEHICLE_SIZE.x, VEHICLE_SIZE.y))),
+            MeshMaterial2d(materials.add(color)),
+            Transform::from_translation(position.extend(60.0)),
+        ))
+        .id()
+}
+
+pub fn move_vehicles_system(
+    clock: Res<SimClock>,
+    mut vehicle_q: Query<(Entity, &mut Vehicle, &mut Transform), Without<Draggable>>,
+    node_q: Query<&Transform, With<Draggable>>,
+    mut reached: EventWriter<NodeReached>,
+) {
+    for (entity, mut vehicle, mut transform) in &mut vehicle_q {
+        if vehicle.finished() {
+            continue;
+        }
+
+        let mut minutes = clock.delta;
+        if vehicle.dwell > 0.0 {
+            let waited = vehicle.dwell.min(minutes);
+            vehicle.dwell -= waited;
+            minutes -= waited;
+        }
+
+        let node = vehicle.route[vehicle.next];
+        let Ok(target) = node_q.get(node) else {
+            // the road under it is gone, give up on the route
+            let index = vehicle.next;
+            vehicle.next = vehicle.route.len();
+            reached.send(NodeReached { vehicle: entity, node, index, last: true });
+            continue;
+        };
+
+        let position = transform.translation.truncate();
+        let to_target = target.translation.truncate() - position;
+        let mut step = vehicle.speed * minutes;
+        if vehicle.held {
+            step = step.min((to_target.length() - STOP_LINE).max(0.0));
+        }
+
+        if to_target.length() <= step && !vehicle.held {
+            transform.translation = target.translation.truncate().extend(transform.translation.z);
+            let index = vehicle.next;
+            vehicle.next += 1;
+            reached.send(NodeReached {
+                vehicle: entity,
+                node,
+                index,
+                last: vehicle.finished(),
+            });
+        } else if step > 0.0 {
+            let direction = to_target.normalize();
+            transform.translation += (direction * step).extend(0.0);
+            transform.rotation = Quat::from_rotation_z(direction.y.atan2(direction.x));
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//! Rivers and lakes.
+//!
+//! Water is a resource of polylines and polygons. It is drawn into a transparent
+//! image laid over the terrain, nothing can be placed on it and roads crossing it
+//! become bridges (see `roads`). Besides the generator, water can be drawn by hand
+//! with the water tool (R, again to switch between river and lake): left clicks add
+//! points, Backspace removes the last one and Enter finishes.
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    city::geometry::{point_in_polygon, point_segment_distance, segment_distance, segment_hits_polygon},
+    city::tools::Tool,
+    game::{CityEntity, OnGameScreen, PlayState},
+    rng::SimpleRng,
+    settings::bindings::{Actions, InputAction},
+};
+
+const WATER_SEED_SALT: u64 = 0x51DE_77A7_E4B0_0C2B;
+const WATER_COLOR: [u8; 4] = [40, 90, 170, 220];
+/// World units per pixel of the water image
+const WATER_PIXEL: f32 = 10.0;
+
+pub fn water_plugin(app: &mut App) {
+    app
+        .init_resource::<Water>()
+        .init_resource::<WaterBrush>()
+        .add_systems(Update, sync_water_surface_system.run_if(resource_changed::<Water>))
+        .add_systems(
+            Update,
+            (water_brush_system, draw_water_brush_system)
+                .chain()
+                .run_if(in_state(PlayState::Play).and(in_state(Tool::Water))),
+        );
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct River {
+    pub points: Vec<Vec2>,
+    pub width: f32,
+}
+
+#[derive(Resource, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Water {
+    pub rivers: Vec<River>,
+    /// Closed polygons
+    pub lakes: Vec<Vec<Vec2>>,
+}
+
+impl Water {
+    /// One meandering river across the map and a couple of lakes
+    pub fn generate(seed: u64, map_size: Vec2) -> Self {
+        let mut rng = SimpleRng::new(seed ^ WATER_SEED_SALT);
+        let half = map_size / 2.0;
+
+        let mut points = vec![];
+        let mut pos = Vec2::new(-half.x, rng.next_range(-half.y, half.y) * 0.6);
+        let mut heading = rng.next_range(-0.4, 0.4);
+        while pos.x < half.x {
+            points.push(pos);
+            heading = (heading + rng.next_range(-0.5, 0.5)).clamp(-1.0, 1.0);
+            pos += Vec2::from_angle(heading) * 150.0;
+            pos.y = pos.y.clamp(-half.y, half.y);
+        }
+        points.push(pos);
+
+        let lakes = (0..1 + rng.next_u32() % 2)
+            .map(|_| {
+                let center = Vec2::new(rng.next_range(-half.x, half.x), rng.next_range(-half.y, half.y)) * 0.7;
+                let radius = rng.next_range(150.0, 300.0);
+                (0..12)
+                    .map(|i| {
+                        let angle = std::f32::consts::TAU * i as f32 / 12.0;
+                        center + Vec2::from_angle(angle) * radius * rng.next_range(0.7, 1.1)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            rivers: vec![River { points, width: 60.0 }],
+            lakes,
+        }
+    }
+
+    /// Whether a position is on water, used to block placing things there
+    pub fn contains(&self, pos: Vec2) -> bool {
+        self.lakes.iter().any(|lake| point_in_polygon(pos, lake))
+            || self.rivers.iter().any(|river| {
+                river
+                    .points
+                    .windows(2)
+                    .any(|w| point_segment_distance(pos, w[0], w[1]) <= river.width / 2.0)
+            })
+    }
+
+    /// Whether a straight road from a to b would have to cross water
+    pub fn crosses(&self, a: Vec2, b: Vec2) -> bool {
+        self.lakes.iter().any(|lake| segment_hits_polygon(a, b, lake))
+            || self.rivers.iter().any(|river| {
+                river
+                    .points
+                    .windows(2)
+                    .any(|w| segment_distance(a, b, w[0], w[1]) <= river.width / 2.0)
+            })
+    }
+
+    /// Distance to the nearest shore, 0.0 on water and infinite without any water
+    pub fn distance(&self, pos: Vec2) -> f32 {
+        if self.contains(pos) {
+            return 0.0;
+        }
+        let lakes = self.lakes.iter().flat_map(|lake| {
+            lake.iter()
+                .zip(lake.iter().cycle().skip(1))
+                .map(|(a, b)| point_segment_distance(pos, *a, *b))
+        });
+        let rivers = self.rivers.iter().flat_map(|river| {
+            river
+                .points
+                .windows(2)
+                .map(|w| point_segment_distance(pos, w[0], w[1]) - river.width / 2.0)
+        });
+        lakes.chain(rivers).fold(f32::INFINITY, f32::min)
+    }
+
+    pub fn to_image(&self, origin: Vec2, size: Vec2) -> Image {
+        let width = (size.x / WATER_PIXEL).max(1.0) as u32;
+        let height = (size.y / WATER_PIXEL).max(1.0) as u32;
+        let mut data = Vec::with_capacity((width * height * 4) as usize);
+
+        for y in (0..height).rev() {
+            for x in 0..width {
+                let pos = origin + (Vec2::new(x as f32, y as f32) + 0.5) * WATER_PIXEL;
+                if self.contains(pos) {
+                    data.extend_from_slice(&WATER_COLOR);
+                } else {
+                    data.extend_from_slice(&[0, 0, 0, 0]);
+                }
+            }
+        }
+
+        Image::new(
+            Extent3d { width, height, depth_or_array_layers: 1 },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::RENDER_WORLD,
+        )
+    }
+
+    /// Area the water image has to cover
+    fn bounds(&self) -> Option<Rect> {
+        let mut points = self
+            .lakes
+            .iter()
+            .flatten()
+            .map(|p| (*p, 0.0))
+            .chain(self.rivers.iter().flat_map(|r| r.points.iter().map(|p| (*p, r.width))));
+        let (first, width) = points.next()?;
+        let mut rect = Rect::from_center_half_size(first, Vec2::splat(width));
+        for (p, width) in points {
+            rect = rect.union(Rect::from_center_half_size(p, Vec2::splat(width)));
+        }
+        Some(rect)
+    }
+}
+
+// Tag component for the sprite showing all the water
+#[derive(Component)]
+pub struct WaterSurface;
+
+// Redraws the water sprite whenever the water changes
+fn sync_water_surface_system(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    water: Res<Water>,
+    surface_q: Query<Entity, With<WaterSurface>>,
+) {
+    for entity in &surface_q {
+        commands.entity(entity).despawn_recursive();
+    }
+    let Some(bounds) = water.bounds() else { return };
+
+    commands.spawn((
+        OnGameScreen,
+        CityEntity,
+        WaterSurface,
+        Sprite {
+            image: images.add(water.to_image(bounds.min, bounds.size())),
+            custom_size: Some(bounds.size()),
+            ..default()
+        },
+        Transform::from_translation(bounds.center().extend(-90.0)),
+    ));
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WaterBrushKind {
+    #[default]
+    River,
+    Lake,
+}
+
+/// Water being drawn by hand with the water tool
+#[derive(Resource, Default, Debug)]
+pub struct WaterBrush {
+    pub kind: WaterBrushKind,
+    pub points: Vec<Vec2>,
+}
+
+fn water_brush_system(
+    actions: Actions,
+    buttons: Res<ButtonInput<MouseButton>>,
+    windows: Query<&Window>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    mut brush: ResMut<WaterBrush>,
+    mut water: ResMut<Water>,
+) {
+    if buttons.just_pressed(MouseButton::Left) {
+        let Ok((camera, cam_transform)) = camera_q.get_single() else { return };
+        let Ok(window) = windows.get_single() else { return };
+        let Some(cursor) = window.cursor_position() else { return };
+        let Ok(world_pos) = camera.viewport_to_world_2d(cam_transform, cursor) else { return };
+        brush.points.push(world_pos);
+    }
+
+    if actions.just_pressed(InputAction::UndoPoint) {
+        brush.points.pop();
+    }
+
+    if actions.just_pressed(InputAction::ConfirmDrawing) {
+        let points = std::mem::take(&mut brush.points);
+        match brush.kind {
+            WaterBrushKind::River if points.len() >= 2 => water.rivers.push(River { points, width: 60.0 }),
+            WaterBrushKind::Lake if points.len() >= 3 => water.lakes.push(points),
+            _ => brush.points = points,
+        }
+    }
+}
+
+fn draw_water_brush_system(brush: Res<WaterBrush>, mut gizmos: Gizmos) {
+    let color = Color::srgb(0.3, 0.6, 1.0);
+    match brush.kind {
+        WaterBrushKind::River => gizmos.linestrip_2d(brush.points.iter().copied(), color),
+        WaterBrushKind::Lake => gizmos.linestrip_2d(
+            brush.points.iter().chain(brush.points.first()).copied(),
+            color,
+        ),
+    }
+}
@@ -0,0 +1,132 @@
+//! Zoned lots: homes, shops and industry placed along the roads.
+//!
+//! Every lot is attached to a road node, that node is where its trips start and end.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    city::{generator::CityPlan, geometry::point_segment_distance},
+    rng::SimpleRng,
+};
+
+const ZONE_SEED_SALT: u64 = 0x20AE_5EED_0F1E_1075;
+pub const LOT_SIZE: f32 = 50.0;
+/// Distance from the road center line to the lot center
+const LOT_OFFSET: f32 = 45.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect, Serialize, Deserialize)]
+pub enum ZoneKind {
+    Residential,
+    Commercial,
+    Industrial,
+}
+
+impl ZoneKind {
+    pub fn color(&self) -> Color {
+        match self {
+            ZoneKind::Residential => Color::srgba(0.2, 0.75, 0.3, 0.8),
+            ZoneKind::Commercial => Color::srgba(0.2, 0.45, 0.9, 0.8),
+            ZoneKind::Industrial => Color::srgba(0.9, 0.75, 0.2, 0.8),
+        }
+    }
+}
+
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq)]
+#[reflect(Component)]
+pub struct Zone {
+    pub kind: ZoneKind,
+    /// Road node the lot is connected to
+    pub node: Entity,
+    pub level: u32,
+}
+
+impl Zone {
+    /// Homes on residential lots, jobs on the others
+    pub fn capacity(&self) -> u32 {
+        let per_level = match self.kind {
+            ZoneKind::Residential => 10,
+            ZoneKind::Commercial => 8,
+            ZoneKind::Industrial => 12,
+        };
+        per_level * self.level
+    }
+
+    pub fn homes(&self) -> u32 {
+        if self.kind == ZoneKind::Residential { self.capacity() } else { 0 }
+    }
+
+    pub fn jobs(&self) -> u32 {
+        if self.kind == ZoneKind::Residential { 0 } else { self.capacity() }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LotPlan {
+    pub pos: Vec2,
+    pub kind: ZoneKind,
+    /// Index of the road node in `CityPlan::nodes`
+    pub node: usize,
+    pub level: u32,
+}
+
+/// Lines both sides of the roads with lots, commerce downtown and a sprinkle of industry
+/// further out. Lots never overlap roads, nodes or each other, and `keep` can veto a spot.
+pub fn place_lots(plan: &mut CityPlan, seed: u64, density: f32, keep: impl Fn(Vec2) -> bool) {
+    let mut rng = SimpleRng::new(seed ^ ZONE_SEED_SALT);
+    let radius = plan.nodes.iter().map(|p| p.length()).fold(1.0, f32::max);
+    let fill = 0.3 + 0.6 * density.clamp(0.0, 1.0);
+
+    for e in 0..plan.edges.len() {
+        let (a, b) = plan.edges[e];
+        let (pa, pb) = (plan.nodes[a], plan.nodes[b]);
+        if pa.distance(pb) < LOT_SIZE * 2.0 {
+            continue;
+        }
+        let normal = (pb - pa).normalize().perp();
+
+        for side in [-1.0, 1.0] {
+            if rng.next_scaled() > fill {
+                continue;
+            }
+            let pos = (pa + pb) / 2.0 + normal * side * LOT_OFFSET;
+            let blocked = !keep(pos)
+                || plan.lots.iter().any(|lot| lot.pos.distance(pos) < LOT_SIZE)
+                || plan.nodes.iter().any(|node| node.distance(pos) < LOT_SIZE)
+                || plan
+                    .edges
+                    .iter()
+                    .any(|&(x, y)| point_segment_distance(pos, plan.nodes[x], plan.nodes[y]) < LOT_SIZE * 0.6);
+            if blocked {
+                continue;
+            }
+
+            let kind = if pos.length() < radius * 0.25 && rng.next_scaled() < 0.8 {
+                ZoneKind::Commercial
+            } else if pos.length() > radius * 0.5 && rng.next_scaled() < 0.15 {
+                ZoneKind::Industrial
+            } else {
+                ZoneKind::Residential
+            };
+            let node = if pa.distance(pos) <= pb.distance(pos) { a } else { b };
+            plan.lots.push(LotPlan { pos, kind, node, level: 1 });
+        }
+    }
+}
+
+pub fn spawn_lot(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<ColorMaterial>,
+    zone: Zone,
+    position: Vec2,
+) -> Entity {
+    commands
+        .spawn((
+            zone,
+            Mesh2d(meshes.add(Rectangle::new(LOT_SIZE * 0.8, LOT_SIZE * 0.8))),
+            MeshMaterial2d(materials.add(zone.kind.color())),
+            Transform::from_translation(position.extend(20.0)),
+        ))
+        .id()
+}
@@ -1,21 +1,49 @@
+use std::path::Path;
+
 use bevy::{
     prelude::*,
-    input::{
-        ButtonInput,
-        keyboard::KeyCode,
-    },
+    input::ButtonInput,
     sprite::{Wireframe2dConfig, Wireframe2dPlugin}
 };
 
 use crate::{
     common::{despawn_screen,StageSelect},
-    settings::globals::{DisplayQuality, Volume},
+    settings::{
+        bindings::{Actions, InputAction},
+        globals::{DisplayQuality, Volume},
+    },
     rng::SimpleRng,
     // graphics::{ATTRIBUTE_BLEND_COLOR, CustomMaterial, DumbyMatrial},
     menus::{
         settings::SettingsState,
     },
     graphics::{graphics_plugin,CustomMaterial},
+    city::{
+        ambience::ambience_plugin,
+        assignment::assignment_plugin,
+        budget::budget_plugin,
+        camera::{camera_plugin, CameraBookmarks},
+        citizens::citizens_plugin,
+        clock::clock_plugin,
+        demand::demand_plugin,
+        graph::graph_plugin,
+        inspector::inspector_plugin,
+        minimap::minimap_plugin,
+        intersections::{intersections_plugin, ApproachCounts},
+        land_value::land_value_plugin,
+        transit::transit_plugin,
+        vehicles::vehicles_plugin,
+        generator::{generate_city, CityGenParams, CityPlan},
+        pollution::pollution_plugin,
+        render_quality::render_quality_plugin,
+        roads::{roads_plugin, Bridge, ConstructionRules, RoadType},
+        save::save_plugin,
+        services::{services_plugin, spawn_service, ServiceBuilding},
+        terrain::{spawn_terrain_background, terrain_plugin, Terrain},
+        tools::{select_tool_active, tools_plugin},
+        water::{water_plugin, Water},
+        zones::{place_lots, spawn_lot, Zone},
+    },
 };
 
 
@@ -29,21 +57,24 @@ pub enum PlayState {
 }
 
 
-/// trying to run some intresting shaders
+// trying to run some intresting shaders
 
 
 // This plugin will contain the game with an animated shader
 pub fn game_plugin(app: &mut App) {
     app
-        .add_plugins((graphics_plugin,Wireframe2dPlugin::default(),))
+        .add_plugins((graphics_plugin,Wireframe2dPlugin,terrain_plugin,water_plugin,roads_plugin,save_plugin,camera_plugin,ambience_plugin,render_quality_plugin))
+        .add_plugins((clock_plugin,graph_plugin,vehicles_plugin,transit_plugin,intersections_plugin,demand_plugin,assignment_plugin,citizens_plugin,services_plugin,pollution_plugin,land_value_plugin,budget_plugin,inspector_plugin,minimap_plugin,tools_plugin))
         .init_state::<PlayState>() 
         .insert_resource(DragTarget::default())
+        .init_resource::<Selection>()
+        .register_type::<Line>()
+        .init_resource::<CityGenParams>()
 
         .add_systems(OnEnter(StageSelect::Game), game_setup)
         .add_systems(Update, (
                 
-                camera_control_system_2d,
-                select_drag_target_system,
+                select_drag_target_system.run_if(select_tool_active),
                 apply_drag_target_system,
                 update_lines_system
         
@@ -57,6 +88,10 @@ pub fn game_plugin(app: &mut App) {
 #[derive(Component)]
 pub struct OnGameScreen;
 
+// Tag component for everything that belongs to the city itself, loading a save replaces these
+#[derive(Component)]
+pub struct CityEntity;
+
 
 #[derive(Component, Debug, Clone, Copy)]
 pub enum Draggable {
@@ -65,7 +100,8 @@ pub enum Draggable {
 }
 
 
-#[derive(Component)]
+#[derive(Component, Reflect)]
+#[reflect(Component)]
 pub struct Line {
     pub from: Entity,
     pub to: Entity,
@@ -78,19 +114,20 @@ fn line_between(a: &Vec2, b: &Vec2) -> (Vec2, f32, f32) {
     (mid, dir.y.atan2(dir.x), dir.length())
 }
 
-fn spawn_circle(
+pub fn spawn_circle(
     commands: &mut Commands,
     meshes: &mut Assets<Mesh>,
     materials: &mut Assets<ColorMaterial>,
     position: Vec2,
     z: f32,
+    radius: f32,
     color: Color,
 ) -> Entity {
-    let radius = 50.0;
     let mesh = meshes.add(Circle::new(radius));
     commands
         .spawn((
             OnGameScreen,
+            CityEntity,
             Draggable::Circle(radius),
             Mesh2d(mesh),
             MeshMaterial2d(materials.add(color)),
@@ -112,6 +149,7 @@ fn spawn_square(
     commands
         .spawn((
             OnGameScreen,
+            CityEntity,
             Draggable::Rect(rect / 2.0), // store half-extents for hit detection!
             Mesh2d(mesh),
             MeshMaterial2d(materials.add(color)),
@@ -121,7 +159,7 @@ fn spawn_square(
 }
 
 
-fn spawn_line(
+pub fn spawn_line(
     commands: &mut Commands,
     meshes: &mut Assets<Mesh>,
     materials: &mut Assets<ColorMaterial>,
@@ -129,12 +167,14 @@ fn spawn_line(
     b_entity: Entity,
     a_pos: Vec2,
     b_pos: Vec2,
-) {
+) -> Entity {
     let (mid, angle, length) = line_between(&a_pos, &b_pos);
     let mesh = meshes.add(Rectangle::new(length, 4.0));
     commands.spawn((
         OnGameScreen,
+        CityEntity,
         Line { from: a_entity, to: b_entity },
+        RoadType::default(),
         Mesh2d(mesh),
         MeshMaterial2d(materials.add(Color::BLACK)),
         Transform {
@@ -142,65 +182,135 @@ fn spawn_line(
             rotation: Quat::from_rotation_z(angle),
             ..Default::default()
         },
-    ));
+    )).id()
+}
+
+/// Generated cities put intersections a block apart, so they are drawn much smaller
+/// than the 50 px circles of the original demo. Stop lines, pick radii and overlays
+/// are all sized from this.
+pub const NODE_RADIUS: f32 = 12.0;
+pub const NODE_COLOR: Color = Color::srgb(0.15, 0.3, 0.9);
+
+/// Spawns the terrain, nodes, roads and lots described by a plan, returns the node
+/// entities in plan order
+pub fn spawn_city(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<ColorMaterial>,
+    images: &mut Assets<Image>,
+    rng: &mut SimpleRng,
+    plan: &CityPlan,
+    terrain: &Terrain,
+) -> Vec<Entity> {
+    let background = spawn_terrain_background(commands, images, terrain);
+    commands.entity(background).insert((OnGameScreen, CityEntity));
+
+    let z = 40.0;
+    let color1 = NODE_COLOR;
+    let color2 = Color::srgb(0.3, 0.2, 0.9);
+
+    let mut entities = vec![];
+    for (i, pos) in plan.nodes.iter().enumerate() {
+        let color = if i % 2 == 0 { color1 } else { color2 };
+        let z = z+rng.next_scaled();
+        let e = spawn_circle(commands, meshes, materials, *pos, z, NODE_RADIUS, color);
+        entities.push(e);
+    }
+
+    let mut lines = vec![];
+    for (a, b) in &plan.edges {
+        let line = spawn_line(
+            commands,
+            meshes,
+            materials,
+            entities[*a],
+            entities[*b],
+            plan.nodes[*a],
+            plan.nodes[*b],
+        );
+        lines.push(line);
+    }
+
+    for control in &plan.controls {
+        commands.entity(entities[control.node]).insert((control.control.to_control(&lines), ApproachCounts::default()));
+    }
+
+    for lot in &plan.lots {
+        let zone = Zone { kind: lot.kind, node: entities[lot.node], level: lot.level };
+        let e = spawn_lot(commands, meshes, materials, zone, lot.pos);
+        commands.entity(e).insert((OnGameScreen, CityEntity));
+    }
+
+    for line in &plan.transit {
+        commands.spawn((OnGameScreen, CityEntity, line.to_line(&entities)));
+    }
+
+    for service in &plan.services {
+        let building = ServiceBuilding { kind: service.kind, node: entities[service.node] };
+        spawn_service(commands, meshes, materials, building, plan.nodes[service.node]);
+    }
+
+    entities
 }
 
 // fn next_float( rng: &mut GlobalEntropy<WyRand>) ->f32{
 //     (rng.next_u32() as f64 /  u32::MAX as f64) as f32
 // }
 
+#[allow(clippy::too_many_arguments)]
 fn game_setup(
     mut commands: Commands,
     _display_quality: Res<DisplayQuality>,
     _volume: Res<Volume>,
 
     mut rng:ResMut<SimpleRng>,
+    city_params: Res<CityGenParams>,
+    rules: Res<ConstructionRules>,
 
 
     asset_server: Res<AssetServer>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut color_materials: ResMut<Assets<ColorMaterial>>,
+    mut images: ResMut<Assets<Image>>,
     mut custom_materials: ResMut<Assets<CustomMaterial>>,
     mut next_play_state: ResMut<NextState<PlayState>>,
 ) {
     next_play_state.set(PlayState::Play);
 
-    let z = 40.0;
-    let color1 = Color::srgb(0.15, 0.3, 0.9);
-    let color2 = Color::srgb(0.3, 0.2, 0.9);
-    let color3 = Color::srgb(0.9, 0.6, 0.3);
-
-    let positions = [
-        Vec2::new(0.0, 0.0),
-        Vec2::new(150.0, 80.0),
-        Vec2::new(150.0, -80.0),
-        Vec2::new(-150.0, 80.0),
-        Vec2::new(-150.0, -80.0),
-    ];
-
-    let mut entities = vec![];
-    for (i, pos) in positions.iter().enumerate() {
-        let color = if i % 2 == 0 { color1 } else { color2 };
-        let z = z+rng.next_scaled();
-        let e = spawn_circle(&mut commands, &mut meshes, &mut color_materials, *pos, z, color);
-        entities.push((e, *pos));
-    }
+    let terrain = match &city_params.heightmap {
+        Some(path) => Terrain::from_grayscale_png(Path::new(path), city_params.map_size).unwrap_or_else(|e| {
+            error!("{}", e);
+            Terrain::generate(city_params.seed, city_params.map_size)
+        }),
+        None => Terrain::generate(city_params.seed, city_params.map_size),
+    };
 
-    let pos = Vec2::new(-100.0, -150.0);
-    entities.push((spawn_square(&mut commands, &mut meshes, &mut color_materials, pos, 41.0, color3), pos));
-
-    // Draw edges from center node (0) to all others
-    for (target_entity, target_pos) in &entities[1..] {
-        spawn_line(
-            &mut commands,
-            &mut meshes,
-            &mut color_materials,
-            entities[0].0,
-            *target_entity,
-            entities[0].1,
-            *target_pos,
-        );
+    let water = Water::generate(city_params.seed, city_params.map_size);
+
+    // nothing gets built on water, and roads the terrain is too steep for are only
+    // kept where the network would fall apart without them
+    let mut plan = generate_city(&city_params);
+    plan.retain_nodes(|pos| !water.contains(pos));
+    plan.drop_edges_keeping_connected(|a, b| !rules.road_cost(&terrain, &water, a, b).buildable);
+    plan.drop_isolated_nodes();
+    place_lots(&mut plan, city_params.seed, city_params.density, |pos| !water.contains(pos));
+
+    let nodes = spawn_city(&mut commands, &mut meshes, &mut color_materials, &mut images, &mut rng, &plan, &terrain);
+
+    // The draggable square of the original demo, joined to the closest intersection
+    let square_pos = Vec2::new(-100.0, -150.0);
+    let closest = nodes
+        .iter()
+        .zip(&plan.nodes)
+        .min_by(|(_, a), (_, b)| a.distance_squared(square_pos).total_cmp(&b.distance_squared(square_pos)));
+    if let Some((node, node_pos)) = closest {
+        let square = spawn_square(&mut commands, &mut meshes, &mut color_materials, square_pos, 41.0, Color::srgb(0.9, 0.6, 0.3));
+        spawn_line(&mut commands, &mut meshes, &mut color_materials, *node, square, *node_pos, square_pos);
     }
+    commands.insert_resource(terrain);
+    commands.insert_resource(water);
+    // bookmarks belong to the city they were set in
+    commands.insert_resource(CameraBookmarks::default());
 
     // Custom shader rectangle
     commands.spawn((
@@ -209,7 +319,7 @@ fn game_setup(
             color: LinearRgba::RED,
             color_texture: Some(asset_server.load("bevy_examples/branding/icon.png")),
         })),
-        Transform::from_xyz(300.0, 100.0, z).with_scale(Vec3::splat(128.)),
+        Transform::from_xyz(300.0, 100.0, 40.0).with_scale(Vec3::splat(128.)),
     ));
 }
 
@@ -217,12 +327,17 @@ fn game_setup(
 #[derive(Resource, Default, Debug)]
 pub struct DragTarget(pub Option<Entity>);
 
+// Entity shown in the inspector, stays selected after the drag ends
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq)]
+pub struct Selection(pub Option<Entity>);
+
 
 fn select_drag_target_system(
     windows: Query<&Window>,
     buttons: Res<ButtonInput<MouseButton>>,
     camera_q: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
     draggable_q: Query<(Entity, &GlobalTransform, &Draggable)>,
+    ui_q: Query<&Interaction>,
     mut drag_target: ResMut<DragTarget>,
 ) {
     let Ok((camera, cam_transform)) = camera_q.get_single() else { return };
@@ -231,7 +346,8 @@ fn select_drag_target_system(
 
     let Ok(world_pos) = camera.viewport_to_world_2d(cam_transform, cursor) else { return };
 
-    if buttons.just_pressed(MouseButton::Left) {
+    // clicks on the HUD (minimap, panels) don't reach the city underneath
+    if buttons.just_pressed(MouseButton::Left) && ui_q.iter().all(|i| *i == Interaction::None) {
         let mut candidates = vec![];
 
         for (entity, global_transform, shape) in &draggable_q {
@@ -273,6 +389,7 @@ fn apply_drag_target_system(
     camera_q: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
     mut draggable_q: Query<&mut Transform, With<Draggable>>,
     drag_target: Res<DragTarget>,
+    water: Res<Water>,
 ) {
     let Some(target_entity) = drag_target.0 else { return };
 
@@ -294,18 +411,24 @@ fn apply_drag_target_system(
         let dt = 1.0 / 60.0; // could use Time.delta_seconds()
         let new_pos = current + (target - current) * ((speed * dt) as f32 ).min(1.0);
 
+        // can't drop things into water
+        if water.contains(new_pos) {
+            return;
+        }
+
         transform.translation.x = new_pos.x;
         transform.translation.y = new_pos.y;
     }
 }
 
 
+#[allow(clippy::type_complexity)]
 fn update_lines_system(
     mut meshes: ResMut<Assets<Mesh>>,
-    mut line_q: Query<(&Line, &mut Mesh2d, &mut Transform),Without<Draggable>>,
+    mut line_q: Query<(&Line, &mut Mesh2d, &mut Transform, &RoadType, Option<&Bridge>),Without<Draggable>>,
     transform_q: Query<&Transform, With<Draggable>>,
 ) {
-    for (line, mut mesh, mut transform) in &mut line_q {
+    for (line, mut mesh, mut transform, road_type, bridge) in &mut line_q {
         let Ok(from) = transform_q.get(line.from) else { continue };
         let Ok(to) = transform_q.get(line.to) else { continue };
 
@@ -314,7 +437,8 @@ fn update_lines_system(
 
         let (mid, angle, length) = line_between(&from_pos, &to_pos);
 
-        *mesh = Mesh2d(meshes.add(Rectangle::new(length, 4.0))); // Resize mesh
+        let width = road_type.width() * if bridge.is_some() { 2.0 } else { 1.0 }; // bridges are drawn wider
+        *mesh = Mesh2d(meshes.add(Rectangle::new(length, width))); // Resize mesh
         transform.translation = mid.extend(transform.translation.z); // Update position
         transform.rotation = Quat::from_rotation_z(angle); // Update rotation
     }
@@ -322,13 +446,13 @@ fn update_lines_system(
 
 
 fn toggle_settings_with_escape(
-    keys: Res<ButtonInput<KeyCode>>,
+    actions: Actions,
     play_state: Res<State<PlayState>>,
     settings_state: Res<State<SettingsState>>,
     mut next_play_state: ResMut<NextState<PlayState>>,
     mut next_settings_state: ResMut<NextState<SettingsState>>,
 ) {
-    if keys.just_pressed(KeyCode::Escape) {
+    if actions.just_pressed(InputAction::ToggleSettings) {
         match (play_state.get(), settings_state.get()) {
             (PlayState::Play, SettingsState::Disabled) => {
                 next_play_state.set(PlayState::Settings);
@@ -345,64 +469,9 @@ fn toggle_settings_with_escape(
 
 fn toggle_wireframe(
     mut wireframe_config: ResMut<Wireframe2dConfig>,
-    keyboard: Res<ButtonInput<KeyCode>>,
+    actions: Actions,
 ) {
-    if keyboard.just_pressed(KeyCode::Space) {
+    if actions.just_pressed(InputAction::ToggleWireframe) {
         wireframe_config.global = !wireframe_config.global;
     }
 }
-
-
-fn camera_control_system_2d(
-    time: Res<Time>,
-    keys: Res<ButtonInput<KeyCode>>,
-    mut query: Query<&mut Transform, With<Camera2d>>,
-) {
-    let mut transform = match query.get_single_mut() {
-        Ok(t) => t,
-        Err(_) => return,
-    };
-
-    let delta = time.delta_secs();
-    let speed = 300.0;
-    let zoom_speed: f32 = 1.5;
-
-    let mut direction = Vec3::ZERO;
-
-    if keys.pressed(KeyCode::KeyA) {
-        direction.x -= 1.0;
-    }
-    if keys.pressed(KeyCode::KeyD) {
-        direction.x += 1.0;
-    }
-    if keys.pressed(KeyCode::KeyW) {
-        direction.y += 1.0;
-    }
-    if keys.pressed(KeyCode::KeyS) {
-        direction.y -= 1.0;
-    }
-
-    if direction != Vec3::ZERO {
-        transform.translation += direction * speed * delta;
-    }
-
-
-     // Zoom (scale adjustment)
-    let mut scale_change = 0.0;
-    if keys.pressed(KeyCode::KeyQ) {
-        scale_change += 1.0;
-    }
-    if keys.pressed(KeyCode::KeyE) {
-        scale_change -= 1.0;
-    }
-    if scale_change != 0.0 {
-        let scale_delta = zoom_speed.powf(scale_change * delta);
-        transform.scale *= Vec3::splat(scale_delta);
-
-        // Optional: clamp zoom range
-        transform.scale = transform.scale.clamp(
-            Vec3::splat(0.1),
-            Vec3::splat(10.0),
-        );
-    }
-}
\ No newline at end of file
@@ -0,0 +1,53 @@
+//! Playing with a gamepad.
+//!
+//! The right stick moves the mouse cursor, and while playing A and B click the left
+//! and right mouse buttons, so every mouse driven tool works from the pad. The left
+//! stick and triggers move the camera (see `city::camera`) and the D-pad drives the
+//! menus (see `menus::navigation`).
+
+use bevy::{input::InputSystem, prelude::*, ui::UiSystem};
+
+use crate::game::PlayState;
+
+/// Cursor speed in pixels per second at full stick
+const CURSOR_SPEED: f32 = 900.0;
+
+pub fn gamepad_plugin(app: &mut App) {
+    app.add_systems(
+        PreUpdate,
+        (
+            virtual_cursor_system,
+            gamepad_click_system.run_if(in_state(PlayState::Play)),
+        )
+            .after(InputSystem)
+            .before(UiSystem::Focus),
+    );
+}
+
+// Moves the window's cursor with the right stick
+fn virtual_cursor_system(time: Res<Time>, gamepads: Query<&Gamepad>, mut windows: Query<&mut Window>) {
+    let stick = gamepads.iter().map(|gamepad| gamepad.right_stick()).sum::<Vec2>();
+    if stick == Vec2::ZERO {
+        return;
+    }
+    let Ok(mut window) = windows.get_single_mut() else { return };
+    let size = window.size();
+    let cursor = window.cursor_position().unwrap_or(size / 2.0);
+    // Stick up is screen up, window y points down
+    let moved = cursor + stick * Vec2::new(1.0, -1.0) * CURSOR_SPEED * time.delta_secs();
+    window.set_cursor_position(Some(moved.clamp(Vec2::ZERO, size)));
+}
+
+// A and B act as the mouse buttons, before the UI looks at the mouse
+fn gamepad_click_system(gamepads: Query<&Gamepad>, mut mouse: ResMut<ButtonInput<MouseButton>>) {
+    for gamepad in &gamepads {
+        for (pad, button) in [(GamepadButton::South, MouseButton::Left), (GamepadButton::East, MouseButton::Right)] {
+            if gamepad.just_pressed(pad) {
+                mouse.press(button);
+            }
+            if gamepad.just_released(pad) {
+                mouse.release(button);
+            }
+        }
+    }
+}
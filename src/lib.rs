@@ -4,3 +4,8 @@ pub mod game;
 pub mod rng;
 pub mod graphics;
 pub mod menus;
+pub mod city;
+pub mod notifications;
+pub mod gamepad;
+pub mod audio;
+pub mod music;
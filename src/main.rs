@@ -3,13 +3,12 @@
 //! settings for 5 seconds before going back to the menu.
 
 use city_simulation::rng;
-use bevy_framepace::debug::DiagnosticsPlugin;
 use iyes_perf_ui::PerfUiPlugin;
 use iyes_perf_ui::entries::PerfUiAllEntries;
 use std::path::Path;
 use bevy::{
     prelude::*,
-    diagnostic::{LogDiagnosticsPlugin,FrameTimeDiagnosticsPlugin}
+    diagnostic::FrameTimeDiagnosticsPlugin
 };
 
 
@@ -18,11 +17,17 @@ use bevy::{
 use city_simulation::{
     game,
     menus::{menu, splash},
+    notifications::notifications_plugin,
+    gamepad::gamepad_plugin,
+    audio::audio_plugin,
+    music::music_plugin,
     common::StageSelect,
     settings::{
         globals::{DisplayQuality, Volume},
         settings_io::SettingPlugin,
-        framerate::{framerate_plugin}
+        framerate::{framerate_plugin},
+        bindings::bindings_plugin,
+        quality::quality_plugin,
     }
 };
 
@@ -32,7 +37,7 @@ fn main() {
     App::new()
         .add_plugins((
             DefaultPlugins,
-            FrameTimeDiagnosticsPlugin::default(),
+            FrameTimeDiagnosticsPlugin,
 
             bevy::diagnostic::EntityCountDiagnosticsPlugin,
             bevy::diagnostic::SystemInformationDiagnosticsPlugin,
@@ -49,6 +54,8 @@ fn main() {
 
         .add_plugins((            
             framerate_plugin,
+            bindings_plugin,
+            quality_plugin,
             SettingPlugin::new(Path::new("assets/settings/volume.json"),Volume(70)),
             SettingPlugin::new(Path::new("assets/settings/quality.json"),DisplayQuality::Medium),
 
@@ -62,7 +69,7 @@ fn main() {
         .init_state::<StageSelect>()
         .add_systems(Startup, setup)
         // Adds the plugins for each state
-        .add_plugins((splash::splash_plugin,rng::rng_plugin, menu::menu_plugin, game::game_plugin))
+        .add_plugins((splash::splash_plugin,rng::rng_plugin, menu::menu_plugin, game::game_plugin, notifications_plugin, gamepad_plugin, audio_plugin, music_plugin))
         .run();
 }
 
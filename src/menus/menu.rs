@@ -8,6 +8,7 @@ use crate::{
     game::PlayState,
     common::{despawn_screen,StageSelect},
     menus::{
+        navigation::navigation_plugin,
         settings::{SettingsState, settings_sub_plugin},
         ui::{SettingButton, button_system, NORMAL_BUTTON, TEXT_COLOR},
     },
@@ -24,7 +25,7 @@ pub fn menu_plugin(app: &mut App) {
         // Current screen in the menu is handled by an independent state from `StageSelect`
         .init_state::<MenuState>()
 
-        .add_plugins(settings_sub_plugin)
+        .add_plugins((settings_sub_plugin, navigation_plugin))
 
         .add_systems(OnEnter(StageSelect::Menu), menu_setup)
         // Systems to handle the main menu screen
@@ -200,6 +201,7 @@ pub enum MenuButtonAction {
     Settings,
     SettingsDisplay,
     SettingsSound,
+    SettingsControls,
     BackToMainMenu,
     BackToSettings,
     Quit,
@@ -244,6 +246,9 @@ fn menu_action(
                 MenuButtonAction::SettingsSound => {
                     settings_state.set(SettingsState::Sound);
                 }
+                MenuButtonAction::SettingsControls => {
+                    settings_state.set(SettingsState::Controls);
+                }
                 MenuButtonAction::BackToMainMenu => {
                     game_state.set(StageSelect::Menu);
                     menu_state.set(MenuState::Main);
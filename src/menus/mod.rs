@@ -1,4 +1,5 @@
 pub mod menu;
+pub mod navigation;
 pub mod settings;
 pub mod splash;
 pub mod ui;
@@ -0,0 +1,251 @@
+//! Moving through menu buttons without the mouse.
+//!
+//! One button or slider at a time has the focus and gets an outline. Tab and
+//! Shift+Tab step through them in reading order, the arrow keys and the gamepad
+//! D-pad move to the nearest one in that direction. Enter or A presses the focused
+//! button and B presses the screen's back button. Pressing just sets the button's
+//! `Interaction`, so `menu_action` and the setting buttons handle it like a click.
+//! On a focused slider left and right nudge the value instead of moving on.
+
+use bevy::{prelude::*, ui::UiSystem};
+
+use crate::{
+    game::PlayState,
+    menus::{
+        menu::MenuButtonAction,
+        settings::rebinding_inactive,
+        ui::{NudgeSlider, SettingButton, SliderBar},
+    },
+};
+
+/// Fraction of a slider's range one key press moves it
+const SLIDER_STEP: f32 = 0.05;
+
+const FOCUS_COLOR: Color = Color::srgb(1.0, 0.85, 0.2);
+
+pub fn navigation_plugin(app: &mut App) {
+    app
+        .init_resource::<MenuFocus>()
+        .add_event::<NudgeSlider>()
+        .add_systems(
+            PreUpdate,
+            (
+                release_pressed_system,
+                keyboard_menu_system.run_if(rebinding_inactive),
+                gamepad_menu_system,
+            )
+                .chain()
+                .after(UiSystem::Focus)
+                .run_if(not(in_state(PlayState::Play))),
+        )
+        .add_systems(Update, focus_outline_system);
+}
+
+/// Button or slider that the keyboard and gamepad act on
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq)]
+pub struct MenuFocus(pub Option<Entity>);
+
+/// Buttons that are part of the game screen rather than a menu, e.g. the toolbar
+#[derive(Component)]
+pub struct NoMenuFocus;
+
+// Button pressed by navigation, released again on the next frame like a click
+#[derive(Component)]
+struct NavigationPress;
+
+type FocusCandidates<'w, 's> = Query<
+    'w,
+    's,
+    (Entity, &'static GlobalTransform, &'static InheritedVisibility),
+    (With<Button>, Or<(With<SettingButton>, With<SliderBar>)>, Without<NoMenuFocus>),
+>;
+
+/// Visible buttons in reading order, top to bottom and left to right
+fn focus_candidates(candidates: &FocusCandidates) -> Vec<(Entity, Vec2)> {
+    let mut buttons: Vec<(Entity, Vec2)> = candidates
+        .iter()
+        .filter(|(_, _, visible)| visible.get())
+        .map(|(entity, transform, _)| (entity, transform.translation().truncate()))
+        .collect();
+    buttons.sort_by(|a, b| a.1.y.total_cmp(&b.1.y).then(a.1.x.total_cmp(&b.1.x)));
+    buttons
+}
+
+/// Nearest button in a direction (UI coordinates, y pointing down), staying in line is preferred
+fn step_focus(buttons: &[(Entity, Vec2)], from: Vec2, direction: Vec2) -> Option<Entity> {
+    buttons
+        .iter()
+        .filter_map(|(entity, pos)| {
+            let offset = *pos - from;
+            let along = offset.dot(direction);
+            let across = offset.perp_dot(direction).abs();
+            (along > 1.0).then_some((*entity, along + across * 2.0))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(entity, _)| entity)
+}
+
+/// Moves the focus, the first button gets it when nothing had it yet
+pub fn move_focus(focus: &mut MenuFocus, candidates: &FocusCandidates, direction: Vec2) {
+    let buttons = focus_candidates(candidates);
+    let current = focus.0.and_then(|entity| buttons.iter().find(|(e, _)| *e == entity));
+    focus.0 = match current {
+        Some((entity, pos)) => step_focus(&buttons, *pos, direction).or(Some(*entity)),
+        None => buttons.first().map(|(entity, _)| *entity),
+    };
+}
+
+/// Steps through the buttons in reading order, wrapping around at the ends
+fn cycle_focus(focus: &mut MenuFocus, candidates: &FocusCandidates, forward: bool) {
+    let buttons = focus_candidates(candidates);
+    if buttons.is_empty() {
+        focus.0 = None;
+        return;
+    }
+    let len = buttons.len();
+    let next = match focus.0.and_then(|entity| buttons.iter().position(|(e, _)| *e == entity)) {
+        Some(index) if forward => (index + 1) % len,
+        Some(index) => (index + len - 1) % len,
+        None if forward => 0,
+        None => len - 1,
+    };
+    focus.0 = Some(buttons[next].0);
+}
+
+/// Left or right on a focused slider moves its value, elsewhere it moves the focus
+fn move_or_nudge(
+    focus: &mut MenuFocus,
+    candidates: &FocusCandidates,
+    slider_q: &Query<(), With<SliderBar>>,
+    nudges: &mut EventWriter<NudgeSlider>,
+    direction: Vec2,
+) {
+    match focus.0 {
+        Some(bar) if direction.y == 0.0 && slider_q.get(bar).is_ok() => {
+            nudges.send(NudgeSlider { bar, step: direction.x * SLIDER_STEP });
+        }
+        _ => move_focus(focus, candidates, direction),
+    }
+}
+
+/// Presses a button for one frame
+pub fn press_button(commands: &mut Commands, interaction_q: &mut Query<&mut Interaction>, entity: Entity) {
+    if let Ok(mut interaction) = interaction_q.get_mut(entity) {
+        *interaction = Interaction::Pressed;
+        commands.entity(entity).try_insert(NavigationPress);
+    }
+}
+
+fn release_pressed_system(
+    mut commands: Commands,
+    mut pressed_q: Query<(Entity, &mut Interaction), With<NavigationPress>>,
+) {
+    for (entity, mut interaction) in &mut pressed_q {
+        interaction.set_if_neq(Interaction::None);
+        commands.entity(entity).remove::<NavigationPress>();
+    }
+}
+
+fn keyboard_menu_system(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    candidates: FocusCandidates,
+    slider_q: Query<(), With<SliderBar>>,
+    mut interaction_q: Query<&mut Interaction>,
+    mut nudges: EventWriter<NudgeSlider>,
+    mut focus: ResMut<MenuFocus>,
+) {
+    if keys.just_pressed(KeyCode::Tab) {
+        let backwards = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+        cycle_focus(&mut focus, &candidates, !backwards);
+    }
+
+    for (key, direction) in [
+        (KeyCode::ArrowUp, Vec2::NEG_Y),
+        (KeyCode::ArrowDown, Vec2::Y),
+        (KeyCode::ArrowLeft, Vec2::NEG_X),
+        (KeyCode::ArrowRight, Vec2::X),
+    ] {
+        if keys.just_pressed(key) {
+            move_or_nudge(&mut focus, &candidates, &slider_q, &mut nudges, direction);
+        }
+    }
+
+    if keys.any_just_pressed([KeyCode::Enter, KeyCode::NumpadEnter])
+        && let Some(entity) = focus.0.filter(|entity| slider_q.get(*entity).is_err())
+    {
+        press_button(&mut commands, &mut interaction_q, entity);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn gamepad_menu_system(
+    mut commands: Commands,
+    gamepads: Query<&Gamepad>,
+    candidates: FocusCandidates,
+    slider_q: Query<(), With<SliderBar>>,
+    mut nudges: EventWriter<NudgeSlider>,
+    back_q: Query<(Entity, &MenuButtonAction, &InheritedVisibility)>,
+    mut interaction_q: Query<&mut Interaction>,
+    mut focus: ResMut<MenuFocus>,
+) {
+    for gamepad in &gamepads {
+        for (button, direction) in [
+            (GamepadButton::DPadUp, Vec2::NEG_Y),
+            (GamepadButton::DPadDown, Vec2::Y),
+            (GamepadButton::DPadLeft, Vec2::NEG_X),
+            (GamepadButton::DPadRight, Vec2::X),
+        ] {
+            if gamepad.just_pressed(button) {
+                move_or_nudge(&mut focus, &candidates, &slider_q, &mut nudges, direction);
+            }
+        }
+
+        if gamepad.just_pressed(GamepadButton::South)
+            && let Some(entity) = focus.0.filter(|entity| slider_q.get(*entity).is_err())
+        {
+            press_button(&mut commands, &mut interaction_q, entity);
+        }
+
+        // B leaves the current screen, through whichever way back it offers
+        if gamepad.just_pressed(GamepadButton::East) {
+            let back = back_q
+                .iter()
+                .filter(|(_, _, visible)| visible.get())
+                .find(|(_, action, _)| {
+                    matches!(action, MenuButtonAction::BackToSettings | MenuButtonAction::ResumePlay)
+                });
+            if let Some((entity, _, _)) = back {
+                press_button(&mut commands, &mut interaction_q, entity);
+            }
+        }
+    }
+}
+
+// Outlines the focused button, and forgets the focus once its button is gone
+#[allow(clippy::type_complexity)]
+fn focus_outline_system(
+    mut commands: Commands,
+    mut focus: ResMut<MenuFocus>,
+    outlined_q: Query<Entity, With<Outline>>,
+    button_q: Query<(), Or<(With<SettingButton>, With<SliderBar>)>>,
+) {
+    if let Some(entity) = focus.0
+        && button_q.get(entity).is_err()
+    {
+        focus.0 = None;
+    }
+    if !focus.is_changed() {
+        return;
+    }
+    for entity in &outlined_q {
+        if Some(entity) != focus.0 && button_q.get(entity).is_ok() {
+            commands.entity(entity).remove::<Outline>();
+        }
+    }
+    if let Some(entity) = focus.0 {
+        commands
+            .entity(entity)
+            .try_insert(Outline::new(Val::Px(3.0), Val::Px(2.0), FOCUS_COLOR));
+    }
+}
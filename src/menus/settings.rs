@@ -5,16 +5,21 @@ use bevy::{
 
 use crate::{
     common::{despawn_screen,StageSelect},
-    settings::globals::{DisplayQuality, Volume},
+    settings::globals::{
+        AdaptiveQuality, AmbienceVolume, CameraSmoothing, DisplayQuality, EdgeScroll, EffectsVolume, MusicVolume, PanSensitivity,
+        Volume, ZoomSensitivity,
+    },
     menus::{
         menu::MenuButtonAction,
         ui::{
             SettingButton, COLOR_GRAY, COLOR_MAROON, COLOR_RED, COLOR_WHITE, NORMAL_BUTTON,
-            SelectedOption, TEXT_COLOR, create_setting_text, drag_slider_system,
+            SelectedOption, TEXT_COLOR, create_setting_text, drag_slider_system, nudge_slider_system,
             setting_button, spawn_slider_system, update_resource_text,
         },
     },
     settings::{
+        bindings::{binding_name, Binding, InputAction, KeyBindings},
+        quality::EffectiveQuality,
         settings_io::save_setting_system,
         framerate::{
             ManualFpsCap,
@@ -30,6 +35,7 @@ pub enum SettingsState {
     Settings,
     Display,
     Sound,
+    Controls,
     #[default]
     Disabled,
 }
@@ -52,10 +58,13 @@ pub fn settings_sub_plugin(app: &mut App) {
             Update,
             (
                 setting_button::<DisplayQuality>,
+                setting_button::<AdaptiveQuality>,
                 setting_button::<FramerateMode>,
                 setting_button::<VsyncMode>,
                 drag_slider_system::<ManualFpsCap>,
+                nudge_slider_system::<ManualFpsCap>,
                 update_resource_text::<ManualFpsCap>.run_if(resource_changed::<ManualFpsCap>),
+                update_resource_text::<EffectiveQuality>.run_if(resource_changed::<EffectiveQuality>),
             ).run_if(in_state(SettingsState::Display)),
         )
         .add_systems(
@@ -63,6 +72,7 @@ pub fn settings_sub_plugin(app: &mut App) {
             (
                 despawn_screen::<OnDisplaySettingsMenuScreen>,
                 save_setting_system::<DisplayQuality>,
+                save_setting_system::<AdaptiveQuality>,
                 save_setting_system::<FramerateMode>,
                 save_setting_system::<VsyncMode>,
                 save_setting_system::<ManualFpsCap>,                
@@ -76,7 +86,17 @@ pub fn settings_sub_plugin(app: &mut App) {
             Update,
             (
                 drag_slider_system::<Volume>,
+                nudge_slider_system::<Volume>,
                 update_resource_text::<Volume>.run_if(resource_changed::<Volume>),
+                drag_slider_system::<MusicVolume>,
+                nudge_slider_system::<MusicVolume>,
+                update_resource_text::<MusicVolume>.run_if(resource_changed::<MusicVolume>),
+                drag_slider_system::<EffectsVolume>,
+                nudge_slider_system::<EffectsVolume>,
+                update_resource_text::<EffectsVolume>.run_if(resource_changed::<EffectsVolume>),
+                drag_slider_system::<AmbienceVolume>,
+                nudge_slider_system::<AmbienceVolume>,
+                update_resource_text::<AmbienceVolume>.run_if(resource_changed::<AmbienceVolume>),
             )
             .run_if(in_state(SettingsState::Sound)),
         )
@@ -85,6 +105,43 @@ pub fn settings_sub_plugin(app: &mut App) {
             (
                 despawn_screen::<OnSoundSettingsMenuScreen>,
                 save_setting_system::<Volume>,
+                save_setting_system::<MusicVolume>,
+                save_setting_system::<EffectsVolume>,
+                save_setting_system::<AmbienceVolume>,
+            ),
+        )
+        .init_resource::<Rebinding>()
+        .add_systems(
+            OnEnter(SettingsState::Controls),
+            controls_settings_menu_setup,
+        )
+        .add_systems(
+            Update,
+            (
+                setting_button::<EdgeScroll>,
+                drag_slider_system::<ZoomSensitivity>,
+                nudge_slider_system::<ZoomSensitivity>,
+                drag_slider_system::<PanSensitivity>,
+                nudge_slider_system::<PanSensitivity>,
+                drag_slider_system::<CameraSmoothing>,
+                nudge_slider_system::<CameraSmoothing>,
+                update_resource_text::<ZoomSensitivity>.run_if(resource_changed::<ZoomSensitivity>),
+                update_resource_text::<PanSensitivity>.run_if(resource_changed::<PanSensitivity>),
+                update_resource_text::<CameraSmoothing>.run_if(resource_changed::<CameraSmoothing>),
+                (start_rebind_system, capture_rebind_system, update_binding_text_system).chain(),
+            )
+            .run_if(in_state(SettingsState::Controls)),
+        )
+        .add_systems(
+            OnExit(SettingsState::Controls),
+            (
+                despawn_screen::<OnControlsSettingsMenuScreen>,
+                stop_rebind_system,
+                save_setting_system::<KeyBindings>,
+                save_setting_system::<EdgeScroll>,
+                save_setting_system::<ZoomSensitivity>,
+                save_setting_system::<PanSensitivity>,
+                save_setting_system::<CameraSmoothing>,
             ),
         );
 }
@@ -101,6 +158,32 @@ struct OnDisplaySettingsMenuScreen;
 #[derive(Component,Clone,Copy)]
 struct OnSoundSettingsMenuScreen;
 
+// Tag component used to tag entities added on the controls settings menu screen
+#[derive(Component,Clone,Copy)]
+struct OnControlsSettingsMenuScreen;
+
+const CONFLICT_COLOR: Color = Color::srgb(1.0, 0.85, 0.2);
+
+// Button showing the key of an action, pressing it waits for a new key
+#[derive(Component, Clone, Copy)]
+struct BindingButton(InputAction);
+
+#[derive(Component, Clone, Copy)]
+struct BindingText(InputAction);
+
+// Lists bindings that share a key
+#[derive(Component)]
+struct BindingConflictText;
+
+// Action waiting for its new key
+#[derive(Resource, Default)]
+pub struct Rebinding(pub Option<InputAction>);
+
+// Menu navigation keys would otherwise also be taken as the new binding
+pub fn rebinding_inactive(rebinding: Res<Rebinding>) -> bool {
+    rebinding.0.is_none()
+}
+
 
 fn settings_menu_setup(
     mut commands: Commands,
@@ -167,6 +250,7 @@ fn settings_menu_setup(
                     for (action, text) in [
                         (MenuButtonAction::SettingsDisplay, "Display"),
                         (MenuButtonAction::SettingsSound, "Sound"),
+                        (MenuButtonAction::SettingsControls, "Controls"),
                     ] {
                         parent
                             .spawn((
@@ -308,6 +392,8 @@ fn display_settings_menu_setup(
     fps_cap: Res<ManualFpsCap>,
     framerate_mode: Res<FramerateMode>,
     vsync_mode: Res<VsyncMode>,
+    adaptive_quality: Res<AdaptiveQuality>,
+    effective_quality: Res<EffectiveQuality>,
 ) {
 
     //for later when we do fps slider
@@ -390,6 +476,43 @@ fn display_settings_menu_setup(
                             }
                         });
 
+                    // --- Adaptive Quality Setting ---
+                    parent
+                        .spawn((
+                            Node {
+                                align_items: AlignItems::Center,
+                                ..default()
+                            },
+                            BackgroundColor(CRIMSON.into()),
+                        ))
+                        .with_children(|parent| {
+                            parent.spawn((Text::new("Auto Quality"), button_text_style.clone()));
+                            for mode in [AdaptiveQuality::Enabled, AdaptiveQuality::Disabled] {
+                                let mut entity = parent.spawn((
+                                    SettingButton,
+                                    Button,
+                                    Node {
+                                        width: Val::Px(150.0),
+                                        height: Val::Px(50.0),
+                                        ..button_node.clone()
+                                    },
+                                    BackgroundColor(NORMAL_BUTTON),
+                                    mode,
+                                ));
+                                entity.with_children(|parent| {
+                                    parent.spawn((
+                                        Text::new(format!("{mode:?}")),
+                                        button_text_style.clone(),
+                                    ));
+                                });
+                                if *adaptive_quality == mode {
+                                    entity.insert(SelectedOption);
+                                }
+                            }
+                            // the buttons above keep the chosen quality, this is what is drawn
+                            create_setting_text(parent, &effective_quality);
+                        });
+
                     // --- VSync Setting ---
                     parent
                         .spawn((
@@ -540,6 +663,9 @@ fn display_settings_menu_setup(
 fn sound_settings_menu_setup(
     mut commands: Commands,
     volume: Res<Volume>,
+    music: Res<MusicVolume>,
+    effects: Res<EffectsVolume>,
+    ambience: Res<AmbienceVolume>,
 ) {
     let button_node = Node {
         width: Val::Px(200.0),
@@ -555,7 +681,8 @@ fn sound_settings_menu_setup(
     );
 
     // 1) UI layout for the “Volume Menu” background, text, etc.
-    let mut volume_container = None;
+    //    One row per bus, the sliders are spawned into these afterwards
+    let mut volume_containers = vec![];
 
     commands
         .spawn((
@@ -579,35 +706,202 @@ fn sound_settings_menu_setup(
                     BackgroundColor(COLOR_RED), // or CRIMSON, etc.
                 ))
                 .with_children(|parent| {
+                    for label in ["Master:", "Music:", "Effects:", "Ambience:"] {
+                        parent
+                            .spawn((
+                                Node {
+                                    align_items: AlignItems::Center,
+                                    ..default()
+                                },
+                                BackgroundColor(COLOR_MAROON),
+                            ))
+                            .with_children(|parent| {
+                                parent.spawn((Text::new(label), button_text_style.clone()));
+                                match label {
+                                    "Master:" => create_setting_text(parent, &volume),
+                                    "Music:" => create_setting_text(parent, &music),
+                                    "Effects:" => create_setting_text(parent, &effects),
+                                    _ => create_setting_text(parent, &ambience),
+                                };
+                                volume_containers.push(
+                                    parent
+                                        .spawn(Node {
+                                            align_items: AlignItems::Center,
+                                            ..default()
+                                        })
+                                        .id(),
+                                );
+                            });
+                    }
+
+                    // "Back" button
+                    parent
+                        .spawn((
+                            Button,
+                            SettingButton,
+                            button_node,
+                            BackgroundColor(COLOR_GRAY),
+                            MenuButtonAction::BackToSettings,
+                        ))
+                        .with_child((Text::new("Back"), button_text_style));
+                });
+        });
+
+    // 2) Spawn the sliders with proper positioning
+    //    We map [0..1] → Volume(0..100).
+    let bar_node = Node {
+        width: Val::Px(250.0),
+        height: Val::Px(20.0),
+        // Center slider and add proper margins
+        margin: UiRect::all(Val::Px(20.0)),
+        align_self: AlignSelf::Center,
+        ..default()
+    };
+    spawn_slider_system::<Volume, OnSoundSettingsMenuScreen>(
+        commands.reborrow(),
+        bar_node.clone(),
+        None,
+        OnSoundSettingsMenuScreen,
+        Some(volume_containers[0]),
+        Some(volume),
+    );
+    spawn_slider_system::<MusicVolume, OnSoundSettingsMenuScreen>(
+        commands.reborrow(),
+        bar_node.clone(),
+        None,
+        OnSoundSettingsMenuScreen,
+        Some(volume_containers[1]),
+        Some(music),
+    );
+    spawn_slider_system::<EffectsVolume, OnSoundSettingsMenuScreen>(
+        commands.reborrow(),
+        bar_node.clone(),
+        None,
+        OnSoundSettingsMenuScreen,
+        Some(volume_containers[2]),
+        Some(effects),
+    );
+    spawn_slider_system::<AmbienceVolume, OnSoundSettingsMenuScreen>(
+        commands,
+        bar_node,
+        None,
+        OnSoundSettingsMenuScreen,
+        Some(volume_containers[3]),
+        Some(ambience),
+    );
+}
+
+
+fn controls_settings_menu_setup(
+    mut commands: Commands,
+    zoom: Res<ZoomSensitivity>,
+    pan: Res<PanSensitivity>,
+    smoothing: Res<CameraSmoothing>,
+    edge_scroll: Res<EdgeScroll>,
+    bindings: Res<KeyBindings>,
+) {
+    let button_node = Node {
+        width: Val::Px(200.0),
+        height: Val::Px(65.0),
+        margin: UiRect::all(Val::Px(20.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+    let button_text_style = (
+        TextFont { font_size: 33.0, ..default() },
+        TextColor(COLOR_WHITE),
+    );
+
+    // One row per slider, the sliders are spawned into these afterwards
+    let mut slider_containers = vec![];
+
+    commands
+        .spawn((
+            Node {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            OnControlsSettingsMenuScreen,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn((
+                    Node {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    BackgroundColor(COLOR_RED),
+                ))
+                .with_children(|parent| {
+                    for label in ["Zoom:", "Pan:", "Smoothing:"] {
+                        parent
+                            .spawn((
+                                Node {
+                                    align_items: AlignItems::Center,
+                                    ..default()
+                                },
+                                BackgroundColor(COLOR_MAROON),
+                            ))
+                            .with_children(|parent| {
+                                parent.spawn((Text::new(label), button_text_style.clone()));
+                                match label {
+                                    "Zoom:" => create_setting_text(parent, &zoom),
+                                    "Pan:" => create_setting_text(parent, &pan),
+                                    _ => create_setting_text(parent, &smoothing),
+                                };
+                                slider_containers.push(
+                                    parent
+                                        .spawn(Node {
+                                            align_items: AlignItems::Center,
+                                            ..default()
+                                        })
+                                        .id(),
+                                );
+                            });
+                    }
+
+                    // --- Edge Scroll Buttons ---
                     parent
                         .spawn((
                             Node {
                                 align_items: AlignItems::Center,
+                                margin: UiRect::all(Val::Px(15.0)),
                                 ..default()
                             },
                             BackgroundColor(COLOR_MAROON),
                         ))
                         .with_children(|parent| {
-                            
-                            // parent.spawn((Text::new("Volume"), button_text_style.clone()));
-                            parent.spawn((Text::new("Volume:"), button_text_style.clone()));
-                            create_setting_text(parent,&volume);
-                            // parent.spawn((
-                            //     SliderValueText::<Volume>(PhantomData),
-                            //     Text::new(format!("{:.2}", volume.as_fraction())),
-                            //     button_text_style.clone()));
-
-                                
-                            volume_container=Some(
-                                parent.spawn((
+                            parent.spawn((Text::new("Edge Scroll"), button_text_style.clone()));
+                            for mode in [EdgeScroll::Enabled, EdgeScroll::Disabled] {
+                                let mut entity = parent.spawn((
+                                    SettingButton,
+                                    Button,
                                     Node {
+                                        width: Val::Px(180.0),
+                                        height: Val::Px(60.0),
+                                        margin: UiRect::all(Val::Px(10.0)),
+                                        justify_content: JustifyContent::Center,
                                         align_items: AlignItems::Center,
                                         ..default()
                                     },
-                                ))
-                                .id()
-                            );
-
+                                    BackgroundColor(NORMAL_BUTTON),
+                                    mode,
+                                ));
+                                entity.with_children(|parent| {
+                                    parent.spawn((
+                                        Text::new(format!("{mode:?}")),
+                                        button_text_style.clone(),
+                                    ));
+                                });
+                                if *edge_scroll == mode {
+                                    entity.insert(SelectedOption);
+                                }
+                            }
                         });
 
                     // "Back" button
@@ -619,33 +913,192 @@ fn sound_settings_menu_setup(
                             BackgroundColor(COLOR_GRAY),
                             MenuButtonAction::BackToSettings,
                         ))
-                        .with_child((Text::new("Back"), button_text_style));
+                        .with_children(|parent| {
+                            parent.spawn((Text::new("Back"), button_text_style));
+                        });
+                });
+
+            // --- Key Bindings Column ---
+            let binding_text_style = (
+                TextFont { font_size: 16.0, ..default() },
+                TextColor(COLOR_WHITE),
+            );
+            parent
+                .spawn((
+                    Node {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::End,
+                        margin: UiRect::left(Val::Px(20.0)),
+                        padding: UiRect::all(Val::Px(10.0)),
+                        ..default()
+                    },
+                    BackgroundColor(COLOR_RED),
+                ))
+                .with_children(|parent| {
+                    // there are too many actions for a single column
+                    parent
+                        .spawn(Node {
+                            display: Display::Grid,
+                            grid_template_columns: RepeatedGridTrack::auto(3),
+                            column_gap: Val::Px(12.0),
+                            ..default()
+                        })
+                        .with_children(|parent| {
+                            for action in InputAction::ALL {
+                                parent
+                                    .spawn(Node {
+                                        align_items: AlignItems::Center,
+                                        justify_content: JustifyContent::End,
+                                        ..default()
+                                    })
+                                    .with_children(|parent| {
+                                        parent.spawn((Text::new(action.label()), binding_text_style.clone()));
+                                        parent
+                                            .spawn((
+                                                SettingButton,
+                                                Button,
+                                                Node {
+                                                    width: Val::Px(100.0),
+                                                    height: Val::Px(26.0),
+                                                    margin: UiRect::all(Val::Px(2.0)),
+                                                    justify_content: JustifyContent::Center,
+                                                    align_items: AlignItems::Center,
+                                                    ..default()
+                                                },
+                                                BackgroundColor(NORMAL_BUTTON),
+                                                BindingButton(action),
+                                            ))
+                                            .with_children(|parent| {
+                                                parent.spawn((
+                                                    BindingText(action),
+                                                    Text::new(binding_name(bindings.binding(action))),
+                                                    binding_text_style.clone(),
+                                                ));
+                                            });
+                                    });
+                            }
+                        });
+                    parent.spawn((
+                        BindingConflictText,
+                        Text::new(""),
+                        TextFont { font_size: 18.0, ..default() },
+                        TextColor(CONFLICT_COLOR),
+                    ));
                 });
         });
 
-    // 2) Spawn the slider with proper positioning
-    //    We map [0..1] → Volume(0..10).
-    //    Get node reference to the volume parent container
-    spawn_slider_system::<Volume, OnSoundSettingsMenuScreen>(
+    let bar_node = Node {
+        width: Val::Px(250.0),
+        height: Val::Px(20.0),
+        margin: UiRect::all(Val::Px(20.0)),
+        align_self: AlignSelf::Center,
+        ..default()
+    };
+    spawn_slider_system::<ZoomSensitivity, OnControlsSettingsMenuScreen>(
+        commands.reborrow(),
+        bar_node.clone(),
+        None,
+        OnControlsSettingsMenuScreen,
+        Some(slider_containers[0]),
+        Some(zoom),
+    );
+    spawn_slider_system::<PanSensitivity, OnControlsSettingsMenuScreen>(
+        commands.reborrow(),
+        bar_node.clone(),
+        None,
+        OnControlsSettingsMenuScreen,
+        Some(slider_containers[1]),
+        Some(pan),
+    );
+    spawn_slider_system::<CameraSmoothing, OnControlsSettingsMenuScreen>(
         commands,
-        Node {
-            width: Val::Px(250.0),
-            height: Val::Px(20.0),
-            // Center slider and add proper margins
-            margin: UiRect::all(Val::Px(20.0)),
-            align_self: AlignSelf::Center,
-            ..default()
-        },
-        Some(Node {
-            width: Val::Px(20.0),
-            height: Val::Px(30.0),
-            position_type: PositionType::Absolute,
-            left: Val::Px(0.0),
-            top: Val::Px(-5.0),
-            ..default()
-        }),
-        OnSoundSettingsMenuScreen,
-        Some(volume_container.unwrap()),
-        Some(volume)
+        bar_node,
+        None,
+        OnControlsSettingsMenuScreen,
+        Some(slider_containers[2]),
+        Some(smoothing),
     );
-}
\ No newline at end of file
+}
+
+
+// Pressing a binding starts listening for a key, pressing it again cancels
+fn start_rebind_system(
+    interaction_q: Query<(&Interaction, &BindingButton), Changed<Interaction>>,
+    mut rebinding: ResMut<Rebinding>,
+) {
+    for (interaction, button) in &interaction_q {
+        if *interaction == Interaction::Pressed {
+            rebinding.0 = if rebinding.0 == Some(button.0) { None } else { Some(button.0) };
+        }
+    }
+}
+
+// Takes the next key or mouse button, Escape cancels. The left button drives the
+// menus so it can't be bound, and the click that started listening is not taken.
+fn capture_rebind_system(
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    mut rebinding: ResMut<Rebinding>,
+    mut bindings: ResMut<KeyBindings>,
+) {
+    let Some(action) = rebinding.0 else { return };
+    if rebinding.is_changed() {
+        return;
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        rebinding.0 = None;
+        return;
+    }
+    let key = keys.get_just_pressed().next().map(|key| Binding::Key(*key));
+    let button = mouse.get_just_pressed().find(|b| **b != MouseButton::Left).map(|b| Binding::Mouse(*b));
+    if let Some(binding) = key.or(button) {
+        bindings.0.insert(action, binding);
+        rebinding.0 = None;
+    }
+}
+
+// Shows the bound keys, the one being rebound and any keys used twice
+fn update_binding_text_system(
+    bindings: Res<KeyBindings>,
+    rebinding: Res<Rebinding>,
+    mut text_q: Query<(&BindingText, &mut Text, &mut TextColor), Without<BindingConflictText>>,
+    mut conflict_q: Query<&mut Text, With<BindingConflictText>>,
+    spawned_q: Query<(), Added<BindingConflictText>>,
+) {
+    if !bindings.is_changed() && !rebinding.is_changed() && spawned_q.is_empty() {
+        return;
+    }
+    for (binding, mut text, mut color) in &mut text_q {
+        let conflicts = bindings.conflicts(binding.0);
+        text.0 = if rebinding.0 == Some(binding.0) {
+            "Press a key".to_string()
+        } else {
+            binding_name(bindings.binding(binding.0))
+        };
+        color.0 = if conflicts.is_empty() { COLOR_WHITE } else { CONFLICT_COLOR };
+    }
+
+    let mut lines = vec![];
+    if let Some(action) = rebinding.0 {
+        lines.push(format!("Press a key or mouse button for {}, Esc cancels", action.label()));
+    }
+    for action in InputAction::ALL {
+        for other in bindings.conflicts(action) {
+            if (action as usize) < (other as usize) {
+                lines.push(format!(
+                    "{} and {} share {}",
+                    action.label(),
+                    other.label(),
+                    binding_name(bindings.binding(action))
+                ));
+            }
+        }
+    }
+    for mut text in &mut conflict_q {
+        text.0 = lines.join("\n");
+    }
+}
+
+fn stop_rebind_system(mut rebinding: ResMut<Rebinding>) {
+    rebinding.0 = None;
+}
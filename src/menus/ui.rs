@@ -255,6 +255,43 @@ pub fn drag_slider_system<T: Resource + Slidble>(
 
 
 
+/// Moves a slider by a fraction of its range, sent by keyboard and gamepad navigation
+#[derive(Event, Debug, Clone, Copy)]
+pub struct NudgeSlider {
+    pub bar: Entity,
+    pub step: f32,
+}
+
+pub fn nudge_slider_system<T: Resource + Slidble>(
+    mut events: EventReader<NudgeSlider>,
+    mut bar_query: Query<(&Node, &mut Slider<T>), Without<SliderHandle<T>>>,
+    mut handle_query: Query<&mut Node, With<SliderHandle<T>>>,
+    mut setting: ResMut<T>,
+) {
+    for event in events.read() {
+        // Each slider type has its own copy of this system, only one owns the bar
+        let Ok((bar_node, mut slider)) = bar_query.get_mut(event.bar) else { continue };
+
+        let fraction = (setting.as_fraction() + event.step).clamp(0.0, 1.0);
+        slider.fraction = fraction;
+        *setting = T::from_fraction(fraction);
+
+        if let Ok(mut handle_node) = handle_query.get_mut(slider.handle) {
+            let bar_width = match bar_node.width {
+                Val::Px(px) => px,
+                _ => 200.0,
+            };
+            let handle_width = match handle_node.width {
+                Val::Px(px) => px,
+                _ => 20.0,
+            };
+            handle_node.left = Val::Px(fraction * (bar_width - handle_width));
+        }
+    }
+}
+
+
+
 // Marker component for our text display
 #[derive(Component)]
 pub struct ReasourceText<R: Resource + Display>(pub PhantomData<R>);
@@ -0,0 +1,221 @@
+//! Background music.
+//!
+//! The menus and the city each have their own playlist. Switching between them
+//! crossfades, the old track fades out while the new one fades in. Within the city
+//! playlist tracks play one after another in a shuffled order, reshuffled with
+//! `SimpleRng` every time it runs out, and crossfade too: a track starts fading
+//! out `FADE_TIME` before it ends, its length read from the Ogg headers. Music
+//! plays on the music bus, so its volume follows the sound settings.
+
+use bevy::{audio::Volume as AudioVolume, prelude::*};
+
+use crate::{
+    audio::{AudioBus, SoundLevel},
+    common::StageSelect,
+    rng::SimpleRng,
+};
+
+/// Seconds for a full fade in or out
+const FADE_TIME: f32 = 2.0;
+
+pub fn music_plugin(app: &mut App) {
+    app
+        .init_resource::<MusicPlayer>()
+        .add_systems(
+            Update,
+            (switch_playlist_system, track_ending_system, next_track_system, fade_music_system).chain(),
+        );
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Playlist {
+    Menu,
+    Game,
+}
+
+impl Playlist {
+    pub fn tracks(self) -> &'static [&'static str] {
+        match self {
+            Playlist::Menu => &["bevy_examples/sounds/Mysterious acoustic guitar.ogg"],
+            Playlist::Game => &[
+                "bevy_examples/sounds/Windless Slopes.ogg",
+                "bevy_examples/sounds/Epic orchestra music.ogg",
+            ],
+        }
+    }
+
+    fn for_stage(stage: StageSelect) -> Self {
+        match stage {
+            StageSelect::Splash | StageSelect::Menu => Playlist::Menu,
+            StageSelect::Game => Playlist::Game,
+        }
+    }
+}
+
+/// What is playing and what comes next
+#[derive(Resource, Default, Debug)]
+pub struct MusicPlayer {
+    pub playlist: Option<Playlist>,
+    /// Shuffled track indices still to play
+    queue: Vec<usize>,
+    last: Option<usize>,
+}
+
+impl MusicPlayer {
+    fn next_track(&mut self, rng: &mut SimpleRng) -> Option<&'static str> {
+        let tracks = self.playlist?.tracks();
+        if self.queue.is_empty() {
+            self.queue = (0..tracks.len()).collect();
+            // Fisher-Yates
+            for i in (1..self.queue.len()).rev() {
+                let j = rng.next_u32() as usize % (i + 1);
+                self.queue.swap(i, j);
+            }
+            // Don't play the same track twice in a row across reshuffles
+            let end = self.queue.len().saturating_sub(1);
+            if end > 0 && self.queue.last() == self.last.as_ref() {
+                self.queue.swap(0, end);
+            }
+        }
+        let index = self.queue.pop()?;
+        self.last = Some(index);
+        Some(tracks[index])
+    }
+}
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+enum Fade {
+    In,
+    Out,
+}
+
+// Component of the music entities, tracking how far into the track they are
+#[derive(Component, Default)]
+struct MusicTrack {
+    /// Seconds played so far
+    elapsed: f32,
+    /// Length of the track once its asset has loaded
+    duration: Option<f32>,
+}
+
+/// Length in seconds of an Ogg Vorbis file, from the sample rate in its
+/// identification header and the granule position of its last page
+fn ogg_vorbis_duration(bytes: &[u8]) -> Option<f32> {
+    let header = bytes.windows(7).position(|w| w == b"\x01vorbis")?;
+    let rate = u32::from_le_bytes(bytes.get(header + 12..header + 16)?.try_into().ok()?);
+    let last_page = bytes.windows(4).rposition(|w| w == b"OggS")?;
+    let granule = u64::from_le_bytes(bytes.get(last_page + 6..last_page + 14)?.try_into().ok()?);
+    (rate > 0).then(|| granule as f32 / rate as f32)
+}
+
+// Fades out whatever plays when the stage asks for another playlist
+fn switch_playlist_system(
+    mut commands: Commands,
+    stage: Res<State<StageSelect>>,
+    mut player: ResMut<MusicPlayer>,
+    track_q: Query<Entity, With<MusicTrack>>,
+) {
+    let playlist = Playlist::for_stage(*stage.get());
+    if player.playlist == Some(playlist) {
+        return;
+    }
+    for entity in &track_q {
+        commands.entity(entity).insert(Fade::Out);
+    }
+    *player = MusicPlayer {
+        playlist: Some(playlist),
+        ..default()
+    };
+}
+
+// Fades a track out ahead of its end so the next one can fade in over it
+#[allow(clippy::type_complexity)]
+fn track_ending_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    sources: Res<Assets<AudioSource>>,
+    mut track_q: Query<(Entity, &mut MusicTrack, &AudioPlayer, Option<&AudioSink>, Has<Fade>)>,
+) {
+    for (entity, mut track, player, sink, fading) in &mut track_q {
+        // the sink shows up once the track actually plays
+        let Some(sink) = sink else { continue };
+        if !sink.is_paused() {
+            track.elapsed += time.delta_secs() * sink.speed();
+        }
+        if track.duration.is_none() {
+            track.duration = sources.get(&player.0).and_then(|source| ogg_vorbis_duration(&source.bytes));
+        }
+        if let Some(duration) = track.duration
+            && !fading
+            && track.elapsed >= duration - FADE_TIME
+        {
+            commands.entity(entity).insert(Fade::Out);
+        }
+    }
+}
+
+// Starts the next track once nothing but fading out tracks is left
+fn next_track_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut rng: ResMut<SimpleRng>,
+    mut player: ResMut<MusicPlayer>,
+    track_q: Query<Option<&Fade>, With<MusicTrack>>,
+) {
+    if track_q.iter().any(|fade| fade != Some(&Fade::Out)) {
+        return;
+    }
+    let crossfading = !track_q.is_empty();
+    let Some(path) = player.next_track(&mut rng) else { return };
+    let mut track = commands.spawn((
+        MusicTrack::default(),
+        AudioPlayer::new(asset_server.load(path)),
+        PlaybackSettings::DESPAWN.with_volume(AudioVolume::new(0.0)),
+        AudioBus::Music,
+    ));
+    if crossfading {
+        track.insert((SoundLevel(0.0), Fade::In));
+    } else {
+        track.insert(SoundLevel(1.0));
+    }
+}
+
+fn fade_music_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut track_q: Query<(Entity, &Fade, &mut SoundLevel), With<MusicTrack>>,
+) {
+    let step = time.delta_secs() / FADE_TIME;
+    for (entity, fade, mut level) in &mut track_q {
+        match fade {
+            Fade::In => {
+                level.0 = (level.0 + step).min(1.0);
+                if level.0 >= 1.0 {
+                    commands.entity(entity).remove::<Fade>();
+                }
+            }
+            Fade::Out => {
+                level.0 = (level.0 - step).max(0.0);
+                if level.0 <= 0.0 {
+                    commands.entity(entity).despawn();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_length_of_every_track() {
+        for playlist in [Playlist::Menu, Playlist::Game] {
+            for track in playlist.tracks() {
+                let bytes = std::fs::read(format!("assets/{track}")).unwrap();
+                let duration = ogg_vorbis_duration(&bytes).unwrap();
+                assert!(duration > 2.0 * FADE_TIME, "{track} is {duration} s long");
+            }
+        }
+    }
+}
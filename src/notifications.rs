@@ -0,0 +1,323 @@
+//! Things worth telling the player about.
+//!
+//! Anything can send a `Notification`, it pops up as a toast for a few seconds and
+//! is kept in the event log. H opens the log, clicking an entry with a location
+//! moves the camera there.
+
+use bevy::{
+    input::mouse::{MouseScrollUnit, MouseWheel},
+    prelude::*,
+    ui::RelativeCursorPosition,
+};
+
+use crate::{
+    game::PlayState,
+    menus::ui::{HOVERED_BUTTON, NORMAL_BUTTON, TEXT_COLOR},
+    settings::bindings::{Actions, InputAction},
+};
+
+/// Real seconds a toast stays up
+const TOAST_SECONDS: f32 = 4.0;
+const MAX_TOASTS: usize = 4;
+/// Older entries are dropped from the log
+const MAX_LOG_ENTRIES: usize = 200;
+const LINE_HEIGHT: f32 = 24.0;
+
+pub fn notifications_plugin(app: &mut App) {
+    app
+        .add_event::<Notification>()
+        .init_resource::<EventLog>()
+        .add_systems(Startup, notifications_setup)
+        .add_systems(Update, (record_notifications_system, expire_toasts_system).chain())
+        .add_systems(
+            Update,
+            (
+                toggle_event_log_system.run_if(in_state(PlayState::Play)),
+                rebuild_event_log_system.after(record_notifications_system),
+                scroll_event_log_system,
+                jump_to_entry_system,
+            )
+                .chain(),
+        );
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NotificationKind {
+    /// The city's balance went below zero
+    BudgetDeficit { balance: f32 },
+    /// Part of the road network can no longer be reached from the rest
+    DisconnectedDistrict { nodes: usize },
+    BuildingAbandoned,
+    SaveComplete { path: String },
+    SettingSaveFailed { error: String },
+}
+
+#[derive(Event, Clone, Debug)]
+pub struct Notification {
+    pub kind: NotificationKind,
+    /// Where it happened, if anywhere in particular
+    pub location: Option<Vec2>,
+}
+
+impl Notification {
+    pub fn new(kind: NotificationKind) -> Self {
+        Self { kind, location: None }
+    }
+
+    pub fn at(kind: NotificationKind, location: Vec2) -> Self {
+        Self { kind, location: Some(location) }
+    }
+
+    pub fn message(&self) -> String {
+        match &self.kind {
+            NotificationKind::BudgetDeficit { balance } => format!("The city is in debt (${:.0})", balance),
+            NotificationKind::DisconnectedDistrict { nodes } => {
+                format!("A district of {} intersections is cut off from the city", nodes)
+            }
+            NotificationKind::BuildingAbandoned => "A building was abandoned".to_string(),
+            NotificationKind::SaveComplete { path } => format!("City saved to {}", path),
+            NotificationKind::SettingSaveFailed { error } => format!("Could not save settings: {}", error),
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self.kind {
+            NotificationKind::SaveComplete { .. } => Color::srgba(0.1, 0.35, 0.15, 0.9),
+            NotificationKind::BuildingAbandoned | NotificationKind::DisconnectedDistrict { .. } => {
+                Color::srgba(0.45, 0.35, 0.05, 0.9)
+            }
+            NotificationKind::BudgetDeficit { .. } | NotificationKind::SettingSaveFailed { .. } => {
+                Color::srgba(0.5, 0.1, 0.1, 0.9)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LogEntry {
+    pub notification: Notification,
+    /// Real seconds since startup
+    pub time: f32,
+}
+
+/// Every notification so far, oldest first
+#[derive(Resource, Debug, Default)]
+pub struct EventLog {
+    pub entries: Vec<LogEntry>,
+}
+
+// Tag component for the column holding the toasts
+#[derive(Component)]
+struct ToastColumn;
+
+#[derive(Component)]
+struct Toast {
+    expires: f32,
+}
+
+// Tag component for the log panel
+#[derive(Component)]
+struct EventLogPanel;
+
+// Tag component for the scrolling list inside the log panel
+#[derive(Component)]
+struct EventLogList;
+
+// Log entry button, with the index of its entry
+#[derive(Component)]
+struct EventLogEntry(usize);
+
+// Toasts and the log live outside the game screen so menus can show them too
+fn notifications_setup(mut commands: Commands) {
+    commands.spawn((
+        ToastColumn,
+        Node {
+            position_type: PositionType::Absolute,
+            // below the toolbar
+            top: Val::Px(60.0),
+            left: Val::Percent(50.0),
+            margin: UiRect::left(Val::Px(-200.0)),
+            width: Val::Px(400.0),
+            flex_direction: FlexDirection::Column,
+            row_gap: Val::Px(6.0),
+            ..default()
+        },
+        GlobalZIndex(10),
+    ));
+
+    commands
+        .spawn((
+            EventLogPanel,
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(60.0),
+                right: Val::Px(10.0),
+                width: Val::Px(420.0),
+                height: Val::Px(300.0),
+                flex_direction: FlexDirection::Column,
+                padding: UiRect::all(Val::Px(8.0)),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.8)),
+            Visibility::Hidden,
+            GlobalZIndex(9),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new("Event log"),
+                TextFont { font_size: 18.0, ..default() },
+                TextColor(TEXT_COLOR),
+            ));
+            parent.spawn((
+                EventLogList,
+                Node {
+                    flex_direction: FlexDirection::Column,
+                    overflow: Overflow::scroll_y(),
+                    flex_grow: 1.0,
+                    ..default()
+                },
+                RelativeCursorPosition::default(),
+            ));
+        });
+}
+
+fn record_notifications_system(
+    mut commands: Commands,
+    time: Res<Time<Real>>,
+    mut notifications: EventReader<Notification>,
+    mut log: ResMut<EventLog>,
+    column_q: Query<Entity, With<ToastColumn>>,
+    toast_q: Query<(Entity, &Toast)>,
+) {
+    let Ok(column) = column_q.get_single() else { return };
+    let mut shown = toast_q.iter().count();
+    for notification in notifications.read() {
+        info!("{}", notification.message());
+        log.entries.push(LogEntry { notification: notification.clone(), time: time.elapsed_secs() });
+
+        // too many at once, the oldest gives way
+        if shown >= MAX_TOASTS {
+            if let Some((oldest, _)) = toast_q.iter().min_by(|(_, a), (_, b)| a.expires.total_cmp(&b.expires)) {
+                commands.entity(oldest).despawn_recursive();
+            }
+        } else {
+            shown += 1;
+        }
+        commands.entity(column).with_children(|parent| {
+            parent
+                .spawn((
+                    Toast { expires: time.elapsed_secs() + TOAST_SECONDS },
+                    Node { padding: UiRect::all(Val::Px(8.0)), ..default() },
+                    BackgroundColor(notification.color()),
+                ))
+                .with_child((
+                    Text::new(notification.message()),
+                    TextFont { font_size: 16.0, ..default() },
+                    TextColor(TEXT_COLOR),
+                ));
+        });
+    }
+
+    // only touch the log when it really overflows, any change rebuilds its panel
+    let overflow = log.entries.len().saturating_sub(MAX_LOG_ENTRIES);
+    if overflow > 0 {
+        log.entries.drain(..overflow);
+    }
+}
+
+fn expire_toasts_system(mut commands: Commands, time: Res<Time<Real>>, toast_q: Query<(Entity, &Toast)>) {
+    for (entity, toast) in &toast_q {
+        if time.elapsed_secs() >= toast.expires {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+fn toggle_event_log_system(actions: Actions, mut panel_q: Query<&mut Visibility, With<EventLogPanel>>) {
+    if !actions.just_pressed(InputAction::EventLog) {
+        return;
+    }
+    for mut visibility in &mut panel_q {
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Inherited,
+            _ => Visibility::Hidden,
+        };
+    }
+}
+
+// Newest entries first, the ones with a location can be clicked
+fn rebuild_event_log_system(
+    mut commands: Commands,
+    log: Res<EventLog>,
+    list_q: Query<Entity, With<EventLogList>>,
+) {
+    if !log.is_changed() {
+        return;
+    }
+    let Ok(list) = list_q.get_single() else { return };
+    commands.entity(list).despawn_descendants().with_children(|parent| {
+        for (i, entry) in log.entries.iter().enumerate().rev() {
+            let seconds = entry.time as u32;
+            let mut row = parent.spawn((
+                Node {
+                    min_height: Val::Px(LINE_HEIGHT),
+                    padding: UiRect::horizontal(Val::Px(4.0)),
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                BackgroundColor(NORMAL_BUTTON),
+            ));
+            if entry.notification.location.is_some() {
+                row.insert((Button, EventLogEntry(i)));
+            }
+            row.with_child((
+                Text::new(format!(
+                    "[{:02}:{:02}] {}",
+                    seconds / 60,
+                    seconds % 60,
+                    entry.notification.message()
+                )),
+                TextFont { font_size: 14.0, ..default() },
+                TextColor(TEXT_COLOR),
+            ));
+        }
+    });
+}
+
+fn scroll_event_log_system(
+    mut wheel: EventReader<MouseWheel>,
+    mut list_q: Query<(&mut ScrollPosition, &RelativeCursorPosition, &InheritedVisibility), With<EventLogList>>,
+) {
+    let Ok((mut scroll, cursor, visible)) = list_q.get_single_mut() else { return };
+    for event in wheel.read() {
+        if !visible.get() || !cursor.mouse_over() {
+            continue;
+        }
+        let dy = match event.unit {
+            MouseScrollUnit::Line => event.y * LINE_HEIGHT,
+            MouseScrollUnit::Pixel => event.y,
+        };
+        scroll.offset_y = (scroll.offset_y - dy).max(0.0);
+    }
+}
+
+fn jump_to_entry_system(
+    log: Res<EventLog>,
+    mut entry_q: Query<(&Interaction, &EventLogEntry, &mut BackgroundColor), Changed<Interaction>>,
+    mut camera_q: Query<&mut Transform, With<Camera2d>>,
+) {
+    for (interaction, entry, mut background) in &mut entry_q {
+        *background = match interaction {
+            Interaction::None => NORMAL_BUTTON.into(),
+            _ => HOVERED_BUTTON.into(),
+        };
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let Some(location) = log.entries.get(entry.0).and_then(|e| e.notification.location) else { continue };
+        for mut transform in &mut camera_q {
+            transform.translation.x = location.x;
+            transform.translation.y = location.y;
+        }
+    }
+}
@@ -34,5 +34,10 @@ impl SimpleRng {
         f32::from_bits(bits) - 1.0
     }
 
+    /// Generates a random float in [min, max)
+    pub fn next_range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_scaled()
+    }
+
 }
 
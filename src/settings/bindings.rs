@@ -0,0 +1,294 @@
+use std::{collections::HashMap, path::Path};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::settings::settings_io::SettingPlugin;
+
+pub fn bindings_plugin(app: &mut App) {
+    app.add_plugins(SettingPlugin::new(Path::new("assets/settings/key_bindings.json"), KeyBindings::default()));
+}
+
+// Everything the player can rebind on the controls page
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InputAction {
+    PanUp,
+    PanDown,
+    PanLeft,
+    PanRight,
+    ZoomIn,
+    ZoomOut,
+    ToggleWireframe,
+    ToggleSettings,
+    SelectTool,
+    RoadTool,
+    NodeTool,
+    ZoneTool,
+    BulldozeTool,
+    MeasureTool,
+    WaterTool,
+    TransitTool,
+    ServiceTool,
+    UndoPoint,
+    ConfirmDrawing,
+    CycleControl,
+    FollowSelection,
+    CoverageOverlay,
+    PollutionOverlay,
+    LandValueView,
+    TrafficView,
+    EventLog,
+    Bookmark1,
+    Bookmark2,
+    Bookmark3,
+    Bookmark4,
+    Bookmark5,
+    Bookmark6,
+    Bookmark7,
+    Bookmark8,
+    Bookmark9,
+    SaveCity,
+    LoadCity,
+    ExportDemand,
+    ExportPollution,
+}
+
+impl InputAction {
+    pub const ALL: [InputAction; 39] = [
+        InputAction::PanUp,
+        InputAction::PanDown,
+        InputAction::PanLeft,
+        InputAction::PanRight,
+        InputAction::ZoomIn,
+        InputAction::ZoomOut,
+        InputAction::ToggleWireframe,
+        InputAction::ToggleSettings,
+        InputAction::SelectTool,
+        InputAction::RoadTool,
+        InputAction::NodeTool,
+        InputAction::ZoneTool,
+        InputAction::BulldozeTool,
+        InputAction::MeasureTool,
+        InputAction::WaterTool,
+        InputAction::TransitTool,
+        InputAction::ServiceTool,
+        InputAction::UndoPoint,
+        InputAction::ConfirmDrawing,
+        InputAction::CycleControl,
+        InputAction::FollowSelection,
+        InputAction::CoverageOverlay,
+        InputAction::PollutionOverlay,
+        InputAction::LandValueView,
+        InputAction::TrafficView,
+        InputAction::EventLog,
+        InputAction::Bookmark1,
+        InputAction::Bookmark2,
+        InputAction::Bookmark3,
+        InputAction::Bookmark4,
+        InputAction::Bookmark5,
+        InputAction::Bookmark6,
+        InputAction::Bookmark7,
+        InputAction::Bookmark8,
+        InputAction::Bookmark9,
+        InputAction::SaveCity,
+        InputAction::LoadCity,
+        InputAction::ExportDemand,
+        InputAction::ExportPollution,
+    ];
+
+    /// Camera bookmark slots in order
+    pub const BOOKMARKS: [InputAction; 9] = [
+        InputAction::Bookmark1,
+        InputAction::Bookmark2,
+        InputAction::Bookmark3,
+        InputAction::Bookmark4,
+        InputAction::Bookmark5,
+        InputAction::Bookmark6,
+        InputAction::Bookmark7,
+        InputAction::Bookmark8,
+        InputAction::Bookmark9,
+    ];
+
+    pub fn default_binding(self) -> Binding {
+        let key = match self {
+            InputAction::PanUp => KeyCode::KeyW,
+            InputAction::PanDown => KeyCode::KeyS,
+            InputAction::PanLeft => KeyCode::KeyA,
+            InputAction::PanRight => KeyCode::KeyD,
+            InputAction::ZoomIn => KeyCode::KeyE,
+            InputAction::ZoomOut => KeyCode::KeyQ,
+            InputAction::ToggleWireframe => KeyCode::Space,
+            InputAction::ToggleSettings => KeyCode::Escape,
+            InputAction::SelectTool => KeyCode::KeyI,
+            InputAction::RoadTool => KeyCode::KeyN,
+            InputAction::NodeTool => KeyCode::KeyJ,
+            InputAction::ZoneTool => KeyCode::KeyZ,
+            InputAction::BulldozeTool => KeyCode::KeyX,
+            InputAction::MeasureTool => KeyCode::KeyM,
+            InputAction::WaterTool => KeyCode::KeyR,
+            InputAction::TransitTool => KeyCode::KeyT,
+            InputAction::ServiceTool => KeyCode::KeyB,
+            InputAction::UndoPoint => KeyCode::Backspace,
+            InputAction::ConfirmDrawing => KeyCode::Enter,
+            InputAction::CycleControl => KeyCode::KeyC,
+            InputAction::FollowSelection => KeyCode::KeyF,
+            InputAction::CoverageOverlay => KeyCode::KeyO,
+            InputAction::PollutionOverlay => KeyCode::KeyP,
+            InputAction::LandValueView => KeyCode::KeyG,
+            InputAction::TrafficView => KeyCode::KeyV,
+            InputAction::EventLog => KeyCode::KeyH,
+            InputAction::Bookmark1 => KeyCode::Digit1,
+            InputAction::Bookmark2 => KeyCode::Digit2,
+            InputAction::Bookmark3 => KeyCode::Digit3,
+            InputAction::Bookmark4 => KeyCode::Digit4,
+            InputAction::Bookmark5 => KeyCode::Digit5,
+            InputAction::Bookmark6 => KeyCode::Digit6,
+            InputAction::Bookmark7 => KeyCode::Digit7,
+            InputAction::Bookmark8 => KeyCode::Digit8,
+            InputAction::Bookmark9 => KeyCode::Digit9,
+            InputAction::SaveCity => KeyCode::F5,
+            InputAction::LoadCity => KeyCode::F9,
+            InputAction::ExportDemand => KeyCode::F6,
+            InputAction::ExportPollution => KeyCode::F7,
+        };
+        Binding::Key(key)
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            InputAction::PanUp => "Pan Up",
+            InputAction::PanDown => "Pan Down",
+            InputAction::PanLeft => "Pan Left",
+            InputAction::PanRight => "Pan Right",
+            InputAction::ZoomIn => "Zoom In",
+            InputAction::ZoomOut => "Zoom Out",
+            InputAction::ToggleWireframe => "Wireframe",
+            InputAction::ToggleSettings => "Settings",
+            InputAction::SelectTool => "Select",
+            InputAction::RoadTool => "Road",
+            InputAction::NodeTool => "Node",
+            InputAction::ZoneTool => "Zone",
+            InputAction::BulldozeTool => "Bulldoze",
+            InputAction::MeasureTool => "Measure",
+            InputAction::WaterTool => "Water",
+            InputAction::TransitTool => "Transit",
+            InputAction::ServiceTool => "Service",
+            InputAction::UndoPoint => "Undo Point",
+            InputAction::ConfirmDrawing => "Finish Drawing",
+            InputAction::CycleControl => "Intersection Control",
+            InputAction::FollowSelection => "Follow",
+            InputAction::CoverageOverlay => "Coverage",
+            InputAction::PollutionOverlay => "Pollution",
+            InputAction::LandValueView => "Land Value",
+            InputAction::TrafficView => "Traffic",
+            InputAction::EventLog => "Event Log",
+            InputAction::Bookmark1 => "Bookmark 1",
+            InputAction::Bookmark2 => "Bookmark 2",
+            InputAction::Bookmark3 => "Bookmark 3",
+            InputAction::Bookmark4 => "Bookmark 4",
+            InputAction::Bookmark5 => "Bookmark 5",
+            InputAction::Bookmark6 => "Bookmark 6",
+            InputAction::Bookmark7 => "Bookmark 7",
+            InputAction::Bookmark8 => "Bookmark 8",
+            InputAction::Bookmark9 => "Bookmark 9",
+            InputAction::SaveCity => "Save City",
+            InputAction::LoadCity => "Load City",
+            InputAction::ExportDemand => "Export Demand",
+            InputAction::ExportPollution => "Export Pollution",
+        }
+    }
+}
+
+/// Key or mouse button an action is bound to. Untagged so files written when only
+/// keys could be bound still load.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+// Binding of each action, actions missing from the file keep their default binding
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KeyBindings(pub HashMap<InputAction, Binding>);
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings(InputAction::ALL.into_iter().map(|action| (action, action.default_binding())).collect())
+    }
+}
+
+impl KeyBindings {
+    pub fn binding(&self, action: InputAction) -> Binding {
+        self.0.get(&action).copied().unwrap_or(action.default_binding())
+    }
+
+    /// Other actions sharing the binding of this one
+    pub fn conflicts(&self, action: InputAction) -> Vec<InputAction> {
+        let binding = self.binding(action);
+        InputAction::ALL
+            .into_iter()
+            .filter(|other| *other != action && self.binding(*other) == binding)
+            .collect()
+    }
+}
+
+/// Keyboard and mouse read through the bindings
+#[derive(SystemParam)]
+pub struct Actions<'w> {
+    bindings: Res<'w, KeyBindings>,
+    keys: Res<'w, ButtonInput<KeyCode>>,
+    mouse: Res<'w, ButtonInput<MouseButton>>,
+}
+
+impl Actions<'_> {
+    pub fn pressed(&self, action: InputAction) -> bool {
+        match self.bindings.binding(action) {
+            Binding::Key(key) => self.keys.pressed(key),
+            Binding::Mouse(button) => self.mouse.pressed(button),
+        }
+    }
+
+    pub fn just_pressed(&self, action: InputAction) -> bool {
+        match self.bindings.binding(action) {
+            Binding::Key(key) => self.keys.just_pressed(key),
+            Binding::Mouse(button) => self.mouse.just_pressed(button),
+        }
+    }
+}
+
+/// Run condition like `input_just_pressed` but for a bound action
+pub fn action_just_pressed(action: InputAction) -> impl FnMut(Actions) -> bool + Clone {
+    move |actions: Actions| actions.just_pressed(action)
+}
+
+/// Short name of a binding for the controls page, "KeyW" becomes "W"
+pub fn binding_name(binding: Binding) -> String {
+    let key = match binding {
+        Binding::Key(key) => key,
+        Binding::Mouse(MouseButton::Other(button)) => return format!("Mouse {button}"),
+        Binding::Mouse(button) => return format!("Mouse {button:?}"),
+    };
+    let name = format!("{key:?}");
+    for prefix in ["Key", "Digit"] {
+        if let Some(rest) = name.strip_prefix(prefix)
+            && !rest.is_empty()
+        {
+            return rest.to_string();
+        }
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_only_files_still_load() {
+        let bindings: KeyBindings = serde_json::from_str(r#"{"PanUp":"KeyW","ZoomIn":"Middle"}"#).unwrap();
+        assert_eq!(bindings.binding(InputAction::PanUp), Binding::Key(KeyCode::KeyW));
+        assert_eq!(bindings.binding(InputAction::ZoomIn), Binding::Mouse(MouseButton::Middle));
+        assert_eq!(bindings.binding(InputAction::SaveCity), Binding::Key(KeyCode::F5));
+    }
+}
@@ -39,6 +39,35 @@ impl std::fmt::Display for Volume {
     }
 }
 
+// The music, effects and ambience buses all get a 0-100 level scaled by the master
+// `Volume`, each its own resource so it has its own slider and settings file
+macro_rules! bus_volume {
+    ($name:ident) => {
+        #[derive(Resource,Component,Serialize,Deserialize, Debug, PartialEq, Clone, Copy)]
+        pub struct $name(pub u32);
+
+        impl Slidble for $name {
+            fn as_fraction(&self) -> f32 {
+                self.0 as f32/100.0
+            }
+
+            fn from_fraction(fraction: f32) -> Self {
+                Self((fraction * 100.0) as u32)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{:3}", self.0)
+            }
+        }
+    };
+}
+
+bus_volume!(MusicVolume);
+bus_volume!(EffectsVolume);
+bus_volume!(AmbienceVolume);
+
 
 #[derive(Resource,Component,Default, Debug, Clone,Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum FramerateMode {
@@ -72,7 +101,7 @@ impl Slidble for ManualFpsCap{
         ((self.0-1.0)/249.0) as f32
     }
     fn from_fraction(fraction: f32) -> Self{
-        ManualFpsCap(249.0 as f64*fraction as f64 +1.0)
+        ManualFpsCap(249.0*fraction as f64 +1.0)
     }
 }
 
@@ -80,4 +109,96 @@ impl fmt::Display for ManualFpsCap {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:.0} FPS", self.0)
     }
-}
\ No newline at end of file
+}
+
+// Multiplier on how fast the mouse wheel and Q/E zoom
+#[derive(Resource,Component, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ZoomSensitivity(pub f32);
+
+impl Default for ZoomSensitivity {
+    fn default() -> Self {
+        ZoomSensitivity(1.0)
+    }
+}
+
+impl Slidble for ZoomSensitivity {
+    fn as_fraction(&self) -> f32 {
+        (self.0 - 0.1) / 2.9
+    }
+    fn from_fraction(fraction: f32) -> Self {
+        ZoomSensitivity(2.9 * fraction + 0.1)
+    }
+}
+
+impl fmt::Display for ZoomSensitivity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.1}x", self.0)
+    }
+}
+
+// Multiplier on how fast WASD and edge scrolling move the camera
+#[derive(Resource,Component, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PanSensitivity(pub f32);
+
+impl Default for PanSensitivity {
+    fn default() -> Self {
+        PanSensitivity(1.0)
+    }
+}
+
+impl Slidble for PanSensitivity {
+    fn as_fraction(&self) -> f32 {
+        (self.0 - 0.1) / 2.9
+    }
+    fn from_fraction(fraction: f32) -> Self {
+        PanSensitivity(2.9 * fraction + 0.1)
+    }
+}
+
+impl fmt::Display for PanSensitivity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.1}x", self.0)
+    }
+}
+
+// How much the camera eases towards where it is headed, 0 snaps instantly
+#[derive(Resource,Component, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CameraSmoothing(pub f32);
+
+impl Default for CameraSmoothing {
+    fn default() -> Self {
+        CameraSmoothing(0.5)
+    }
+}
+
+impl Slidble for CameraSmoothing {
+    fn as_fraction(&self) -> f32 {
+        self.0
+    }
+    fn from_fraction(fraction: f32) -> Self {
+        CameraSmoothing(fraction)
+    }
+}
+
+impl fmt::Display for CameraSmoothing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:3.0}%", self.0 * 100.0)
+    }
+}
+
+// Moving the camera by holding the cursor at the window border
+#[derive(Resource,Component,Default, Debug, Clone,Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EdgeScroll {
+    Enabled,
+    #[default]
+    Disabled,
+}
+
+// Lowers the display quality below the chosen one on its own to hold the framerate
+// target, and raises it back when there is room
+#[derive(Resource,Component,Default, Debug, Clone,Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AdaptiveQuality {
+    Enabled,
+    #[default]
+    Disabled,
+}
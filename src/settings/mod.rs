@@ -1,3 +1,5 @@
 pub mod globals;
 pub mod framerate;
 pub mod settings_io;
+pub mod bindings;
+pub mod quality;
@@ -0,0 +1,258 @@
+pub use crate::settings::globals::DisplayQuality;
+pub use crate::settings::globals::AdaptiveQuality;
+
+use std::path::Path;
+
+use bevy::{
+    core_pipeline::bloom::Bloom,
+    diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin},
+    prelude::*,
+    window::{Monitor, PrimaryMonitor},
+};
+
+use crate::settings::{
+    globals::{FramerateMode, ManualFpsCap},
+    settings_io::SettingPlugin,
+};
+
+/// Framerate aimed for when the framerate mode sets no cap of its own and the
+/// monitor's refresh rate is unknown
+const DEFAULT_TARGET_FPS: f64 = 60.0;
+/// Below this share of the target the quality goes down
+const LOWER_BELOW: f64 = 0.85;
+/// Above this share of the target the quality may go up again
+const RAISE_ABOVE: f64 = 0.97;
+/// Seconds the framerate has to stay low before lowering
+const LOWER_AFTER: f32 = 3.0;
+/// Seconds the framerate has to stay good before raising
+const RAISE_AFTER: f32 = 10.0;
+/// A level that was too slow before is only retried after this much longer
+const RETRY_FACTOR: f32 = 6.0;
+/// Seconds after a change before judging the new level
+const SETTLE_TIME: f32 = 2.0;
+
+// What each display quality level turns on. The camera wide parts are applied here,
+// the city applies the rest (see `city::render_quality`). Both go by `EffectiveQuality`.
+impl DisplayQuality {
+    pub fn msaa(self) -> Msaa {
+        match self {
+            DisplayQuality::Low => Msaa::Off,
+            DisplayQuality::Medium => Msaa::Sample2,
+            DisplayQuality::High => Msaa::Sample4,
+        }
+    }
+
+    /// Segments of the intersection circles
+    pub fn circle_segments(self) -> u32 {
+        match self {
+            DisplayQuality::Low => 12,
+            DisplayQuality::Medium => 24,
+            DisplayQuality::High => 48,
+        }
+    }
+
+    /// Drop shadows under buildings
+    pub fn shadows(self) -> bool {
+        self != DisplayQuality::Low
+    }
+
+    /// Bloom on the HDR camera
+    pub fn post_process(self) -> bool {
+        self == DisplayQuality::High
+    }
+
+    /// Overlay image pixels per grid cell side
+    pub fn overlay_upsample(self) -> usize {
+        match self {
+            DisplayQuality::Low => 1,
+            DisplayQuality::Medium => 2,
+            DisplayQuality::High => 4,
+        }
+    }
+
+    fn lower(self) -> Option<Self> {
+        match self {
+            DisplayQuality::Low => None,
+            DisplayQuality::Medium => Some(DisplayQuality::Low),
+            DisplayQuality::High => Some(DisplayQuality::Medium),
+        }
+    }
+
+    fn higher(self) -> Option<Self> {
+        match self {
+            DisplayQuality::Low => Some(DisplayQuality::Medium),
+            DisplayQuality::Medium => Some(DisplayQuality::High),
+            DisplayQuality::High => None,
+        }
+    }
+
+    /// Most vehicles drawn at once, the rest still drive but are hidden
+    pub fn agent_cap(self) -> usize {
+        match self {
+            DisplayQuality::Low => 200,
+            DisplayQuality::Medium => 1000,
+            DisplayQuality::High => 5000,
+        }
+    }
+}
+
+pub fn quality_plugin(app: &mut App) {
+    let adaptive_plugin = SettingPlugin::new(Path::new("assets/settings/adaptive_quality.json"), AdaptiveQuality::default());
+
+    app.add_plugins(adaptive_plugin)
+        .init_resource::<AdaptiveState>()
+        .init_resource::<EffectiveQuality>()
+        .add_systems(
+            Update,
+            (
+                reset_effective_quality_system
+                    .run_if(resource_changed::<DisplayQuality>.or(resource_changed::<AdaptiveQuality>)),
+                adaptive_quality_system,
+                apply_camera_quality.run_if(resource_changed::<EffectiveQuality>.or(camera_added)),
+            )
+                .chain(),
+        );
+}
+
+/// Quality the game is drawn at. `DisplayQuality` is the player's choice and is
+/// what gets saved, adaptive quality only ever goes below it.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Deref)]
+pub struct EffectiveQuality(pub DisplayQuality);
+
+impl Default for EffectiveQuality {
+    fn default() -> Self {
+        Self(DisplayQuality::Medium)
+    }
+}
+
+impl std::fmt::Display for EffectiveQuality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Now {:?}", self.0)
+    }
+}
+
+/// What adaptive quality has seen so far
+#[derive(Resource, Default, Debug)]
+pub struct AdaptiveState {
+    /// Seconds the framerate has been too low, or good, in a row
+    low_for: f32,
+    good_for: f32,
+    /// Seconds since the last change
+    since_change: f32,
+    /// Highest level that could not hold the target, retried only reluctantly
+    too_slow: Option<DisplayQuality>,
+}
+
+/// Frames per second to aim for under the current framerate settings, uncapped
+/// modes aim for the monitor's refresh rate when it is known
+pub fn target_fps(mode: FramerateMode, cap: ManualFpsCap, refresh_rate: Option<f64>) -> f64 {
+    match mode {
+        FramerateMode::Manual => cap.0,
+        FramerateMode::Auto | FramerateMode::Off => refresh_rate.unwrap_or(DEFAULT_TARGET_FPS),
+    }
+}
+
+// A new choice of quality, or switching adaptive quality on or off, starts over
+// from the chosen quality
+fn reset_effective_quality_system(
+    quality: Res<DisplayQuality>,
+    mut effective: ResMut<EffectiveQuality>,
+    mut state: ResMut<AdaptiveState>,
+) {
+    effective.set_if_neq(EffectiveQuality(*quality));
+    *state = AdaptiveState::default();
+}
+
+// Steps the effective quality down when the smoothed framerate stays under the
+// target, and back up to the chosen quality after a long stretch at the target
+#[allow(clippy::too_many_arguments)]
+pub fn adaptive_quality_system(
+    time: Res<Time>,
+    adaptive: Res<AdaptiveQuality>,
+    diagnostics: Res<DiagnosticsStore>,
+    mode: Res<FramerateMode>,
+    cap: Res<ManualFpsCap>,
+    monitor_q: Query<&Monitor, With<PrimaryMonitor>>,
+    ceiling: Res<DisplayQuality>,
+    mut state: ResMut<AdaptiveState>,
+    mut quality: ResMut<EffectiveQuality>,
+) {
+    if *adaptive == AdaptiveQuality::Disabled {
+        return;
+    }
+    let Some(fps) = diagnostics
+        .get(&FrameTimeDiagnosticsPlugin::FPS)
+        .and_then(|fps| fps.smoothed())
+    else {
+        return;
+    };
+
+    let delta = time.delta_secs();
+    state.since_change += delta;
+    if state.since_change < SETTLE_TIME {
+        return;
+    }
+
+    let refresh_rate = monitor_q
+        .get_single()
+        .ok()
+        .and_then(|monitor| monitor.refresh_rate_millihertz)
+        .map(|millihertz| millihertz as f64 / 1000.0);
+    let target = target_fps(*mode, *cap, refresh_rate);
+    if fps < target * LOWER_BELOW {
+        state.low_for += delta;
+        state.good_for = 0.0;
+    } else if fps > target * RAISE_ABOVE {
+        state.good_for += delta;
+        state.low_for = 0.0;
+    } else {
+        // In between is fine where we are, the gap keeps it from flip-flopping
+        state.low_for = 0.0;
+        state.good_for = 0.0;
+    }
+
+    let current = quality.0;
+    if state.low_for >= LOWER_AFTER {
+        if let Some(lower) = current.lower() {
+            info!("Framerate {fps:.0} under target {target:.0}, lowering display quality to {lower:?}");
+            state.too_slow = Some(current);
+            quality.0 = lower;
+        }
+        state.low_for = 0.0;
+        state.since_change = 0.0;
+        return;
+    }
+
+    if current == *ceiling {
+        return;
+    }
+    let Some(higher) = current.higher() else { return };
+    let wait = if state.too_slow == Some(higher) { RAISE_AFTER * RETRY_FACTOR } else { RAISE_AFTER };
+    if state.good_for >= wait {
+        info!("Framerate holding target {target:.0}, raising display quality to {higher:?}");
+        quality.0 = higher;
+        state.good_for = 0.0;
+        state.since_change = 0.0;
+    }
+}
+
+fn camera_added(camera_q: Query<(), Added<Camera>>) -> bool {
+    !camera_q.is_empty()
+}
+
+pub fn apply_camera_quality(
+    mut commands: Commands,
+    quality: Res<EffectiveQuality>,
+    mut camera_q: Query<(Entity, &mut Camera)>,
+) {
+    for (entity, mut camera) in &mut camera_q {
+        commands.entity(entity).insert(quality.msaa());
+        if quality.post_process() {
+            camera.hdr = true;
+            commands.entity(entity).insert(Bloom::NATURAL);
+        } else {
+            camera.hdr = false;
+            commands.entity(entity).remove::<Bloom>();
+        }
+    }
+}
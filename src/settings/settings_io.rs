@@ -2,6 +2,8 @@ use std::{fs, marker::PhantomData, path::Path};
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
+use crate::notifications::{Notification, NotificationKind};
+
 /// Plugin for handling settings persistence
 pub struct SettingPlugin<T: 'static + Resource + Serialize + for<'de> Deserialize<'de> + Clone> {
     path: &'static Path,
@@ -92,12 +94,11 @@ pub fn save_setting<T: Resource + Serialize>(world: &mut World) -> Result<(), St
         let path = path_holder.path;
         
         // Ensure parent directory exists
-        if let Some(parent) = path.parent() {
-            if !parent.exists() {
-                if let Err(e) = fs::create_dir_all(parent) {
-                    return Err(format!("Failed to create settings directory: {}", e));
-                }
-            }
+        if let Some(parent) = path.parent()
+            && !parent.exists()
+            && let Err(e) = fs::create_dir_all(parent)
+        {
+            return Err(format!("Failed to create settings directory: {}", e));
         }
         
         match serde_json::to_string_pretty(settings) {
@@ -124,7 +125,10 @@ pub fn save_setting<T: Resource + Serialize>(world: &mut World) -> Result<(), St
 }
 
 pub fn save_setting_system<T: Resource + Serialize>(world: &mut World){
-    save_setting::<T>(world).unwrap();
+    if let Err(e) = save_setting::<T>(world) {
+        error!("Failed to save settings: {}", e);
+        world.send_event(Notification::new(NotificationKind::SettingSaveFailed { error: e }));
+    }
 }
 
 /// Add a save system to run on exit from a state
@@ -135,6 +139,7 @@ pub fn add_setting_save_on_exit<T: 'static + Resource + Serialize, S: States + s
     app.add_systems(OnExit(state), move |world: &mut World| {
         if let Err(e) = save_setting::<T>(world) {
             error!("Failed to save settings on exit from state {:?}: {}", state, e);
+            world.send_event(Notification::new(NotificationKind::SettingSaveFailed { error: e }));
         } else {
             info!("Settings saved on exit from state {:?}", state);
         }